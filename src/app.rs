@@ -4,22 +4,27 @@ pub mod graph;
 mod biome;
 pub mod action;
 pub mod texture_loader;
+pub mod heightmap_loader;
+mod map_export;
 
 use core::f32;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use action::ActionManager;
 use texture_loader::TextureLoader;
+use heightmap_loader::HeightmapLoader;
 use viewport::{TerrainRenderResources, ViewportTab};
-use crate::biome::Biomes;
-use crate::compiler::biomes::compile_biome_preview;
-use crate::compiler::{compile, CompilationTarget};
+use crate::biome::{BiomeParamKind, Biomes};
+use crate::compiler::biomes::{biome_inspect_len, compile_biome_preview};
+use crate::compiler::{compile, compile_unreal_mesh_settings, CompilationTarget};
 use crate::graph::node_types::terrain::{HeightmapTerrain, TerrainOutput};
-use crate::graph::{NodeInput, TerrainGraph, Value};
+use crate::graph::{NodeInput, NodeType, TerrainGraph, Value};
 use crate::project::Project;
 use crate::terrain::biome_preview::BiomePreviewRenderer;
+use crate::terrain::map_export::{HeightFormat, MapEvaluation, MapRegion};
+use crate::terrain::renderer::TerrainShading;
 use crate::terrain::texture_atlas::{TextureAtlas, TextureBlitter};
-use crate::terrain::{meshgen::TerrainMeshGenerator, renderer::TerrainRenderer, Terrain};
+use crate::terrain::{meshgen::{MeshSettings, TerrainMeshGenerator}, renderer::TerrainRenderer, Terrain};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum SidePanelTab {
@@ -38,10 +43,17 @@ pub struct App {
     cam_pitch: f32,
 
     biome_preview_depth: f32,
+    biome_preview_center: glam::Vec2,
+    // world units from the center to the top of the biome preview
+    biome_preview_scale: f32,
+    // position under the cursor and the values read back there
 
     tri_counter: Arc<Mutex<u64>>,
+    terrain_shading: TerrainShading,
 
     side_panel_tab: SidePanelTab,
+    // biome whose graph is shown in the graph tab instead of the project graph
+    edited_biome_graph: Option<usize>,
     viewport_tab: ViewportTab,
 
     project_path: PathBuf,
@@ -52,8 +64,19 @@ pub struct App {
 
     add_biome_parameter_dialog_open: bool,
     add_biome_parameter_name: String,
+    add_biome_parameter_kind: BiomeParamKind,
+
+    map_export_dialog_open: bool,
+    map_region: MapRegion,
+    map_height_format: HeightFormat,
+    map_export_status: String,
+    // the export being evaluated, with where and how to save it
+    map_export: Option<(MapEvaluation, PathBuf, HeightFormat)>,
+    // the maps being saved, and where to
+    map_save: Option<(std::thread::JoinHandle<image::ImageResult<()>>, PathBuf)>,
 
     texture_loader: TextureLoader,
+    heightmap_loader: HeightmapLoader,
     blitter: TextureBlitter,
     
     prev_unreal_hlsl: String
@@ -62,6 +85,22 @@ pub struct App {
 const UNDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
 const REDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
 
+pub fn default_terrain_graph() -> TerrainGraph {
+    let mut terrain_graph = TerrainGraph::new();
+    let heightmap = terrain_graph.add_node(egui::Pos2::ZERO, HeightmapTerrain {
+        height: Value::scalar(0.0).into(),
+    });
+    terrain_graph.add_node(egui::pos2(300.0, 25.0), TerrainOutput {
+        terrain: NodeInput {
+            val: Value::terrain(),
+            connection: Some((heightmap, 0)),
+        },
+        ..TerrainOutput::make()
+    });
+    terrain_graph.transform = egui::emath::TSTransform::from_translation(egui::vec2(0.0, 300.0));
+    terrain_graph
+}
+
 impl App {
 
     pub fn new(cc: &eframe::CreationContext, project_path: PathBuf, texture_path: PathBuf) -> Self {
@@ -75,17 +114,7 @@ impl App {
         let queue = &wgpu_render_state.queue;
 
         // create default graph
-        let mut terrain_graph = TerrainGraph::new();
-        let heightmap = terrain_graph.add_node(egui::Pos2::ZERO, HeightmapTerrain {
-            height: Value::scalar(0.0).into(),
-        });
-        terrain_graph.add_node(egui::pos2(300.0, 25.0), TerrainOutput {
-            terrain: NodeInput {
-                val: Value::terrain(),
-                connection: Some((heightmap, 0)),
-            },
-        });
-        terrain_graph.transform = egui::emath::TSTransform::from_translation(egui::vec2(0.0, 300.0));
+        let terrain_graph = default_terrain_graph();
 
         // create default biomes
         let biomes = Biomes::new();
@@ -95,12 +124,15 @@ impl App {
 
         let texture_loader = TextureLoader::new(texture_path.clone());
 
-        let sdf_code = compile(&terrain_graph, &biomes, &texture_loader, 
+        let heightmap_loader = HeightmapLoader::new(project_path.clone());
+
+        let sdf_code = compile(&terrain_graph, &biomes, &texture_loader, &heightmap_loader, 
             CompilationTarget::WGSL,project_path.file_name().unwrap().to_str()); 
 
         let mesh_generator = TerrainMeshGenerator::new(device, &sdf_code);
 
         let biome_preview_code = compile_biome_preview(&biomes);
+        let biome_inspect_len = biome_inspect_len(&biomes);
 
         let mut blitter = TextureBlitter::new(device, queue); 
         let atlas = TextureAtlas::new(device, queue, &mut blitter);
@@ -109,7 +141,9 @@ impl App {
         let mut app = Self {
             project: Project {
                 terrain_graph, 
-                biomes
+                biomes,
+                meshing: MeshSettings::default(),
+                cache_chunks_on_disk: false
             },
             actions: ActionManager::new(),
             god_center: glam::Vec3::splat(0.0),
@@ -117,8 +151,12 @@ impl App {
             cam_yaw: 0.0,
             cam_pitch: 0.5,
             biome_preview_depth: 0.0,
+            biome_preview_center: glam::Vec2::ZERO,
+            biome_preview_scale: 1000.0,
             tri_counter: Arc::new(Mutex::new(0)),
+            terrain_shading: TerrainShading::Textured,
             side_panel_tab: SidePanelTab::Graph,
+            edited_biome_graph: None,
             viewport_tab: ViewportTab::Terrain,
             project_path: project_path.clone(),
             regenerate_on_update: true,
@@ -126,14 +164,22 @@ impl App {
             prev_biome_preview_code: biome_preview_code.clone(),
             add_biome_parameter_dialog_open: false,
             add_biome_parameter_name: String::new(),
+            add_biome_parameter_kind: BiomeParamKind::Scalar,
+            map_export_dialog_open: false,
+            map_region: MapRegion::default(),
+            map_height_format: HeightFormat::Png16,
+            map_export_status: String::new(),
+            map_export: None,
+            map_save: None,
             texture_loader,
+            heightmap_loader,
             blitter,
             prev_unreal_hlsl: "".to_owned()
         };
 
         app.load_project();
 
-        let biome_preview_renderer = BiomePreviewRenderer::new(device, wgpu_render_state.target_format, biome_preview_code);
+        let biome_preview_renderer = BiomePreviewRenderer::new(device, wgpu_render_state.target_format, biome_preview_code, biome_inspect_len);
 
         wgpu_render_state.renderer.write().callback_resources.insert(TerrainRenderResources {
             terrain: Terrain::new(),
@@ -190,9 +236,17 @@ impl eframe::App for App {
                                 self.actions.redo(&mut self.project, device, queue);
                         }
                     });
+                    ui.menu_button("Export", |ui| {
+                        if ui.button("Maps...").clicked() {
+                            self.map_export_dialog_open = true;
+                            ui.close_menu();
+                        }
+                    });
                 });
             });
 
+        self.render_map_export_dialog(ctx, device, queue);
+
         egui::SidePanel::left("side_panel")
             .resizable(true)
             .default_width(400.0)
@@ -225,7 +279,7 @@ impl eframe::App for App {
         let resources = renderer.callback_resources.get_mut::<TerrainRenderResources>().unwrap();
 
         egui::CentralPanel::default().frame(egui::Frame::none()).show(ctx, |ui| {
-            self.render_viewport(ui, device, frame.wgpu_render_state().unwrap().target_format, resources);
+            self.render_viewport(ui, device, queue, frame.wgpu_render_state().unwrap().target_format, resources);
         });
 
         if ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT)) {
@@ -242,8 +296,12 @@ impl eframe::App for App {
 
         self.save_project();
         self.texture_loader.tick(&device, &queue, &mut self.blitter, &mut renderer);
+        self.heightmap_loader.set_referenced(self.project.heightmaps());
+        self.heightmap_loader.tick(device, queue, &mut renderer);
 
-        let hlsl_code = compile(&self.project.terrain_graph, &self.project.biomes, &self.texture_loader, CompilationTarget::UnrealHLSL, self.project_path.file_name().unwrap().to_str());
+        let project_name = self.project_path.file_name().unwrap().to_str();
+        let hlsl_code = compile_unreal_mesh_settings(&self.project.meshing, project_name)
+            + &compile(&self.project.terrain_graph, &self.project.biomes, &self.texture_loader, &self.heightmap_loader, CompilationTarget::UnrealHLSL, project_name);
 
         if hlsl_code != self.prev_unreal_hlsl {
             std::fs::write(self.project_path.join("unreal.ush"), &hlsl_code).unwrap();
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 
 use eframe::wgpu;
 
-use crate::{biome::Biome, graph::{Connection, NodeId, NodeTypeDyn, Value}, project::Project};
+use crate::{biome::{Biome, BiomeAdjacency, BiomeDistribution, BiomeParam, BiomeParamBlend, BiomeParamValue, BiomeRule, ClimateAxis}, graph::{Connection, NodeId, NodeTypeDyn, TerrainGraph, Value}, project::Project};
 
 pub enum Action {
     GraphMoveNode(NodeId, egui::Vec2),
@@ -19,11 +19,18 @@ pub enum Action {
         in_idx: u32,
         val: Value
     },
+    // what a node's custom ui edits, as saved by custom_serialize
+    GraphSetCustomData {
+        node: NodeId,
+        data: serde_json::Value
+    },
 
-    BiomeCreate(usize, Biome),
+    // the biome and the adjacency rules from before it was deleted
+    BiomeCreate(usize, Biome, Vec<BiomeAdjacency>),
     BiomeDelete(usize),
     BiomeSetSize(f32),
     BiomeSetBlending(f32),
+    BiomeSetRuleFalloff(f32),
     BiomeSetName(usize, String),
     BiomeSetFrequency(usize, f32),
     BiomeSetTexture(usize, PathBuf),
@@ -32,40 +39,65 @@ pub enum Action {
     BiomeSetParameter {
         biome: usize,
         param: String,
-        val: f32
+        val: BiomeParamValue
     },
     BiomeCreateParameter {
-        param: String
+        idx: usize,
+        param: BiomeParam
     },
     BiomeDeleteParameter {
-        param: String
+        idx: usize
+    },
+    BiomeSetParameterBlending {
+        param: usize,
+        blend: BiomeParamBlend,
+        sharpness: f32
+    },
+    BiomeSetDistribution(BiomeDistribution),
+    BiomeCreateClimateAxis {
+        axis_idx: usize,
+        axis: ClimateAxis,
+        ranges: Vec<Option<[f32; 2]>>
+    },
+    BiomeDeleteClimateAxis(usize),
+    BiomeSetClimateAxis(usize, ClimateAxis),
+    BiomeSetClimateRange {
+        biome: usize,
+        axis: usize,
+        range: [f32; 2]
     },
+    BiomeSetRules(usize, Vec<BiomeRule>),
+    BiomeSetAdjacency(Vec<BiomeAdjacency>),
+    BiomeSetGraph(usize, Option<TerrainGraph>),
+    // a graph action performed on the graph of a biome
+    BiomeGraph(usize, Box<Action>),
 
     Compound(Vec<Action>)
 }
 
 impl Action {
 
-    fn perform(self, project: &mut Project, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+    // Graph actions apply to whichever graph they were recorded in
+    fn perform_on_graph(self, graph: &mut TerrainGraph) -> Self {
         match self {
             Action::GraphMoveNode(node_id, offset) => {
-                project.terrain_graph.nodes.get_mut(&node_id).unwrap().pos += offset;
+                graph.nodes.get_mut(&node_id).unwrap().pos += offset;
                 Action::GraphMoveNode(node_id, -offset)
             },
             Action::GraphAddNode(node_id, pos, ty) => {
-                project.terrain_graph.add_node_from_box_ty_with_id(pos, ty, node_id);
+                graph.add_node_from_box_ty_with_id(pos, ty, node_id);
                 Action::GraphDeleteNode(node_id)
             },
             Action::GraphDeleteNode(node_id) => {
-                let node = project.terrain_graph.delete_node(node_id).1.unwrap();
+                let node = graph.delete_node(node_id).1.unwrap();
                 Action::GraphAddNode(node_id, node.pos, node.ty)
             },
             Action::GraphConnect(connection) => {
-                project.terrain_graph.connect(connection);
+                graph.connect(connection);
                 Action::GraphDisconnect { to_node: connection.to, in_idx: connection.inp_idx }
             },
             Action::GraphDisconnect { to_node, in_idx } => {
-                let node = project.terrain_graph.nodes.get_mut(&to_node).unwrap();
+                let node = graph.nodes.get_mut(&to_node).unwrap();
                 let inp = &mut node.ty.inputs_mut()[in_idx as usize].2;
                 let (from, out_idx) = inp.connection.unwrap();
                 inp.connection = None;
@@ -77,19 +109,50 @@ impl Action {
                 })
             },
             Action::GraphSetInput { node: node_id, in_idx, val } => {
-                let node = project.terrain_graph.nodes.get_mut(&node_id).unwrap();
+                let node = graph.nodes.get_mut(&node_id).unwrap();
                 let inp = &mut node.ty.inputs_mut()[in_idx as usize].2;
                 let old_val = std::mem::replace(&mut inp.val, val);
                 Action::GraphSetInput { node: node_id, in_idx, val: old_val }
             },
+            Action::GraphSetCustomData { node: node_id, data } => {
+                let node = graph.nodes.get_mut(&node_id).unwrap();
+                let old_data = node.ty.custom_serialize();
+                node.ty.custom_deserialize(&data);
+                Action::GraphSetCustomData { node: node_id, data: old_data }
+            },
+            Action::Compound(acts) => {
+                let mut inv = Vec::new();
+                for act in acts {
+                    inv.push(act.perform_on_graph(graph));
+                }
+                inv.reverse();
+                Action::Compound(inv)
+            },
+            _ => unreachable!()
+        }
+    }
 
-            Action::BiomeCreate(idx, biome) => {
-                project.biomes.biomes.insert(idx, biome);
+    fn perform(self, project: &mut Project, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        match self {
+            act @ (Action::GraphMoveNode(..) | Action::GraphAddNode(..) | Action::GraphDeleteNode(..) | Action::GraphConnect(..) | Action::GraphDisconnect { .. } | Action::GraphSetInput { .. } | Action::GraphSetCustomData { .. }) => {
+                act.perform_on_graph(&mut project.terrain_graph)
+            },
+            Action::BiomeGraph(idx, act) => {
+                let graph = project.biomes.biomes[idx].graph.as_mut().unwrap();
+                Action::BiomeGraph(idx, Box::new(act.perform_on_graph(graph)))
+            },
+            Action::BiomeSetGraph(idx, graph) => {
+                let old_graph = std::mem::replace(&mut project.biomes.biomes[idx].graph, graph);
+                Action::BiomeSetGraph(idx, old_graph)
+            },
+
+            Action::BiomeCreate(idx, biome, adjacency) => {
+                project.biomes.insert_biome(idx, biome, adjacency);
                 Action::BiomeDelete(idx)
             },
             Action::BiomeDelete(idx) => {
-                let biome = project.biomes.biomes.remove(idx);
-                Action::BiomeCreate(idx, biome)
+                let (biome, adjacency) = project.biomes.remove_biome(idx);
+                Action::BiomeCreate(idx, biome, adjacency)
             },
             Action::BiomeSetSize(size) => {
                 let old_size = project.biomes.biome_size;
@@ -101,6 +164,11 @@ impl Action {
                 project.biomes.biome_blending = blending;
                 Action::BiomeSetBlending(old_blending)
             },
+            Action::BiomeSetRuleFalloff(falloff) => {
+                let old_falloff = project.biomes.rule_falloff;
+                project.biomes.rule_falloff = falloff;
+                Action::BiomeSetRuleFalloff(old_falloff)
+            },
             Action::BiomeSetName(idx, name) => {
                 let old_name = std::mem::replace(&mut project.biomes.biomes[idx].name, name);
                 Action::BiomeSetName(idx, old_name)
@@ -125,20 +193,60 @@ impl Action {
                 Action::BiomeSetMaxDepth(idx, old_max_depth)
             },
             Action::BiomeSetParameter { biome, param, val } => {
-                let old_val = *project.biomes.biomes[biome].params.get(&param).unwrap_or(&0.0);
+                let old_val = *project.biomes.biomes[biome].params.get(&param).unwrap_or(&BiomeParamValue::Scalar(0.0));
                 project.biomes.biomes[biome].params.insert(param.clone(), val);
                 Action::BiomeSetParameter { biome, param, val: old_val }
             },
-            Action::BiomeCreateParameter { param } => {
-                project.biomes.biome_params.push(param.clone());
-                Action::BiomeDeleteParameter { param }
+            Action::BiomeCreateParameter { idx, param } => {
+                project.biomes.biome_params.insert(idx, param);
+                Action::BiomeDeleteParameter { idx }
             },
-            Action::BiomeDeleteParameter { param } => {
-                if let Some(idx) = project.biomes.biome_params.iter().position(|other| other == &param) {
-                    project.biomes.biome_params.remove(idx);
+            Action::BiomeDeleteParameter { idx } => {
+                Action::BiomeCreateParameter { idx, param: project.biomes.biome_params.remove(idx) }
+            },
+            Action::BiomeSetParameterBlending { param, blend, sharpness } => {
+                let param_info = &mut project.biomes.biome_params[param];
+                let old_blend = std::mem::replace(&mut param_info.blend, blend);
+                let old_sharpness = std::mem::replace(&mut param_info.sharpness, sharpness);
+                Action::BiomeSetParameterBlending { param, blend: old_blend, sharpness: old_sharpness }
+            },
+            Action::BiomeSetDistribution(distribution) => {
+                let old_distribution = std::mem::replace(&mut project.biomes.distribution, distribution);
+                Action::BiomeSetDistribution(old_distribution)
+            },
+            Action::BiomeCreateClimateAxis { axis_idx, axis, ranges } => {
+                project.biomes.climate_axes.insert(axis_idx, axis);
+                for (biome, range) in project.biomes.biomes.iter_mut().zip(ranges) {
+                    if let Some(range) = range {
+                        biome.climate.insert(axis_idx, range);
+                    }
                 }
-                Action::BiomeCreateParameter { param }
-            }
+                Action::BiomeDeleteClimateAxis(axis_idx)
+            },
+            Action::BiomeDeleteClimateAxis(axis_idx) => {
+                let axis = project.biomes.climate_axes.remove(axis_idx);
+                let ranges = project.biomes.biomes.iter_mut()
+                    .map(|biome| (axis_idx < biome.climate.len()).then(|| biome.climate.remove(axis_idx)))
+                    .collect();
+                Action::BiomeCreateClimateAxis { axis_idx, axis, ranges }
+            },
+            Action::BiomeSetClimateAxis(axis_idx, axis) => {
+                let old_axis = std::mem::replace(&mut project.biomes.climate_axes[axis_idx], axis);
+                Action::BiomeSetClimateAxis(axis_idx, old_axis)
+            },
+            Action::BiomeSetClimateRange { biome, axis, range } => {
+                project.biomes.biomes[biome].fill_climate(&project.biomes.climate_axes);
+                let old_range = std::mem::replace(&mut project.biomes.biomes[biome].climate[axis], range);
+                Action::BiomeSetClimateRange { biome, axis, range: old_range }
+            },
+            Action::BiomeSetRules(idx, rules) => {
+                let old_rules = std::mem::replace(&mut project.biomes.biomes[idx].rules, rules);
+                Action::BiomeSetRules(idx, old_rules)
+            },
+            Action::BiomeSetAdjacency(adjacency) => {
+                let old_adjacency = std::mem::replace(&mut project.biomes.adjacency, adjacency);
+                Action::BiomeSetAdjacency(old_adjacency)
+            },
             Action::Compound(acts) => {
                 let mut inv = Vec::new();
                 for act in acts {
@@ -171,6 +279,13 @@ impl ActionManager {
         self.undo_stack.push(act);
     }
 
+    // Records the undo actions of `other`, e.g. to move edits of a biome graph over as BiomeGraph actions
+    pub fn push_undo_actions_from(&mut self, other: ActionManager, wrap: impl Fn(Action) -> Action) {
+        for act in other.undo_stack {
+            self.push_undo_action(wrap(act));
+        }
+    }
+
     pub fn can_undo(&self) -> bool {
         !self.undo_stack.is_empty()
     }
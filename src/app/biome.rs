@@ -1,9 +1,9 @@
 
 use std::{collections::HashMap, path::PathBuf};
 
-use crate::{biome::{Biome, BIOME_DEPTH_LIMIT}, util::ui::{drag_value_with_undo, ranged_drag_value_with_undo, slider_with_undo, textedit_with_undo}};
+use crate::{biome::{Biome, BiomeAdjacency, BiomeAdjacencyKind, BiomeDistribution, BiomeParamBlend, BiomeParamKind, BiomeParamValue, BiomeRule, BiomeRuleSource, ClimateAxis, BIOME_DEPTH_LIMIT}, compiler::BIOME_RULE_INPUTS, util::ui::{drag_value_with_undo, ranged_drag_value_with_undo, slider_with_undo, textedit_with_undo}};
 
-use super::{action::Action, App};
+use super::{action::{Action, ActionManager}, App};
 
 impl App {
 
@@ -14,6 +14,9 @@ impl App {
                 frequency: 1.0,
                 texture: PathBuf::new(),
                 params: HashMap::new(),
+                climate: Vec::new(),
+                rules: Vec::new(),
+                graph: None,
                 min_depth: -BIOME_DEPTH_LIMIT,
                 max_depth: BIOME_DEPTH_LIMIT,
                 color: [1.0, 0.0, 0.0] 
@@ -22,13 +25,40 @@ impl App {
         }
 
         ui.horizontal(|ui| {
-            ui.label("Biome Size:");
-            slider_with_undo(ui, &mut self.project.biomes.biome_size, 50.0..=2000.0, Action::BiomeSetSize, &mut self.actions);
+            ui.label("Distribution:");
+            let old_distribution = self.project.biomes.distribution;
+            egui::ComboBox::new("biome_distribution", "")
+                .selected_text(old_distribution.label())
+                .show_ui(ui, |ui| {
+                    for distribution in [BiomeDistribution::Voronoi, BiomeDistribution::Climate] {
+                        if ui.selectable_value(&mut self.project.biomes.distribution, distribution, distribution.label()).clicked() && distribution != old_distribution {
+                            self.actions.push_undo_action(Action::BiomeSetDistribution(old_distribution));
+                        }
+                    }
+                });
         });
+        if self.project.biomes.distribution == BiomeDistribution::Voronoi {
+            ui.horizontal(|ui| {
+                ui.label("Biome Size:");
+                slider_with_undo(ui, &mut self.project.biomes.biome_size, 50.0..=2000.0, Action::BiomeSetSize, &mut self.actions);
+            });
+        }
         ui.horizontal(|ui| {
             ui.label("Biome Blending:");
             slider_with_undo(ui, &mut self.project.biomes.biome_blending, 0.01..=0.9, Action::BiomeSetBlending, &mut self.actions);
         });
+        if self.project.biomes.biomes.iter().any(|biome| !biome.rules.is_empty()) {
+            ui.horizontal(|ui| {
+                ui.label("Rule Falloff:");
+                slider_with_undo(ui, &mut self.project.biomes.rule_falloff, 0.01..=1.0, Action::BiomeSetRuleFalloff, &mut self.actions);
+            });
+        }
+
+        if self.project.biomes.distribution == BiomeDistribution::Climate {
+            self.render_climate_axes(ui);
+        }
+        self.render_biome_adjacency(ui);
+        self.render_biome_parameters(ui);
 
         egui::ScrollArea::vertical().show(ui, |ui| {
             ui.allocate_exact_size(egui::Vec2::X * ui.available_width(), egui::Sense::click());
@@ -42,15 +72,32 @@ impl App {
                             ui.label("Name:");
                             textedit_with_undo(ui, &mut biome.name, |name| Action::BiomeSetName(idx, name), &mut self.actions);
                         });
-                        ui.horizontal(|ui| {
-                            ui.label("Frequency:");
-                            drag_value_with_undo(ui, &mut biome.frequency, |freq| Action::BiomeSetFrequency(idx, freq), &mut self.actions);
-                        });
+                        match self.project.biomes.distribution {
+                            BiomeDistribution::Voronoi => {
+                                ui.horizontal(|ui| {
+                                    ui.label("Frequency:");
+                                    drag_value_with_undo(ui, &mut biome.frequency, |freq| Action::BiomeSetFrequency(idx, freq), &mut self.actions);
+                                });
+                            },
+                            BiomeDistribution::Climate => {
+                                biome.fill_climate(&self.project.biomes.climate_axes);
+                                for (axis_idx, axis) in self.project.biomes.climate_axes.iter().enumerate() {
+                                    let old_range = biome.climate[axis_idx];
+                                    let range = &mut biome.climate[axis_idx];
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("{}:", axis.name));
+                                        ranged_drag_value_with_undo(ui, &mut range[0], axis.min..=old_range[1], |lo| Action::BiomeSetClimateRange { biome: idx, axis: axis_idx, range: [lo, old_range[1]] }, &mut self.actions);
+                                        ranged_drag_value_with_undo(ui, &mut range[1], old_range[0]..=axis.max, |hi| Action::BiomeSetClimateRange { biome: idx, axis: axis_idx, range: [old_range[0], hi] }, &mut self.actions);
+                                    });
+                                }
+                            }
+                        }
                         ui.horizontal(|ui| {
                             ui.label("Depth Range:");
                             ranged_drag_value_with_undo(ui, &mut biome.min_depth, -BIOME_DEPTH_LIMIT..=biome.max_depth, |depth| Action::BiomeSetMinDepth(idx, depth), &mut self.actions);
                             ranged_drag_value_with_undo(ui, &mut biome.max_depth, biome.min_depth..=BIOME_DEPTH_LIMIT, |depth| Action::BiomeSetMaxDepth(idx, depth), &mut self.actions);
                         });
+                        Self::render_biome_rules(ui, idx, biome, &mut self.actions);
 
                         ui.horizontal(|ui| {
                             ui.label("Texture:");
@@ -75,23 +122,35 @@ impl App {
 
                         ui.add_space(12.0);
                         let mut to_delete = None;
-                        for (idx, param) in self.project.biomes.biome_params.iter_mut().enumerate() { 
-                            if !biome.params.contains_key(param) {
-                                biome.params.insert(param.clone(), 0.0);
-                            }
+                        for (param_idx, param) in self.project.biomes.biome_params.iter().enumerate() { 
+                            let val = biome.params.entry(param.name.clone()).or_insert(param.kind.default_value());
+                            *val = val.of_kind(param.kind);
                             ui.horizontal(|ui| {
-                                ui.add(egui::Label::new(format!("{}:", param)).sense(egui::Sense::click())).context_menu(|ui| {
+                                ui.add(egui::Label::new(format!("{}:", param.name)).sense(egui::Sense::click())).context_menu(|ui| {
                                     if ui.button("Delete").clicked() {
-                                        to_delete = Some(idx);          
+                                        to_delete = Some(param_idx);          
                                     }
                                 });
-                                drag_value_with_undo(ui, biome.params.get_mut(param).unwrap(), |val| Action::BiomeSetParameter { biome: idx, param: param.clone(), val }, &mut self.actions);
+                                match (param.kind, val) {
+                                    (BiomeParamKind::Color, BiomeParamValue::Vector(color)) => {
+                                        ui.color_edit_button_rgb(color);
+                                    },
+                                    (_, BiomeParamValue::Vector(vec)) => {
+                                        let old_vec = *vec;
+                                        for c in vec.iter_mut() {
+                                            drag_value_with_undo(ui, c, |_| Action::BiomeSetParameter { biome: idx, param: param.name.clone(), val: BiomeParamValue::Vector(old_vec) }, &mut self.actions);
+                                        }
+                                    },
+                                    (_, BiomeParamValue::Scalar(x)) => {
+                                        drag_value_with_undo(ui, x, |val| Action::BiomeSetParameter { biome: idx, param: param.name.clone(), val: BiomeParamValue::Scalar(val) }, &mut self.actions);
+                                    }
+                                }
                             });
                         }
 
                         if let Some(to_delete) = to_delete {
                             let param = self.project.biomes.biome_params.remove(to_delete);
-                            self.actions.push_undo_action(Action::BiomeCreateParameter { param });
+                            self.actions.push_undo_action(Action::BiomeCreateParameter { idx: to_delete, param });
                         }
                         if ui.button(format!("{} Parameter", egui_phosphor::regular::PLUS)).clicked() {
                             self.add_biome_parameter_dialog_open = true;
@@ -113,8 +172,8 @@ impl App {
             }
 
             if let Some(biome_to_delete) = biome_to_delete {
-                let biome = self.project.biomes.biomes.remove(biome_to_delete);
-                self.actions.push_undo_action(Action::BiomeCreate(biome_to_delete, biome));
+                let (biome, adjacency) = self.project.biomes.remove_biome(biome_to_delete);
+                self.actions.push_undo_action(Action::BiomeCreate(biome_to_delete, biome, adjacency));
             }
 
         });
@@ -131,9 +190,17 @@ impl App {
                 .show(ui.ctx(), |ui| {
                     ui.vertical_centered(|ui| {
                         ui.text_edit_singleline(&mut self.add_biome_parameter_name);
+                        egui::ComboBox::new("add_biome_parameter_kind", "")
+                            .selected_text(self.add_biome_parameter_kind.label())
+                            .show_ui(ui, |ui| {
+                                for kind in [BiomeParamKind::Scalar, BiomeParamKind::Vector, BiomeParamKind::Color] {
+                                    ui.selectable_value(&mut self.add_biome_parameter_kind, kind, kind.label());
+                                }
+                            });
                         if ui.button("Add").clicked() {
-                            self.actions.push_undo_action(Action::BiomeDeleteParameter { param: self.add_biome_parameter_name.clone() });
-                            self.project.biomes.add_parameter(std::mem::replace(&mut self.add_biome_parameter_name, String::new()));
+                            if self.project.biomes.add_parameter(std::mem::take(&mut self.add_biome_parameter_name), self.add_biome_parameter_kind) {
+                                self.actions.push_undo_action(Action::BiomeDeleteParameter { idx: self.project.biomes.biome_params.len() - 1 });
+                            }
                             close_dialog = true;
                         }
                     });
@@ -144,4 +211,183 @@ impl App {
         }
     }
 
+    fn render_biome_rules(ui: &mut egui::Ui, idx: usize, biome: &mut Biome, actions: &mut ActionManager) {
+        let old_rules = biome.rules.clone();
+        let mut rule_to_delete = None;
+        for (rule_idx, rule) in biome.rules.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                egui::ComboBox::new((idx, "rule_source", rule_idx), "")
+                    .selected_text(rule.source.label())
+                    .width(90.0)
+                    .show_ui(ui, |ui| {
+                        let sources = [BiomeRuleSource::Height, BiomeRuleSource::Slope].into_iter().chain((0..BIOME_RULE_INPUTS).map(BiomeRuleSource::Graph));
+                        for source in sources {
+                            if ui.selectable_label(rule.source == source, source.label()).clicked() && rule.source != source {
+                                rule.source = source;
+                                actions.push_undo_action(Action::BiomeSetRules(idx, old_rules.clone()));
+                            }
+                        }
+                    }).response.context_menu(|ui| {
+                        if ui.button("Delete").clicked() {
+                            rule_to_delete = Some(rule_idx);
+                            ui.close_menu();
+                        }
+                    });
+                ranged_drag_value_with_undo(ui, &mut rule.min, f32::NEG_INFINITY..=rule.max, |_| Action::BiomeSetRules(idx, old_rules.clone()), actions);
+                ranged_drag_value_with_undo(ui, &mut rule.max, rule.min..=f32::INFINITY, |_| Action::BiomeSetRules(idx, old_rules.clone()), actions);
+                ui.label("Smoothness:");
+                ranged_drag_value_with_undo(ui, &mut rule.smoothness, 0.0..=f32::INFINITY, |_| Action::BiomeSetRules(idx, old_rules.clone()), actions);
+            });
+        }
+        if let Some(rule_idx) = rule_to_delete {
+            biome.rules.remove(rule_idx);
+            actions.push_undo_action(Action::BiomeSetRules(idx, old_rules.clone()));
+        }
+        if ui.button(format!("{} Rule", egui_phosphor::regular::PLUS)).clicked() {
+            biome.rules.push(BiomeRule { source: BiomeRuleSource::Height, min: 0.0, max: 100.0, smoothness: 10.0 });
+            actions.push_undo_action(Action::BiomeSetRules(idx, old_rules));
+        }
+    }
+
+    fn render_biome_parameters(&mut self, ui: &mut egui::Ui) {
+        if self.project.biomes.biome_params.is_empty() {
+            return;
+        }
+        egui::CollapsingHeader::new("Parameter Blending").show(ui, |ui| {
+            for (idx, param) in self.project.biomes.biome_params.iter_mut().enumerate() {
+                let (old_blend, old_sharpness) = (param.blend, param.sharpness);
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} ({}):", param.name, param.kind.label()));
+                    egui::ComboBox::new(("param_blend", idx), "")
+                        .selected_text(param.blend.label())
+                        .width(90.0)
+                        .show_ui(ui, |ui| {
+                            for blend in [BiomeParamBlend::Linear, BiomeParamBlend::Max, BiomeParamBlend::Min, BiomeParamBlend::SmoothMax, BiomeParamBlend::Dominant] {
+                                if ui.selectable_label(param.blend == blend, blend.label()).clicked() && param.blend != blend {
+                                    param.blend = blend;
+                                    self.actions.push_undo_action(Action::BiomeSetParameterBlending { param: idx, blend: old_blend, sharpness: old_sharpness });
+                                }
+                            }
+                        });
+                    if param.blend != BiomeParamBlend::Dominant {
+                        ui.label("Sharpness:");
+                        ranged_drag_value_with_undo(ui, &mut param.sharpness, 0.0..=f32::INFINITY, |sharpness| Action::BiomeSetParameterBlending { param: idx, blend: old_blend, sharpness }, &mut self.actions);
+                    }
+                });
+            }
+        });
+    }
+
+    fn render_biome_adjacency(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Adjacency").show(ui, |ui| {
+            // climate biomes go wherever their climate is, so there are no cells to reroll
+            if self.project.biomes.distribution == BiomeDistribution::Climate {
+                ui.label("Adjacency rules only apply to the Voronoi distribution.");
+                return;
+            }
+            let old_adjacency = self.project.biomes.adjacency.clone();
+            let biomes = &self.project.biomes.biomes;
+            let mut changed = false;
+            let mut rule_to_delete = None;
+            for (rule_idx, rule) in self.project.biomes.adjacency.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::new(("adjacency_biome", rule_idx), "")
+                        .selected_text(biomes.get(rule.biome).map(|biome| biome.name.as_str()).unwrap_or(""))
+                        .width(80.0)
+                        .show_ui(ui, |ui| {
+                            for (biome_idx, biome) in biomes.iter().enumerate() {
+                                if ui.selectable_label(rule.biome == biome_idx, &biome.name).clicked() && rule.biome != biome_idx {
+                                    rule.biome = biome_idx;
+                                    changed = true;
+                                }
+                            }
+                        }).response.context_menu(|ui| {
+                            if ui.button("Delete").clicked() {
+                                rule_to_delete = Some(rule_idx);
+                                ui.close_menu();
+                            }
+                        });
+                    egui::ComboBox::new(("adjacency_kind", rule_idx), "")
+                        .selected_text(rule.kind.label())
+                        .width(90.0)
+                        .show_ui(ui, |ui| {
+                            for kind in [BiomeAdjacencyKind::Never, BiomeAdjacencyKind::Only] {
+                                if ui.selectable_label(rule.kind == kind, kind.label()).clicked() && rule.kind != kind {
+                                    rule.kind = kind;
+                                    changed = true;
+                                }
+                            }
+                        });
+                    let others = rule.others.iter().filter_map(|idx| biomes.get(*idx)).map(|biome| biome.name.as_str()).collect::<Vec<_>>().join(", ");
+                    ui.menu_button(if others.is_empty() { "..." } else { &others }, |ui| {
+                        for (biome_idx, biome) in biomes.iter().enumerate() {
+                            let mut selected = rule.others.contains(&biome_idx);
+                            if ui.checkbox(&mut selected, &biome.name).changed() {
+                                if selected {
+                                    rule.others.push(biome_idx);
+                                } else {
+                                    rule.others.retain(|idx| *idx != biome_idx);
+                                }
+                                changed = true;
+                            }
+                        }
+                    });
+                });
+            }
+
+            if let Some(rule_idx) = rule_to_delete {
+                self.project.biomes.adjacency.remove(rule_idx);
+                changed = true;
+            }
+            if ui.button(format!("{} Adjacency Rule", egui_phosphor::regular::PLUS)).clicked() {
+                self.project.biomes.adjacency.push(BiomeAdjacency { biome: 0, kind: BiomeAdjacencyKind::Never, others: Vec::new() });
+                changed = true;
+            }
+            if changed {
+                self.actions.push_undo_action(Action::BiomeSetAdjacency(old_adjacency));
+            }
+        });
+    }
+
+    fn render_climate_axes(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Climate Axes").default_open(true).show(ui, |ui| {
+            let mut axis_to_delete = None;
+            let n_axes = self.project.biomes.climate_axes.len();
+            for (idx, axis) in self.project.biomes.climate_axes.iter_mut().enumerate() {
+                let old_axis = axis.clone();
+                ui.horizontal(|ui| {
+                    textedit_with_undo(ui, &mut axis.name, |name| Action::BiomeSetClimateAxis(idx, ClimateAxis { name, ..old_axis.clone() }), &mut self.actions);
+                    ui.add(egui::Label::new(egui_phosphor::regular::DOTS_THREE).sense(egui::Sense::click())).context_menu(|ui| {
+                        // the climate space needs at least two axes
+                        if ui.add_enabled(n_axes > 2, egui::Button::new("Delete")).clicked() {
+                            axis_to_delete = Some(idx);
+                            ui.close_menu();
+                        }
+                    });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Scale:");
+                    ranged_drag_value_with_undo(ui, &mut axis.scale, 1.0..=f32::INFINITY, |scale| Action::BiomeSetClimateAxis(idx, ClimateAxis { scale, ..old_axis.clone() }), &mut self.actions);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Range:");
+                    ranged_drag_value_with_undo(ui, &mut axis.min, f32::NEG_INFINITY..=old_axis.max, |min| Action::BiomeSetClimateAxis(idx, ClimateAxis { min, ..old_axis.clone() }), &mut self.actions);
+                    ranged_drag_value_with_undo(ui, &mut axis.max, old_axis.min..=f32::INFINITY, |max| Action::BiomeSetClimateAxis(idx, ClimateAxis { max, ..old_axis.clone() }), &mut self.actions);
+                });
+            }
+
+            if let Some(axis_idx) = axis_to_delete {
+                let axis = self.project.biomes.climate_axes.remove(axis_idx);
+                let ranges = self.project.biomes.biomes.iter_mut()
+                    .map(|biome| (axis_idx < biome.climate.len()).then(|| biome.climate.remove(axis_idx)))
+                    .collect();
+                self.actions.push_undo_action(Action::BiomeCreateClimateAxis { axis_idx, axis, ranges });
+            }
+            if ui.button(format!("{} Climate Axis", egui_phosphor::regular::PLUS)).clicked() {
+                self.project.biomes.climate_axes.push(ClimateAxis { name: "Axis".to_owned(), scale: 1000.0, min: 0.0, max: 100.0 });
+                self.actions.push_undo_action(Action::BiomeDeleteClimateAxis(self.project.biomes.climate_axes.len() - 1));
+            }
+        });
+    }
+
 }
@@ -1,12 +1,57 @@
-
-use super::App;
+use super::{action::{Action, ActionManager}, default_terrain_graph, App};
 
 pub mod ui;
 
 impl App {
 
     pub fn render_graph(&mut self, ui: &mut egui::Ui) {
-        self.project.terrain_graph.render(ui, &mut self.actions, &self.project.biomes);
+        if self.edited_biome_graph.is_some_and(|idx| idx >= self.project.biomes.biomes.len()) {
+            self.edited_biome_graph = None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Graph:");
+            let selected_text = match self.edited_biome_graph {
+                Some(idx) => self.project.biomes.biomes[idx].name.clone(),
+                None => "Project".to_owned(),
+            };
+            egui::ComboBox::new("edited_graph", "")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.edited_biome_graph, None, "Project");
+                    for (idx, biome) in self.project.biomes.biomes.iter().enumerate() {
+                        let label = if biome.graph.is_some() { format!("{} (#{})", biome.name, idx) } else { format!("{} (#{}, no graph)", biome.name, idx) };
+                        ui.selectable_value(&mut self.edited_biome_graph, Some(idx), label);
+                    }
+                });
+            if let Some(idx) = self.edited_biome_graph {
+                let biome = &mut self.project.biomes.biomes[idx];
+                if biome.graph.is_none() && ui.button(format!("{} Biome Graph", egui_phosphor::regular::PLUS)).clicked() {
+                    biome.graph = Some(default_terrain_graph());
+                    self.actions.push_undo_action(Action::BiomeSetGraph(idx, None));
+                } else if biome.graph.is_some() && ui.button("Remove Biome Graph").clicked() {
+                    let graph = biome.graph.take();
+                    self.actions.push_undo_action(Action::BiomeSetGraph(idx, graph));
+                }
+            }
+        });
+
+        match self.edited_biome_graph {
+            None => self.project.terrain_graph.render(ui, &mut self.actions, &self.project.biomes, &self.heightmap_loader.files, &self.texture_loader),
+            Some(idx) => {
+                // the graph is taken out of the biome while it's edited, so the biomes can still be shown in the nodes
+                let Some(mut graph) = self.project.biomes.biomes[idx].graph.take() else {
+                    ui.centered_and_justified(|ui| {
+                        ui.label("This biome uses the project graph.");
+                    });
+                    return;
+                };
+                let mut graph_actions = ActionManager::new();
+                graph.render(ui, &mut graph_actions, &self.project.biomes, &self.heightmap_loader.files, &self.texture_loader);
+                self.project.biomes.biomes[idx].graph = Some(graph);
+                self.actions.push_undo_actions_from(graph_actions, |act| Action::BiomeGraph(idx, Box::new(act)));
+            }
+        }
     }
 
 }
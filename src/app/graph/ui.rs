@@ -1,9 +1,9 @@
 
-use std::u32;
+use std::{path::PathBuf, u32};
 
 use egui::{emath::TSTransform, epaint::{CubicBezierShape, RectShape}, pos2, vec2, Align, Color32, Id, LayerId, Layout, Order, Pos2, Rect, Rounding, Sense, Shape, Stroke, TextureId, Vec2};
 
-use crate::{app::action::{Action, ActionManager}, biome::Biomes, graph::{node_types::NODE_TYPES, Connection, GraphProjectInfo, Node, NodeId, TerrainGraph, Type, Value}, util::ui::{drag_value_with_undo, get_init_numeric_val}};
+use crate::{app::{action::{Action, ActionManager}, texture_loader::TextureLoader}, biome::Biomes, graph::{node_types::NODE_TYPES, Connection, GraphProjectInfo, Node, NodeId, TerrainGraph, Type, Value}, util::ui::{drag_value_with_undo, get_init_numeric_val}};
 
 impl Type {
 
@@ -154,7 +154,7 @@ impl TerrainGraph {
         }
     }
 
-    fn node_area_contents(id: NodeId, actions: &mut ActionManager, ui_layer_id: LayerId, node_ui: &mut egui::Ui, rect: Rect, node_rect: Rect, node: &mut Node, delete_node: &mut bool, to_connect: &mut Vec<Connection>, to_disconnect: &mut Vec<u32>, connections_to_draw: &mut Vec<(Pos2, Pos2, Color32)>, reconnection_idx: &mut u32, reconnect: &mut bool, transform: TSTransform, biomes: &Biomes) {
+    fn node_area_contents(id: NodeId, actions: &mut ActionManager, ui_layer_id: LayerId, node_ui: &mut egui::Ui, rect: Rect, node_rect: Rect, node: &mut Node, delete_node: &mut bool, to_connect: &mut Vec<Connection>, to_disconnect: &mut Vec<u32>, connections_to_draw: &mut Vec<(Pos2, Pos2, Color32)>, reconnection_idx: &mut u32, reconnect: &mut bool, transform: TSTransform, biomes: &Biomes, heightmap_files: &[PathBuf], textures: &TextureLoader) {
 
         let label = node.ty.label(); 
         let outputs = node.ty.outputs();
@@ -270,14 +270,37 @@ impl TerrainGraph {
             param_rect = param_rect.translate(Vec2::Y * PARAM_SIZE.y);
         }
 
-        node.ty.custom_ui(node_ui, &GraphProjectInfo {
-            biomes
+        // custom ui gets the space left below the params
+        let custom_ui_rect = Rect::from_min_max(pos2(node_rect.left(), param_rect.top()), node_rect.right_bottom());
+        let mut custom_ui = node_ui.child_ui(custom_ui_rect, Layout::default(), None);
+        let prev_data = node.ty.custom_serialize();
+        node.ty.custom_ui(&mut custom_ui, &GraphProjectInfo {
+            biomes,
+            heightmaps: &[],
+            heightmap_sizes: &[],
+            heightmap_files,
+            layer_textures: &[],
+            textures
         }); 
 
+        // custom ui edits are undone together until the pointer is released and nothing is being typed in, so a drag is one action
+        let edit_id = Id::new(("custom_ui_edit", id));
+        let data = node.ty.custom_serialize();
+        if data != prev_data && node_ui.memory(|mem| mem.data.get_temp::<serde_json::Value>(edit_id).is_none()) {
+            node_ui.memory_mut(|mem| mem.data.insert_temp(edit_id, prev_data));
+        }
+        if !node_ui.input(|i| i.pointer.any_down()) && node_ui.memory(|mem| mem.focused().is_none()) {
+            if let Some(init_data) = node_ui.memory_mut(|mem| mem.data.remove_temp::<serde_json::Value>(edit_id)) {
+                if init_data != data {
+                    actions.push_undo_action(Action::GraphSetCustomData { node: id, data: init_data });
+                }
+            }
+        }
+
         node_ui.advance_cursor_after_rect(node_rect);
     }
 
-    pub fn render_node(&mut self, id: NodeId, ui: &mut egui::Ui, actions: &mut ActionManager, rect: Rect, connections_to_draw: &mut Vec<(Pos2, Pos2, Color32)>, biomes: &Biomes, use_mouse: bool) {
+    pub fn render_node(&mut self, id: NodeId, ui: &mut egui::Ui, actions: &mut ActionManager, rect: Rect, connections_to_draw: &mut Vec<(Pos2, Pos2, Color32)>, biomes: &Biomes, heightmap_files: &[PathBuf], textures: &TextureLoader, use_mouse: bool) {
         let node = self.nodes.get_mut(&id).unwrap();
         let outputs = node.ty.outputs();
         let custom_ui_height = node.ty.custom_ui_height();
@@ -305,7 +328,7 @@ impl TerrainGraph {
             .order(egui::Order::Foreground)
             .sense(egui::Sense::click_and_drag())
             .show(ui.ctx(), |node_ui| {
-                Self::node_area_contents(id, actions, ui.layer_id(), node_ui, rect, node_rect, node, &mut delete_node, &mut to_connect, &mut to_disconnect, connections_to_draw, &mut reconnection_idx, &mut reconnect, self.transform, biomes); 
+                Self::node_area_contents(id, actions, ui.layer_id(), node_ui, rect, node_rect, node, &mut delete_node, &mut to_connect, &mut to_disconnect, connections_to_draw, &mut reconnection_idx, &mut reconnect, self.transform, biomes, heightmap_files, textures); 
             }).response;
 
         ui.ctx().set_transform_layer(resp.layer_id, self.transform);
@@ -394,7 +417,7 @@ impl TerrainGraph {
 
     }
 
-    pub fn render(&mut self, ui: &mut egui::Ui, actions: &mut ActionManager, biomes: &Biomes) {
+    pub fn render(&mut self, ui: &mut egui::Ui, actions: &mut ActionManager, biomes: &Biomes, heightmap_files: &[PathBuf], textures: &TextureLoader) {
         let (rect, resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
 
         let transform = TSTransform::from_translation(ui.min_rect().left_top().to_vec2()) * self.transform;
@@ -439,7 +462,7 @@ impl TerrainGraph {
         
         let use_mouse = resp.rect.contains(ui.input(|i| i.pointer.hover_pos().unwrap_or(Pos2::new(-10.0, -10.0))));
         for id in self.nodes.keys().map(|id| *id).collect::<Vec<_>>() {
-            self.render_node(id, ui, actions, rect, &mut connections, biomes, use_mouse); 
+            self.render_node(id, ui, actions, rect, &mut connections, biomes, heightmap_files, textures, use_mouse); 
         }
 
         egui::Area::new(Id::from("connections")).order(Order::Middle).show(ui.ctx(), |ui| {
@@ -0,0 +1,218 @@
+use std::{collections::{BTreeSet, HashMap}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::{Duration, SystemTime}};
+
+use eframe::wgpu;
+
+use crate::terrain::{map_export::MAP_EXPORT_DIR, meshgen::{Heightmap, CHUNK_CACHE_DIR, MAX_HEIGHTMAP_RES}};
+
+use super::viewport::TerrainRenderResources;
+
+// Loads grayscale PNGs from the project directory so they can be sampled by the Image Heightmap node.
+// Every heightmap keeps its resolution and becomes one layer of the mesh generator's heightmap texture.
+fn load_heightmap(path: &Path) -> Option<Heightmap> {
+    let data = std::fs::read(path).ok()?;
+    let img = image::load_from_memory_with_format(&data, image::ImageFormat::Png).ok()?;
+
+    // 8 bit images are widened, so both end up in the 0..1 range
+    let mut img = img.to_luma16();
+    if img.width() > MAX_HEIGHTMAP_RES || img.height() > MAX_HEIGHTMAP_RES {
+        let scale = MAX_HEIGHTMAP_RES as f32 / img.width().max(img.height()) as f32;
+        let width = ((img.width() as f32 * scale) as u32).clamp(1, MAX_HEIGHTMAP_RES);
+        let height = ((img.height() as f32 * scale) as u32).clamp(1, MAX_HEIGHTMAP_RES);
+        img = image::imageops::resize(&img, width, height, image::imageops::FilterType::Triangle);
+    }
+    Some(Heightmap {
+        width: img.width(),
+        height: img.height(),
+        heights: img.pixels().map(|pixel| pixel.0[0] as f32 / u16::MAX as f32).collect()
+    })
+}
+
+// Every PNG in the project directory, relative to it. The chunk cache is skipped, it only holds meshes and can get big,
+// and so are exported maps.
+fn list_pngs(proj_path: &Path) -> Vec<PathBuf> {
+    let chunk_cache = proj_path.join(CHUNK_CACHE_DIR);
+    let maps = proj_path.join(MAP_EXPORT_DIR);
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(proj_path).into_iter()
+        .filter_entry(|entry| entry.path() != chunk_cache && entry.path() != maps)
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().map(|ext| ext.to_string_lossy().to_lowercase()) == Some("png".to_owned()))
+        .filter_map(|path| pathdiff::diff_paths(path, proj_path))
+        .collect();
+    files.sort();
+    files
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+enum HeightmapEvent {
+    Files(Vec<PathBuf>),
+    Loaded(PathBuf, Heightmap),
+    Removed(PathBuf)
+}
+
+// Only the heightmaps referenced by Image Heightmap nodes are loaded. They're reloaded when the file changes and
+// dropped when it's deleted or no longer referenced. Layers are sorted by path, so they don't depend on load order.
+pub struct HeightmapLoader {
+    thread: Option<std::thread::JoinHandle<()>>,
+    // tells the thread to stop, it's joined when the loader is dropped
+    shutdown: Arc<AtomicBool>,
+    rx: std::sync::mpsc::Receiver<HeightmapEvent>,
+    referenced: Arc<Mutex<BTreeSet<PathBuf>>>,
+    // PNGs in the project directory that can be picked in the node
+    pub files: Vec<PathBuf>,
+    // the loaded heightmaps, the index is the texture layer
+    pub paths: Vec<PathBuf>,
+    data: Vec<Heightmap>
+}
+
+impl HeightmapLoader {
+
+    pub fn new(proj_path: PathBuf) -> Self {
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let referenced = Arc::new(Mutex::new(BTreeSet::<PathBuf>::new()));
+        let thread_referenced = referenced.clone();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+
+        let thread = std::thread::spawn(move || {
+
+            let mut files = Vec::new();
+            let mut loaded: HashMap<PathBuf, SystemTime> = HashMap::new();
+
+            // poor man's file watcher
+            while !thread_shutdown.load(Ordering::Relaxed) {
+                let new_files = list_pngs(&proj_path);
+                if new_files != files {
+                    files = new_files;
+                    if tx.send(HeightmapEvent::Files(files.clone())).is_err() {
+                        return;
+                    }
+                }
+
+                let referenced = thread_referenced.lock().unwrap().clone();
+                for path in &referenced {
+                    let Some(time) = modified(&proj_path.join(path)) else { continue; };
+                    if loaded.get(path) == Some(&time) {
+                        continue;
+                    }
+                    // remembered even if loading fails, so a broken file isn't read again until it changes
+                    loaded.insert(path.clone(), time);
+                    match load_heightmap(&proj_path.join(path)) {
+                        Some(heightmap) => { let _ = tx.send(HeightmapEvent::Loaded(path.clone(), heightmap)); },
+                        None => { let _ = tx.send(HeightmapEvent::Removed(path.clone())); }
+                    }
+                }
+                loaded.retain(|path, _| {
+                    let keep = referenced.contains(path) && proj_path.join(path).exists();
+                    if !keep {
+                        let _ = tx.send(HeightmapEvent::Removed(path.clone()));
+                    }
+                    keep
+                });
+
+                // in short steps, so dropping the loader doesn't wait long
+                for _ in 0..10 {
+                    if thread_shutdown.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            }
+
+        });
+
+        Self {
+            thread: Some(thread),
+            shutdown,
+            rx,
+            referenced,
+            files: Vec::new(),
+            paths: Vec::new(),
+            data: Vec::new()
+        }
+
+    }
+
+    // Loads the given heightmaps right away, without watching for changes
+    pub fn load_now(proj_path: PathBuf, referenced: BTreeSet<PathBuf>) -> Self {
+        let mut loader = Self {
+            thread: None,
+            shutdown: Arc::new(AtomicBool::new(true)),
+            rx: std::sync::mpsc::channel().1,
+            referenced: Arc::new(Mutex::new(BTreeSet::new())),
+            files: list_pngs(&proj_path),
+            paths: Vec::new(),
+            data: Vec::new()
+        };
+        for path in referenced {
+            if let Some(heightmap) = load_heightmap(&proj_path.join(&path)) {
+                loader.insert(path, heightmap);
+            }
+        }
+        loader
+    }
+
+    pub fn heightmaps(&self) -> &[Heightmap] {
+        &self.data
+    }
+
+    // The heightmaps the graphs sample, relative to the project directory
+    pub fn set_referenced(&self, paths: BTreeSet<PathBuf>) {
+        *self.referenced.lock().unwrap() = paths;
+    }
+
+    fn insert(&mut self, path: PathBuf, heightmap: Heightmap) {
+        match self.paths.binary_search(&path) {
+            Ok(idx) => self.data[idx] = heightmap,
+            Err(idx) => {
+                self.paths.insert(idx, path);
+                self.data.insert(idx, heightmap);
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Path) -> bool {
+        let Ok(idx) = self.paths.binary_search_by(|other| other.as_path().cmp(path)) else { return false; };
+        self.paths.remove(idx);
+        self.data.remove(idx);
+        true
+    }
+
+    pub fn tick(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, renderer: &mut eframe::egui_wgpu::Renderer) {
+
+        let mut changed = false;
+        while let Ok(event) = self.rx.try_recv() {
+            match event {
+                HeightmapEvent::Files(files) => self.files = files,
+                HeightmapEvent::Loaded(path, heightmap) => {
+                    self.insert(path, heightmap);
+                    changed = true;
+                },
+                HeightmapEvent::Removed(path) => changed |= self.remove(&path)
+            }
+        }
+
+        if changed {
+            let resources = renderer.callback_resources.get_mut::<TerrainRenderResources>().unwrap();
+            resources.mesh_generator.update_heightmaps(device, queue, &self.data);
+            resources.terrain.clear();
+        }
+
+    }
+
+}
+
+impl Drop for HeightmapLoader {
+
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+}
@@ -0,0 +1,113 @@
+use eframe::wgpu;
+
+use crate::{compiler::{compile, CompilationTarget}, terrain::map_export::{HeightFormat, MapBiome, MapEvaluation, MAP_EXPORT_DIR, MAX_MAP_RESOLUTION}};
+
+use super::App;
+
+impl App {
+
+    pub(super) fn render_map_export_dialog(&mut self, ctx: &egui::Context, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.poll_map_export(ctx, device, queue);
+
+        let mut export = false;
+        egui::Window::new("Export Maps")
+            .open(&mut self.map_export_dialog_open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("map_export_region").num_columns(2).show(ui, |ui| {
+                    let region = &mut self.map_region;
+                    ui.label("Min (x, z):");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut region.min[0]));
+                        ui.add(egui::DragValue::new(&mut region.min[1]));
+                    });
+                    ui.end_row();
+                    ui.label("Size:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut region.size[0]).range(1.0..=f32::INFINITY));
+                        ui.add(egui::DragValue::new(&mut region.size[1]).range(1.0..=f32::INFINITY));
+                    });
+                    ui.end_row();
+                    ui.label("Resolution:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut region.resolution[0]).range(1..=MAX_MAP_RESOLUTION));
+                        ui.add(egui::DragValue::new(&mut region.resolution[1]).range(1..=MAX_MAP_RESOLUTION));
+                    });
+                    ui.end_row();
+                    ui.label("Height Range:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut region.min_height));
+                        ui.add(egui::DragValue::new(&mut region.max_height));
+                    });
+                    ui.end_row();
+                    ui.label("Sampling Step:");
+                    ui.add(egui::DragValue::new(&mut region.step).speed(0.05).range(0.05..=64.0));
+                    ui.end_row();
+                    ui.label("Height Format:");
+                    egui::ComboBox::new("map_height_format", "")
+                        .selected_text(self.map_height_format.label())
+                        .show_ui(ui, |ui| {
+                            for format in [HeightFormat::Png16, HeightFormat::RawF32] {
+                                ui.selectable_value(&mut self.map_height_format, format, format.label());
+                            }
+                        });
+                    ui.end_row();
+                });
+                ui.add_space(4.0);
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(self.map_export.is_none() && self.map_save.is_none(), egui::Button::new("Export...")).clicked() {
+                        export = true;
+                    }
+                    ui.label(&self.map_export_status);
+                });
+            });
+
+        if !export {
+            return;
+        }
+        // not the project directory itself, where the images would be offered as heightmaps
+        let default_dir = self.project_path.join(MAP_EXPORT_DIR);
+        let _ = std::fs::create_dir_all(&default_dir);
+        let Some(dir) = rfd::FileDialog::new().set_directory(&default_dir).pick_folder() else { return; };
+        if self.map_region.min_height >= self.map_region.max_height {
+            self.map_export_status = "Height range is empty".to_owned();
+            return;
+        }
+
+        let code = compile(&self.project.terrain_graph, &self.project.biomes, &self.texture_loader, &self.heightmap_loader, CompilationTarget::WGSL, self.project_path.file_name().unwrap().to_str());
+        let evaluation = MapEvaluation::new(device, queue, &code, self.heightmap_loader.heightmaps(), self.project.biomes.biomes.len(), &self.map_region);
+        self.map_export = Some((evaluation, dir, self.map_height_format));
+        self.map_export_status = "Exporting... 0%".to_owned();
+    }
+
+    // Advances the export in progress. Once every row is evaluated, the maps are encoded and saved on a thread of their own.
+    fn poll_map_export(&mut self, ctx: &egui::Context, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some((save, dir)) = self.map_save.take() {
+            if !save.is_finished() {
+                self.map_save = Some((save, dir));
+                ctx.request_repaint();
+                return;
+            }
+            self.map_export_status = match save.join() {
+                Ok(Ok(())) => format!("Exported to {}", dir.to_string_lossy()),
+                Ok(Err(err)) => format!("Export failed: {}", err),
+                Err(_) => "Export failed".to_owned()
+            };
+        }
+
+        let Some((evaluation, dir, format)) = &mut self.map_export else { return; };
+        let Some(map) = evaluation.poll(device, queue, false) else {
+            self.map_export_status = format!("Exporting... {}%", (evaluation.progress() * 100.0) as u32);
+            ctx.request_repaint();
+            return;
+        };
+        let biomes = MapBiome::from_biomes(&self.project.biomes);
+        let (save_dir, format) = (dir.clone(), *format);
+        self.map_save = Some((std::thread::spawn(move || map.save(&save_dir, &biomes, format)), dir.clone()));
+        self.map_export_status = "Saving...".to_owned();
+        self.map_export = None;
+        ctx.request_repaint();
+    }
+
+}
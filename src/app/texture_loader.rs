@@ -3,7 +3,7 @@ use std::{collections::{HashMap, HashSet}, path::PathBuf};
 
 use eframe::wgpu;
 
-use crate::terrain::texture_atlas::TextureBlitter;
+use crate::terrain::{map_export::MAP_EXPORT_DIR, meshgen::CHUNK_CACHE_DIR, texture_atlas::TextureBlitter};
 
 use super::viewport::TerrainRenderResources;
 
@@ -81,7 +81,7 @@ impl Texture {
 
 pub struct TextureLoader {
     #[allow(unused)]
-    thread: std::thread::JoinHandle<()>,
+    thread: Option<std::thread::JoinHandle<()>>,
     rx: std::sync::mpsc::Receiver<(std::path::PathBuf, image::ImageBuffer<image::Rgba<u8>, Vec<u8>>)>,
     pub textures: HashMap<PathBuf, Texture>,
     curr_slot: u32 
@@ -98,9 +98,12 @@ impl TextureLoader {
 
             let mut loaded = HashSet::new();
 
-            // poor man's file watcher
+            // poor man's file watcher, the chunk cache only holds meshes and exported maps aren't textures
+            let chunk_cache = proj_path.join(CHUNK_CACHE_DIR);
+            let maps = proj_path.join(MAP_EXPORT_DIR);
             loop {
                 for path in walkdir::WalkDir::new(&proj_path).into_iter()
+                    .filter_entry(|entry| entry.path() != chunk_cache && entry.path() != maps)
                     .filter_map(|entry | entry.ok())
                     .map(|entry| entry.into_path()) { 
                         if loaded.contains(&path) {
@@ -120,7 +123,7 @@ impl TextureLoader {
         });
 
         Self {
-            thread,
+            thread: Some(thread),
             rx,
             textures: HashMap::new(),
             curr_slot: 1
@@ -128,6 +131,16 @@ impl TextureLoader {
 
     }
 
+    // For compiling without a renderer, every texture ends up in the error slot
+    pub fn without_textures() -> Self {
+        Self {
+            thread: None,
+            rx: std::sync::mpsc::channel().1,
+            textures: HashMap::new(),
+            curr_slot: 1
+        }
+    }
+
     pub fn tick(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, blitter: &mut TextureBlitter, renderer: &mut eframe::egui_wgpu::Renderer) {
 
         if let Ok((path, img)) = self.rx.try_recv() {
@@ -26,7 +26,7 @@ pub enum ViewportTab {
 
 impl App {
 
-    pub fn render_viewport(&mut self, ui: &mut egui::Ui, device: &wgpu::Device, format: wgpu::TextureFormat, resources: &mut TerrainRenderResources) {
+    pub fn render_viewport(&mut self, ui: &mut egui::Ui, device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, resources: &mut TerrainRenderResources) {
 
         egui::TopBottomPanel::top("viewport_options")
             .frame(egui::Frame::none().fill(ui.visuals().window_fill).inner_margin(4.0))
@@ -47,7 +47,7 @@ impl App {
             .show_inside(ui, |ui| {
                 match self.viewport_tab {
                     ViewportTab::Terrain => self.render_terrain_viewport(ui, device, resources),
-                    ViewportTab::Biomes => self.render_biomes_viewport(ui, device, format, resources),
+                    ViewportTab::Biomes => self.render_biomes_viewport(ui, device, queue, format, resources),
                 }
             });
 
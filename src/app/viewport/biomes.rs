@@ -1,15 +1,13 @@
 
 use eframe::wgpu;
 
-use crate::{app::App, biome::BIOME_DEPTH_LIMIT, compiler::biomes::compile_biome_preview, terrain::biome_preview::BiomePreviewRenderer};
+use crate::{app::App, biome::{BiomeParamKind, BIOME_DEPTH_LIMIT}, compiler::biomes::{biome_inspect_len, compile_biome_preview}, terrain::biome_preview::{BiomePreviewRenderer, BiomePreviewView}};
 
 use super::TerrainRenderResources;
 
 struct RenderCallback {
-    apsect: f32,
-    depth: f32,
+    view: BiomePreviewView
 }
-
 impl eframe::egui_wgpu::CallbackTrait for RenderCallback {
 
     fn prepare(
@@ -21,7 +19,7 @@ impl eframe::egui_wgpu::CallbackTrait for RenderCallback {
             callback_resources: &mut eframe::egui_wgpu::CallbackResources,
         ) -> Vec<eframe::wgpu::CommandBuffer> {
             let TerrainRenderResources { biome_preview_renderer, .. } = callback_resources.get().unwrap();
-            biome_preview_renderer.prepare(queue, self.apsect, self.depth);
+            biome_preview_renderer.prepare(queue, &self.view);
             vec![]
     }
 
@@ -39,9 +37,16 @@ impl eframe::egui_wgpu::CallbackTrait for RenderCallback {
 
 }
 
+fn color_swatch(ui: &mut egui::Ui, color: [f32; 3]) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+    // the preview shader writes biome colors to the target as they are
+    let color = egui::Color32::from_rgb((color[0] * 255.0) as u8, (color[1] * 255.0) as u8, (color[2] * 255.0) as u8);
+    ui.painter().rect_filled(rect, 2.0, color);
+}
+
 impl App {
 
-    pub fn render_biomes_viewport(&mut self, ui: &mut egui::Ui, device: &wgpu::Device, format: wgpu::TextureFormat, resources: &mut TerrainRenderResources) {
+    pub fn render_biomes_viewport(&mut self, ui: &mut egui::Ui, device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, resources: &mut TerrainRenderResources) {
 
         let min_depth = self.project.biomes.biomes.iter().map(|biome| biome.min_depth).min().unwrap_or(-BIOME_DEPTH_LIMIT).max(-200) as f32; 
         let max_depth = self.project.biomes.biomes.iter().map(|biome| biome.max_depth).max().unwrap_or(BIOME_DEPTH_LIMIT).min(200) as f32; 
@@ -52,26 +57,128 @@ impl App {
                 ui.horizontal(|ui| {
                     ui.label("Biome preview depth: ");
                     ui.add(egui::Slider::new(&mut self.biome_preview_depth, min_depth..=max_depth));
+                    if ui.button("Reset View").clicked() {
+                        self.biome_preview_center = glam::Vec2::ZERO;
+                        self.biome_preview_scale = 1000.0;
+                    }
                 });
             });
 
         let biome_preview_code = compile_biome_preview(&self.project.biomes);
         if biome_preview_code != self.prev_biome_preview_code {
-            resources.biome_preview_renderer = BiomePreviewRenderer::new(device, format, biome_preview_code.clone());
+            resources.biome_preview_renderer = BiomePreviewRenderer::new(device, format, biome_preview_code.clone(), biome_inspect_len(&self.project.biomes));
             self.prev_biome_preview_code = biome_preview_code;
         }
 
-        let (rect, _resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+        let (rect, resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+
+        // world units per screen point, the same along both axes
+        let units_per_point = 2.0 * self.biome_preview_scale / rect.height();
+        let to_world = |center: glam::Vec2, units_per_point: f32, pos: egui::Pos2| {
+            let offset = pos - rect.center();
+            center + glam::vec2(offset.x, -offset.y) * units_per_point
+        };
+
+        if resp.dragged() {
+            let delta = resp.drag_delta();
+            self.biome_preview_center += glam::vec2(-delta.x, delta.y) * units_per_point;
+        }
+        if let Some(hover_pos) = resp.hover_pos() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                // zoom around the cursor
+                let before = to_world(self.biome_preview_center, units_per_point, hover_pos);
+                self.biome_preview_scale = (self.biome_preview_scale * (-scroll * 0.002).exp()).clamp(1.0, 100000.0);
+                let after = to_world(self.biome_preview_center, 2.0 * self.biome_preview_scale / rect.height(), hover_pos);
+                self.biome_preview_center += before - after;
+            }
+        }
+        let units_per_point = 2.0 * self.biome_preview_scale / rect.height();
 
         let cb = eframe::egui_wgpu::Callback::new_paint_callback(
             rect,
             RenderCallback {
-                apsect: rect.aspect_ratio(),
-                depth: self.biome_preview_depth
+                view: BiomePreviewView {
+                    center: self.biome_preview_center,
+                    scale: self.biome_preview_scale,
+                    aspect: rect.aspect_ratio(),
+                    depth: self.biome_preview_depth
+                }
             }
         );
         ui.painter().add(cb);
 
+        // legend
+        ui.allocate_ui_at_rect(rect.shrink(8.0), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for biome in &self.project.biomes.biomes {
+                    ui.horizontal(|ui| {
+                        color_swatch(ui, biome.color);
+                        ui.label(&biome.name);
+                    });
+                }
+            });
+        });
+
+        // scale bar, the longest 1, 2 or 5 times a power of ten that fits in about 120 points
+        let target = 120.0 * units_per_point;
+        let magnitude = 10.0f32.powf(target.log10().floor());
+        let length = [5.0, 2.0, 1.0].into_iter().map(|mult| mult * magnitude).find(|length| *length <= target).unwrap_or(magnitude);
+        let bar_width = length / units_per_point;
+        let painter = ui.painter_at(rect);
+        let bar_start = rect.left_bottom() + egui::vec2(16.0, -16.0);
+        let bar_end = bar_start + egui::vec2(bar_width, 0.0);
+        painter.rect_filled(egui::Rect::from_min_max(bar_start + egui::vec2(-8.0, -28.0), bar_end + egui::vec2(8.0, 8.0)), 4.0, egui::Color32::from_black_alpha(160));
+        let stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+        painter.line_segment([bar_start, bar_end], stroke);
+        painter.line_segment([bar_start, bar_start - egui::vec2(0.0, 6.0)], stroke);
+        painter.line_segment([bar_end, bar_end - egui::vec2(0.0, 6.0)], stroke);
+        painter.text(bar_start.lerp(bar_end, 0.5) - egui::vec2(0.0, 8.0), egui::Align2::CENTER_BOTTOM, format!("{} units", length), egui::FontId::proportional(12.0), egui::Color32::WHITE);
+
+        // hover inspection
+        let Some(hover_pos) = resp.hover_pos() else { return; };
+        let world = to_world(self.biome_preview_center, units_per_point, hover_pos);
+        let pos = glam::vec3(world.x, self.biome_preview_depth, world.y);
+        let renderer = &mut resources.biome_preview_renderer;
+        renderer.inspect(device, queue, pos);
+        if renderer.inspecting() {
+            ui.ctx().request_repaint();
+        }
+        let Some((_, values)) = renderer.inspect_result() else { return; };
+        let biomes = &self.project.biomes;
+        resp.on_hover_ui_at_pointer(|ui| {
+            ui.label(format!("x: {:.1}, y: {:.1}, z: {:.1}", pos.x, pos.y, pos.z));
+            ui.separator();
+            for (idx, biome) in biomes.biomes.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    color_swatch(ui, biome.color);
+                    ui.label(format!("{}: {:.4}", biome.name, values.get(idx).copied().unwrap_or(0.0)));
+                });
+            }
+            if !biomes.biome_params.is_empty() {
+                ui.separator();
+            }
+            for (idx, param) in biomes.biome_params.iter().enumerate() {
+                let offset = biomes.biomes.len() + 3 * idx;
+                let val = [0, 1, 2].map(|comp| values.get(offset + comp).copied().unwrap_or(0.0));
+                ui.horizontal(|ui| {
+                    match param.kind {
+                        BiomeParamKind::Scalar => {
+                            ui.label(format!("{}: {:.3}", param.name, val[0]));
+                        },
+                        BiomeParamKind::Vector => {
+                            ui.label(format!("{}: ({:.3}, {:.3}, {:.3})", param.name, val[0], val[1], val[2]));
+                        },
+                        BiomeParamKind::Color => {
+                            ui.label(format!("{}:", param.name));
+                            color_swatch(ui, val);
+                            ui.label(format!("({:.3}, {:.3}, {:.3})", val[0], val[1], val[2]));
+                        },
+                    }
+                });
+            }
+        });
+
     }
 
 }
@@ -4,7 +4,10 @@ use std::sync::{Arc, Mutex};
 use eframe::wgpu;
 use egui::Pos2;
 
-use crate::{app::{viewport::TerrainRenderResources, App}, compiler::{compile, CompilationTarget}};
+use crate::{app::{viewport::TerrainRenderResources, App}, compiler::{compile, CompilationTarget}, terrain::{meshgen::{Mesher, MeshSettings, CHUNK_CACHE_DIR, CHUNK_SIZES}, renderer::TerrainShading, TerrainView}};
+
+// Distant chunks have coarser voxels, so large views stay cheap
+const MAX_VIEW_SIZE: f32 = 32768.0;
 
 fn god_view_cam_pos(center: glam::Vec3, yaw: f32, pitch: f32, r: f32) -> glam::Vec3 {
     let yaw_vec = glam::vec3(yaw.cos(), 0.0, yaw.sin());
@@ -19,6 +22,10 @@ struct RenderCallback {
     cam_yaw: f32,
     cam_pitch: f32,
 
+    mesh_settings: MeshSettings,
+    shading: TerrainShading,
+    biome_colors: Vec<[f32; 3]>,
+
     tri_counter: Arc<Mutex<u64>>
 }
 
@@ -36,14 +43,24 @@ impl eframe::egui_wgpu::CallbackTrait for RenderCallback {
             let resources = callback_resources.get_mut::<TerrainRenderResources>().unwrap();
             let TerrainRenderResources { renderer, mesh_generator, texture_atlas, request_redraw, .. } = resources;
 
-            *request_redraw |= mesh_generator.generate(device, queue, &mut resources.terrain, self.god_center, self.god_size, &self.tri_counter);
+            if mesh_generator.settings != self.mesh_settings {
+                resources.terrain.clear();
+                mesh_generator.set_settings(device, self.mesh_settings);
+            }
 
             let cam_pos = god_view_cam_pos(self.god_center, self.cam_yaw, self.cam_pitch, self.god_size * 1.15);
+            let terrain_view = TerrainView {
+                center: self.god_center,
+                size: self.god_size,
+                cam_pos
+            };
+            *request_redraw |= mesh_generator.generate(device, queue, &mut resources.terrain, &terrain_view, &self.tri_counter);
             let view = glam::Mat4::look_at_rh(cam_pos, self.god_center, glam::Vec3::Y);
 
-            let proj = glam::Mat4::perspective_rh(f32::consts::PI / 2.0, self.aspect, 0.01, 1000.0); 
+            let proj = glam::Mat4::perspective_rh(f32::consts::PI / 2.0, self.aspect, self.god_size * 0.001, self.god_size * 4.0); 
             let cam_trans = proj * view; 
-            renderer.prepare(device, queue, cam_trans, self.god_center - glam::Vec3::splat(self.god_size / 2.0), self.god_center + glam::Vec3::splat(self.god_size / 2.0), &texture_atlas);
+            renderer.set_shading(self.shading, &self.biome_colors);
+            renderer.prepare(device, queue, &resources.terrain, cam_trans, &terrain_view, &texture_atlas);
 
             vec![]
     }
@@ -67,6 +84,7 @@ impl App {
     pub fn render_terrain_viewport(&mut self, ui: &mut egui::Ui, device: &wgpu::Device, resources: &mut TerrainRenderResources) {
 
         let mut regenerate_terrain = self.regenerate_on_update;
+        let mut clear_cache = false;
 
         egui::TopBottomPanel::bottom(ui.next_auto_id())
             .show_inside(ui, |ui| {
@@ -78,12 +96,73 @@ impl App {
                     ui.label("Regenerate on change: ");
                     ui.checkbox(&mut self.regenerate_on_update, "");
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Cache chunks on disk: ");
+                    ui.checkbox(&mut self.project.cache_chunks_on_disk, "");
+                    if ui.button("Clear Chunk Cache").clicked() {
+                        clear_cache = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Mesher: ");
+                    egui::ComboBox::new("terrain_mesher", "")
+                        .selected_text(self.project.meshing.mesher.label())
+                        .show_ui(ui, |ui| {
+                            for mesher in [Mesher::MarchingCubes, Mesher::SurfaceNets, Mesher::DualContouring] {
+                                ui.selectable_value(&mut self.project.meshing.mesher, mesher, mesher.label());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Chunk Size: ");
+                    egui::ComboBox::new("terrain_chunk_size", "")
+                        .selected_text(self.project.meshing.chunk_size.to_string())
+                        .show_ui(ui, |ui| {
+                            for chunk_size in CHUNK_SIZES {
+                                ui.selectable_value(&mut self.project.meshing.chunk_size, chunk_size, chunk_size.to_string());
+                            }
+                        });
+                    ui.label("Voxel Size: ");
+                    ui.add(egui::DragValue::new(&mut self.project.meshing.voxel_size).speed(0.01).range(0.05..=16.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Shading: ");
+                    egui::ComboBox::new("terrain_shading", "")
+                        .selected_text(self.terrain_shading.label())
+                        .show_ui(ui, |ui| {
+                            for shading in [TerrainShading::Textured, TerrainShading::BiomeColors, TerrainShading::BiomeWeight(0)] {
+                                let selected = std::mem::discriminant(&self.terrain_shading) == std::mem::discriminant(&shading);
+                                if ui.selectable_label(selected, shading.label()).clicked() && !selected {
+                                    self.terrain_shading = shading;
+                                }
+                            }
+                        });
+                    if let TerrainShading::BiomeWeight(biome) = &mut self.terrain_shading {
+                        let biomes = &self.project.biomes.biomes;
+                        egui::ComboBox::new("terrain_shading_biome", "")
+                            .selected_text(biomes.get(*biome).map(|biome| biome.name.as_str()).unwrap_or("Select..."))
+                            .show_ui(ui, |ui| {
+                                for (idx, other) in biomes.iter().enumerate() {
+                                    ui.selectable_value(biome, idx, &other.name);
+                                }
+                            });
+                    }
+                });
                 ui.label(format!("Triangles: {}", *self.tri_counter.lock().unwrap()));
+                let too_large = resources.terrain.too_large_chunks();
+                if too_large > 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, format!("{} chunks have too many triangles to show, try a smaller chunk size", too_large));
+                }
             });
 
-        let sdf_code = compile(&self.project.terrain_graph, &self.project.biomes, &self.texture_loader, 
+        let sdf_code = compile(&self.project.terrain_graph, &self.project.biomes, &self.texture_loader, &self.heightmap_loader, 
             CompilationTarget::WGSL, self.project_path.file_name().unwrap().to_str());
 
+        resources.mesh_generator.set_cache_dir(self.project.cache_chunks_on_disk.then(|| self.project_path.join(CHUNK_CACHE_DIR)));
+        if clear_cache {
+            resources.mesh_generator.clear_cache();
+        }
+
         if regenerate_terrain && self.prev_sdf_code != sdf_code {
             resources.terrain.clear();
             resources.mesh_generator.update_shaders(&device, &sdf_code);
@@ -107,14 +186,14 @@ impl App {
             let vert = horiz.cross(view_dir).normalize();
             if ui.input(|i| i.pointer.primary_down()) && contains_pointer {
                 let drag_delta = ui.input(|i| i.pointer.delta());
-                self.god_center += drag_delta.x * horiz * 0.5;
-                self.god_center += drag_delta.y * vert * 0.5;
+                self.god_center += drag_delta.x * horiz * self.god_size / 256.0;
+                self.god_center += drag_delta.y * vert * self.god_size / 256.0;
             }
         }
 
         if contains_pointer {
             self.god_size *= (ui.input(|i| i.smooth_scroll_delta.y) * 0.05).exp();
-            self.god_size = self.god_size.clamp(32.0, 640.0);
+            self.god_size = self.god_size.clamp(32.0 * self.project.meshing.voxel_size, MAX_VIEW_SIZE);
         }
 
         let cb = eframe::egui_wgpu::Callback::new_paint_callback(
@@ -125,6 +204,9 @@ impl App {
                 god_size: self.god_size,
                 cam_yaw: self.cam_yaw,
                 cam_pitch: self.cam_pitch,
+                mesh_settings: self.project.meshing,
+                shading: self.terrain_shading,
+                biome_colors: self.project.biomes.biomes.iter().map(|biome| biome.color).collect(),
                 tri_counter: self.tri_counter.clone()
             }
         );
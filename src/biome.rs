@@ -3,8 +3,159 @@ use std::{collections::HashMap, path::PathBuf};
 
 use serde_json::json;
 
+use crate::graph::TerrainGraph;
+
 pub const BIOME_DEPTH_LIMIT: i32 = 99999;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BiomeRuleSource {
+    // world space height of the surface
+    Height,
+    // angle of the surface in degrees, 0 is flat
+    Slope,
+    // one of the biome rule inputs of the Terrain Output node
+    Graph(usize)
+}
+
+impl BiomeRuleSource {
+
+    pub fn label(&self) -> String {
+        match self {
+            BiomeRuleSource::Height => "Height".to_owned(),
+            BiomeRuleSource::Slope => "Slope".to_owned(),
+            BiomeRuleSource::Graph(idx) => format!("Biome Rule {}", idx + 1),
+        }
+    }
+
+}
+
+// Restricts a biome to surfaces where the source is within min..max, fading out over `smoothness`.
+// Rules are evaluated on the generated surface, so they only affect materials.
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BiomeRule {
+    pub source: BiomeRuleSource,
+    pub min: f32,
+    pub max: f32,
+    pub smoothness: f32
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BiomeParamKind {
+    Scalar,
+    Vector,
+    // a vector edited as an rgb color
+    Color
+}
+
+impl BiomeParamKind {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BiomeParamKind::Scalar => "Scalar",
+            BiomeParamKind::Vector => "Vector",
+            BiomeParamKind::Color => "Color",
+        }
+    }
+
+    pub fn default_value(&self) -> BiomeParamValue {
+        match self {
+            BiomeParamKind::Scalar => BiomeParamValue::Scalar(0.0),
+            BiomeParamKind::Vector => BiomeParamValue::Vector([0.0; 3]),
+            BiomeParamKind::Color => BiomeParamValue::Vector([1.0; 3]),
+        }
+    }
+
+}
+
+// How the values of a parameter in neighbouring biomes are combined
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BiomeParamBlend {
+    // weighted average, with weights raised to the sharpness
+    Linear,
+    // each biome keeps its value until its weight drops below 1 / sharpness
+    Max,
+    Min,
+    // between linear (sharpness 0) and max (high sharpness)
+    SmoothMax,
+    // the value of the biome with the highest weight
+    Dominant
+}
+
+impl BiomeParamBlend {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BiomeParamBlend::Linear => "Linear",
+            BiomeParamBlend::Max => "Max",
+            BiomeParamBlend::Min => "Min",
+            BiomeParamBlend::SmoothMax => "Smooth Max",
+            BiomeParamBlend::Dominant => "Dominant",
+        }
+    }
+
+}
+
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BiomeParam {
+    pub name: String,
+    pub kind: BiomeParamKind,
+    pub blend: BiomeParamBlend,
+    pub sharpness: f32
+}
+
+impl BiomeParam {
+
+    pub fn new(name: String, kind: BiomeParamKind) -> Self {
+        Self {
+            name,
+            kind,
+            blend: BiomeParamBlend::Linear,
+            sharpness: 1.0
+        }
+    }
+
+}
+
+// Scalars are stored as plain numbers so older projects still load
+#[derive(Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum BiomeParamValue {
+    Scalar(f32),
+    Vector([f32; 3])
+}
+
+impl BiomeParamValue {
+
+    pub fn to_vector(self) -> [f32; 3] {
+        match self {
+            BiomeParamValue::Scalar(val) => [val; 3],
+            BiomeParamValue::Vector(vec) => vec,
+        }
+    }
+
+    pub fn to_scalar(self) -> f32 {
+        match self {
+            BiomeParamValue::Scalar(val) => val,
+            BiomeParamValue::Vector(vec) => vec[0],
+        }
+    }
+
+    // Converts values left over from a parameter of another kind
+    pub fn of_kind(self, kind: BiomeParamKind) -> Self {
+        match kind {
+            BiomeParamKind::Scalar => BiomeParamValue::Scalar(self.to_scalar()),
+            BiomeParamKind::Vector | BiomeParamKind::Color => BiomeParamValue::Vector(self.to_vector()),
+        }
+    }
+
+}
+
 pub struct Biome {
     pub name: String,
     pub frequency: f32,
@@ -12,22 +163,130 @@ pub struct Biome {
     pub color: [f32; 3],
     pub min_depth: i32,
     pub max_depth: i32,
-    pub params: HashMap<String, f32>,
+    pub params: HashMap<String, BiomeParamValue>,
+    // range covered on each climate axis, indexed like Biomes::climate_axes
+    pub climate: Vec<[f32; 2]>,
+    pub rules: Vec<BiomeRule>,
+    // replaces the project graph where the biome is present
+    pub graph: Option<TerrainGraph>,
 }
 
 impl Biome {
 
+    pub fn param(&self, param: &BiomeParam) -> BiomeParamValue {
+        self.params.get(&param.name).map(|val| val.of_kind(param.kind)).unwrap_or(param.kind.default_value())
+    }
+
     pub fn frequency(&self) -> f32 {
         self.frequency.max(0.01)
     }
 
+    // Biomes that don't specify a range on an axis cover all of it
+    pub fn climate_range(&self, axis_idx: usize, axis: &ClimateAxis) -> [f32; 2] {
+        self.climate.get(axis_idx).copied().unwrap_or([axis.min, axis.max])
+    }
+
+    pub fn fill_climate(&mut self, axes: &[ClimateAxis]) {
+        while self.climate.len() < axes.len() {
+            let axis = &axes[self.climate.len()];
+            self.climate.push([axis.min, axis.max]);
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BiomeDistribution {
+    // Voronoi cells randomly assigned to biomes according to their frequency
+    Voronoi,
+    // Biomes occupy regions of a space spanned by noise-driven climate axes
+    Climate
+}
+
+impl BiomeDistribution {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BiomeDistribution::Voronoi => "Voronoi Cells",
+            BiomeDistribution::Climate => "Climate",
+        }
+    }
+
+}
+
+// A climate value such as temperature, sampled from noise and mapped to the min..max range
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ClimateAxis {
+    pub name: String,
+    pub scale: f32,
+    pub min: f32,
+    pub max: f32
+}
+
+impl ClimateAxis {
+
+    pub fn default_axes() -> Vec<Self> {
+        vec![
+            ClimateAxis { name: "Temperature".to_owned(), scale: 1500.0, min: -10.0, max: 30.0 },
+            ClimateAxis { name: "Humidity".to_owned(), scale: 1000.0, min: 0.0, max: 100.0 },
+        ]
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BiomeAdjacencyKind {
+    // the biome never touches any of the others
+    Never,
+    // the biome only touches the others and itself
+    Only
+}
+
+impl BiomeAdjacencyKind {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BiomeAdjacencyKind::Never => "never touches",
+            BiomeAdjacencyKind::Only => "only touches",
+        }
+    }
+
+}
+
+// Constrains which biomes may border each other, e.g. "Beach only touches Ocean".
+// Biomes are referenced by index.
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BiomeAdjacency {
+    pub biome: usize,
+    pub kind: BiomeAdjacencyKind,
+    pub others: Vec<usize>
+}
+
+impl BiomeAdjacency {
+
+    fn allows(&self, a: usize, b: usize) -> bool {
+        match self.kind {
+            BiomeAdjacencyKind::Never => (self.biome != a || !self.others.contains(&b)) && (self.biome != b || !self.others.contains(&a)),
+            BiomeAdjacencyKind::Only => (self.biome != a || self.others.contains(&b)) && (self.biome != b || self.others.contains(&a)),
+        }
+    }
+
 }
 
 pub struct Biomes {
-    pub biome_params: Vec<String>,
+    pub biome_params: Vec<BiomeParam>,
     pub biomes: Vec<Biome>,
     pub biome_size: f32,
-    pub biome_blending: f32
+    pub biome_blending: f32,
+    // how much weight the biome rules have to leave before they fully apply
+    pub rule_falloff: f32,
+    pub distribution: BiomeDistribution,
+    pub climate_axes: Vec<ClimateAxis>,
+    pub adjacency: Vec<BiomeAdjacency>
 }
 
 impl Biomes {
@@ -40,20 +299,63 @@ impl Biomes {
                 frequency: 1.0,
                 texture: PathBuf::new(),
                 params: HashMap::new(),
+                climate: Vec::new(),
+                rules: Vec::new(),
+                graph: None,
                 min_depth: -BIOME_DEPTH_LIMIT,
                 max_depth: BIOME_DEPTH_LIMIT,
                 color: [1.0, 0.0, 0.0]
             }],
             biome_size: 500.0,
-            biome_blending: 0.25
+            biome_blending: 0.25,
+            rule_falloff: 0.25,
+            distribution: BiomeDistribution::Voronoi,
+            climate_axes: ClimateAxis::default_axes(),
+            adjacency: Vec::new()
         }
     }
 
-    pub fn add_parameter(&mut self, name: String) {
+    pub fn can_touch(&self, a: usize, b: usize) -> bool {
+        a == b || self.adjacency.iter().all(|rule| rule.allows(a, b))
+    }
+
+    // Removes a biome and the adjacency rules about it, shifting the indices of the later biomes.
+    // Returns the biome and the adjacency rules from before, to put back with insert_biome.
+    pub fn remove_biome(&mut self, idx: usize) -> (Biome, Vec<BiomeAdjacency>) {
+        let biome = self.biomes.remove(idx);
+        let old_adjacency = self.adjacency.clone();
+        self.adjacency.retain_mut(|rule| {
+            if rule.biome == idx {
+                return false;
+            }
+            let had_others = !rule.others.is_empty();
+            rule.others.retain(|other| *other != idx);
+            for other in &mut rule.others {
+                if *other > idx {
+                    *other -= 1;
+                }
+            }
+            if rule.biome > idx {
+                rule.biome -= 1;
+            }
+            // a rule that was only about the removed biome is gone too
+            !had_others || !rule.others.is_empty()
+        });
+        (biome, old_adjacency)
+    }
+
+    pub fn insert_biome(&mut self, idx: usize, biome: Biome, adjacency: Vec<BiomeAdjacency>) {
+        self.biomes.insert(idx, biome);
+        self.adjacency = adjacency;
+    }
+
+    // Returns whether the parameter was added, it needs a name
+    pub fn add_parameter(&mut self, name: String, kind: BiomeParamKind) -> bool {
         if name.is_empty() {
-            return;
+            return false;
         }
-        self.biome_params.push(name);
+        self.biome_params.push(BiomeParam::new(name, kind));
+        true
     }
 
     pub fn to_json(&self) -> serde_json::Value {
@@ -61,10 +363,17 @@ impl Biomes {
             "params": self.biome_params,
             "size": self.biome_size,
             "blending": self.biome_blending,
+            "rule_falloff": self.rule_falloff,
+            "distribution": self.distribution,
+            "climate_axes": self.climate_axes,
+            "adjacency": self.adjacency,
             "biomes": serde_json::Value::Array(self.biomes.iter().map(|biome| json!({
                 "name": biome.name,
                 "frequency": biome.frequency,
                 "params": biome.params,
+                "climate": biome.climate,
+                "rules": biome.rules,
+                "graph": biome.graph.as_ref().map(|graph| graph.to_json()),
                 "color": biome.color,
                 "texture": biome.texture,
                 "min_depth": biome.min_depth,
@@ -75,9 +384,17 @@ impl Biomes {
 
     pub fn from_json(data: &serde_json::Value) -> Option<Self> {
         let data = data.as_object()?;
-        let params: Vec<_> = data.get("params")?.as_array()?.iter().filter_map(|param| param.as_str()).map(|param| param.to_owned()).collect();
+        // parameters used to be plain names of scalar parameters
+        let params: Vec<BiomeParam> = data.get("params")?.as_array()?.iter().filter_map(|param| match param.as_str() {
+            Some(name) => Some(BiomeParam::new(name.to_owned(), BiomeParamKind::Scalar)),
+            None => serde_json::from_value(param.clone()).ok()
+        }).collect();
         let biome_size = data.get("size").map(|val| val.as_f64()).flatten().unwrap_or(500.0) as f32;
         let biome_blending = data.get("blending").map(|val| val.as_f64()).flatten().unwrap_or(0.25) as f32;
+        let rule_falloff = data.get("rule_falloff").and_then(|val| val.as_f64()).unwrap_or(0.25) as f32;
+        let distribution = data.get("distribution").and_then(|val| serde_json::from_value(val.clone()).ok()).unwrap_or(BiomeDistribution::Voronoi);
+        let climate_axes = data.get("climate_axes").and_then(|val| serde_json::from_value(val.clone()).ok()).unwrap_or(ClimateAxis::default_axes());
+        let adjacency = data.get("adjacency").and_then(|val| serde_json::from_value(val.clone()).ok()).unwrap_or(Vec::new());
         
         let mut biomes = vec![];
         for biome_data in data.get("biomes")?.as_array()? {
@@ -91,16 +408,22 @@ impl Biomes {
                 color[2] = color_data.get(2)?.as_f64()? as f32;
             }
             let texture = biome_data.get("texture").map(|texture| texture.as_str()).flatten().map(|str| str.into()).unwrap_or(PathBuf::new());
+            let climate = biome_data.get("climate").and_then(|climate| serde_json::from_value(climate.clone()).ok()).unwrap_or(Vec::new());
+            let rules = biome_data.get("rules").and_then(|rules| serde_json::from_value(rules.clone()).ok()).unwrap_or(Vec::new());
+            let graph = biome_data.get("graph").and_then(TerrainGraph::from_json);
             biomes.push(Biome {
                 name: biome_data.get("name")?.as_str()?.to_owned(),
                 frequency: biome_data.get("frequency").map(|freq| freq.as_f64()).flatten().unwrap_or(1.0) as f32,
                 color, 
                 texture,
+                climate,
+                rules,
+                graph,
                 min_depth: biome_data.get("min_depth").map(|min_depth| min_depth.as_i64()).flatten().unwrap_or(-BIOME_DEPTH_LIMIT as i64) as i32, 
                 max_depth: biome_data.get("max_depth").map(|max_depth| max_depth.as_i64()).flatten().unwrap_or(BIOME_DEPTH_LIMIT as i64) as i32,
                 params: params.iter().map(|param| (
-                    param.clone(), 
-                    biome_params.get(param).map(|val| val.as_f64()).flatten().unwrap_or(0.0) as f32
+                    param.name.clone(), 
+                    biome_params.get(&param.name).and_then(|val| serde_json::from_value::<BiomeParamValue>(val.clone()).ok()).map(|val| val.of_kind(param.kind)).unwrap_or(param.kind.default_value())
                 )).collect(),
             });
         }
@@ -109,7 +432,11 @@ impl Biomes {
             biome_params: params,
             biomes,
             biome_size,
-            biome_blending
+            biome_blending,
+            rule_falloff,
+            distribution,
+            climate_axes,
+            adjacency
         })
     }
 
@@ -0,0 +1,83 @@
+
+use std::path::PathBuf;
+
+use eframe::wgpu;
+
+use crate::{app::{heightmap_loader::HeightmapLoader, texture_loader::TextureLoader}, biome::Biomes, compiler::{compile, CompilationTarget}, graph::TerrainGraph, project::Project, terrain::{map_export::{HeightFormat, MapBiome, MapData, MapRegion, MAX_MAP_RESOLUTION}, meshgen::MeshSettings}};
+
+const EXPORT_MAPS_USAGE: &str = "usage: VoxelWeaver export-maps <project dir> <output dir> [--region <min x> <min z> <width> <depth>] [--resolution <width> <height>] [--heights <min> <max>] [--step <step>] [--raw-height] [--cpu]";
+
+// VoxelWeaver export-maps <project dir> <output dir> [options]
+pub fn export_maps(args: &[String]) -> Result<(), String> {
+    let (Some(proj_path), Some(out_path)) = (args.first(), args.get(1)) else {
+        return Err(EXPORT_MAPS_USAGE.to_owned());
+    };
+    let proj_path = PathBuf::from(proj_path);
+    let out_path = PathBuf::from(out_path);
+
+    let mut region = MapRegion::default();
+    let mut height_format = HeightFormat::Png16;
+    let mut use_cpu = false;
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        let mut num = || -> Result<f32, String> {
+            rest.next().and_then(|val| val.parse().ok()).ok_or_else(|| format!("expected a number after {}", arg))
+        };
+        match arg.as_str() {
+            "--region" => {
+                region.min = [num()?, num()?];
+                region.size = [num()?, num()?];
+            },
+            "--resolution" => region.resolution = [num()? as u32, num()? as u32].map(|res| res.min(MAX_MAP_RESOLUTION)),
+            "--heights" => {
+                region.min_height = num()?;
+                region.max_height = num()?;
+            },
+            "--step" => region.step = num()?,
+            "--raw-height" => height_format = HeightFormat::RawF32,
+            "--cpu" => use_cpu = true,
+            _ => return Err(format!("unknown option {}\n{}", arg, EXPORT_MAPS_USAGE))
+        }
+    }
+    if region.size[0] <= 0.0 || region.size[1] <= 0.0 || region.resolution[0] == 0 || region.resolution[1] == 0 {
+        return Err("region size and resolution must be positive".to_owned());
+    }
+    if region.min_height >= region.max_height || region.step <= 0.0 {
+        return Err("height range is empty".to_owned());
+    }
+
+    let file = std::fs::File::open(proj_path.join("project.terrain")).map_err(|err| format!("failed to open project: {}", err))?;
+    let data = serde_json::from_reader::<_, serde_json::Value>(file).map_err(|err| format!("failed to read project: {}", err))?;
+    let mut project = Project {
+        terrain_graph: TerrainGraph::new(),
+        biomes: Biomes::new(),
+        meshing: MeshSettings::default(),
+        cache_chunks_on_disk: false
+    };
+    project.load_from_json(data);
+
+    let heightmap_loader = HeightmapLoader::load_now(proj_path.clone(), project.heightmaps());
+    let texture_loader = TextureLoader::without_textures();
+    let code = compile(&project.terrain_graph, &project.biomes, &texture_loader, &heightmap_loader, CompilationTarget::WGSL, proj_path.file_name().and_then(|name| name.to_str()));
+
+    let n_biomes = project.biomes.biomes.len();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = if use_cpu { None } else { pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())) };
+    let map = match adapter {
+        Some(adapter) => {
+            let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).map_err(|err| format!("failed to create device: {}", err))?;
+            MapData::evaluate(&device, &queue, &code, heightmap_loader.heightmaps(), n_biomes, &region)
+        },
+        None => {
+            if !use_cpu {
+                eprintln!("no GPU adapter found, evaluating on the CPU");
+            }
+            eprintln!("CPU evaluation is slow, large maps may take a while");
+            MapData::evaluate_cpu(&code, heightmap_loader.heightmaps(), n_biomes, &region)?
+        }
+    };
+    std::fs::create_dir_all(&out_path).map_err(|err| format!("failed to create output directory: {}", err))?;
+    map.save(&out_path, &MapBiome::from_biomes(&project.biomes), height_format).map_err(|err| format!("failed to write maps: {}", err))?;
+
+    Ok(())
+}
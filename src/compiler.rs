@@ -2,11 +2,16 @@
 pub mod biomes;
 
 mod graph;
-use biomes::{compile_biome_distribution, compile_biome_parameters};
+use biomes::{compile_biome_distribution, compile_biome_parameters, compile_biome_rules};
 use graph::compile_graph;
-use std::fmt::Write;
+use std::{fmt::Write, path::PathBuf};
 
-use crate::{app::texture_loader::TextureLoader, biome::Biomes, graph::{GraphProjectInfo, TerrainGraph, Value}};
+use crate::{app::{heightmap_loader::HeightmapLoader, texture_loader::TextureLoader}, biome::Biomes, graph::{node_types::heightmap::heightmap_texture_name, GraphProjectInfo, TerrainGraph, Value}, project::{referenced_heightmaps, referenced_layer_textures}, terrain::{meshgen::MeshSettings, MATERIAL_SLOTS}};
+
+// Number of material layer inputs on the Terrain Output node
+pub const MATERIAL_LAYERS: usize = 4;
+// Number of scalar inputs on the Terrain Output node that biome rules can read
+pub const BIOME_RULE_INPUTS: usize = 2;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum CompilationTarget {
@@ -28,19 +33,36 @@ impl CompilationTarget {
             CompilationTarget::WGSL => concat!(
                 include_str!("compiler/common.wgsl"),
                 include_str!("compiler/fnl.wgsl"),
+                include_str!("compiler/heightmap.wgsl"),
             ).to_owned() + format!("
 const N_BIOMES = {0}u;
+const MATERIAL_LAYERS = {1}u;
+const MATERIAL_SLOTS = {2}u;
+const BIOME_RULE_INPUTS = {3}u;
 struct TerrainOutput {{
     terrain: Terrain,
-    biome_w: array<f32, {0}>
-}}\n", biomes.biomes.len()).as_str(),
+    biome_w: array<f32, {0}>,
+    layer_w: array<f32, {1}>,
+    layer_tex: array<u32, {1}>,
+    rule_values: array<f32, {3}>
+}}\n", biomes.biomes.len(), MATERIAL_LAYERS, MATERIAL_SLOTS, BIOME_RULE_INPUTS).as_str(),
             CompilationTarget::UnrealHLSL => {
                 format!("
 #define N_BIOMES<ProjectName> {}
+#define MATERIAL_LAYERS<ProjectName> {}
+#define MATERIAL_SLOTS<ProjectName> {}
+#define BIOME_RULE_INPUTS<ProjectName> {}
 struct BiomeWeights<ProjectName> {{
     float w[N_BIOMES<ProjectName>];
 }};
 
+struct TerrainOutput<ProjectName> {{
+    Terrain terrain;
+    float layer_w[MATERIAL_LAYERS<ProjectName>];
+    int layer_tex[MATERIAL_LAYERS<ProjectName>];
+    float rule_values[BIOME_RULE_INPUTS<ProjectName>];
+}};
+
 BiomeWeights<ProjectName> lerp_biome_w<ProjectName>(BiomeWeights<ProjectName> a, BiomeWeights<ProjectName> b, float w) {{
     BiomeWeights<ProjectName> result;
     for(int i = 0; i < N_BIOMES<ProjectName>; i++) {{
@@ -49,7 +71,7 @@ BiomeWeights<ProjectName> lerp_biome_w<ProjectName>(BiomeWeights<ProjectName> a,
     return result;
 }}
 
-", biomes.biomes.len()) + "#pragma once\n"
+", biomes.biomes.len(), MATERIAL_LAYERS, MATERIAL_SLOTS, BIOME_RULE_INPUTS) + "#pragma once\n"
             }
         }
     }
@@ -82,55 +104,162 @@ impl Value {
 }
 
 fn compile_texture_reducer(out: &mut String, biomes: &Biomes, textures: &TextureLoader) {
-    let _ = writeln!(out, "fn reduce_material(biome_w_: array<f32, {}>) -> ReducedMaterial {{", biomes.biomes.len());
-    let _ = writeln!(out, "\tvar biome_w = biome_w_;");
-
-    match biomes.biomes.len() {
-        0 => {
-            let _ = writeln!(out, "\treturn ReducedMaterial(0, 0, 0.0);");
-        },
-        1 => {
-            let slot = textures.get(&biomes.biomes[0].texture);
-            let _ = writeln!(out, "\treturn ReducedMaterial({0}, {0}, 0.0);", slot);
-        },
-        2 => {
-            let slot0 = textures.get(&biomes.biomes[0].texture);
-            let slot1 = textures.get(&biomes.biomes[1].texture);
-            let _ = writeln!(out, "\treturn ReducedMaterial({}, {}, biome_w[1] / (biome_w[0] + biome_w[1]));", slot0, slot1);
-        },
-        n_biomes => {
-            let _ = writeln!(out, "\tvar tex0 = 0;");
-            let _ = writeln!(out, "\tvar tex1 = 1;");
-            let _ = writeln!(out, "\tif biome_w[1] > biome_w[0] {{");
-            let _ = writeln!(out, "\t\ttex0 = 1;");
-            let _ = writeln!(out, "\t\ttex1 = 0;");
-            let _ = writeln!(out, "\t}}");
-            let _ = writeln!(out, "\tfor(var i = 2; i < {}; i++) {{", n_biomes);
-            let _ = writeln!(out, "\t\tif biome_w[i] > biome_w[tex0] {{");
-            let _ = writeln!(out, "\t\t\ttex1 = tex0;");
-            let _ = writeln!(out, "\t\t\ttex0 = i;");
-            let _ = writeln!(out, "\t\t}} else if biome_w[i] > biome_w[tex1] {{");
-            let _ = writeln!(out, "\t\t\ttex1 = i;");
-            let _ = writeln!(out, "\t\t}}");
-            let _ = writeln!(out, "\t}}");
-            let _ = writeln!(out, "\tlet tex_w = biome_w[tex1] / (biome_w[tex0] + biome_w[tex1]);");
-            let _ = write!(out, "\tvar tex_slot_map = array(");
-            for biome in &biomes.biomes {
-                let _ = write!(out, "{}u, ", textures.get(&biome.texture));
+    let _ = writeln!(out, "fn reduce_material(terrain_out_: TerrainOutput, pos: vec3<f32>, norm: vec3<f32>) -> ReducedMaterial {{");
+    let _ = writeln!(out, "\tvar terrain_out = terrain_out_;");
+    let _ = writeln!(out, "\tvar biome_w = apply_biome_rules(terrain_out.biome_w, terrain_out.rule_values, pos.y - terrain_out.terrain.sdf, norm);");
+    let _ = writeln!(out, "\tvar material: ReducedMaterial;");
+
+    // biomes sharing a texture end up in the same slot
+    for (idx, biome) in biomes.biomes.iter().enumerate() {
+        let _ = writeln!(out, "\tmaterial = add_material(material, {}u, biome_w[{}]);", textures.get(&biome.texture), idx);
+    }
+
+    // material layers from the graph are blended over the biome textures
+    let _ = writeln!(out, "\tfor(var i = 0u; i < MATERIAL_LAYERS; i++) {{");
+    let _ = writeln!(out, "\t\tmaterial = apply_material_layer(material, terrain_out.layer_tex[i], terrain_out.layer_w[i]);");
+    let _ = writeln!(out, "\t}}");
+    let _ = writeln!(out, "\treturn normalize_material(material);");
+
+    let _ = writeln!(out, "}}");
+
+    // the heaviest biomes, using the texture slots for biome ids
+    let _ = writeln!(out, "fn reduce_biomes(terrain_out_: TerrainOutput, pos: vec3<f32>, norm: vec3<f32>) -> ReducedMaterial {{");
+    let _ = writeln!(out, "\tvar terrain_out = terrain_out_;");
+    let _ = writeln!(out, "\tvar biome_w = apply_biome_rules(terrain_out.biome_w, terrain_out.rule_values, pos.y - terrain_out.terrain.sdf, norm);");
+    let _ = writeln!(out, "\tvar biomes: ReducedMaterial;");
+    for idx in 0..biomes.biomes.len() {
+        let _ = writeln!(out, "\tbiomes = add_material(biomes, {}u, biome_w[{}]);", idx, idx);
+    }
+    let _ = writeln!(out, "\treturn normalize_material(biomes);");
+    let _ = writeln!(out, "}}");
+}
+
+// Each biome graph gets its own sdf function, only called where the biome is present
+fn compile_biome_graphs(out: &mut String, biomes: &Biomes, target: CompilationTarget, info: &GraphProjectInfo) {
+    for (idx, biome) in biomes.biomes.iter().enumerate() {
+        let Some(graph) = &biome.graph else { continue; };
+
+        match target {
+            CompilationTarget::WGSL => {
+                let _ = writeln!(out, "fn biome_sdf{}(pos: vec3<f32>, seed: i32, biome_w: array<f32, {}>) -> TerrainOutput {{", idx, biomes.biomes.len());
+                let _ = writeln!(out, "\tvar terrain_out: TerrainOutput;");
+                let _ = writeln!(out, "\tterrain_out.terrain.sdf = 1.0;");
+            },
+            CompilationTarget::UnrealHLSL => {
+                let _ = writeln!(out, "TerrainOutput<ProjectName> biome_sdf{}<ProjectName>(float3 pos, int seed, BiomeWeights<ProjectName> biome_w) {{", idx);
+                let _ = writeln!(out, "\tTerrainOutput<ProjectName> terrain_out;");
+                let _ = writeln!(out, "\tterrain_out.terrain = make_terrain(1.0);");
+                let _ = writeln!(out, "\tfor(int i = 0; i < MATERIAL_LAYERS<ProjectName>; i++) {{");
+                let _ = writeln!(out, "\t\tterrain_out.layer_w[i] = 0.0;");
+                let _ = writeln!(out, "\t\tterrain_out.layer_tex[i] = 0;");
+                let _ = writeln!(out, "\t}}");
+                let _ = writeln!(out, "\tfor(int i = 0; i < BIOME_RULE_INPUTS<ProjectName>; i++) {{");
+                let _ = writeln!(out, "\t\tterrain_out.rule_values[i] = 0.0;");
+                let _ = writeln!(out, "\t}}");
             }
-            let _ = writeln!(out, ");");
-            let _ = writeln!(out, "\treturn ReducedMaterial(tex_slot_map[tex0], tex_slot_map[tex1], tex_w);");
         }
+        compile_biome_parameters(out, biomes, target);
+        compile_graph(out, graph, target, info);
+        let _ = writeln!(out, "\treturn terrain_out;");
+        let _ = writeln!(out, "}}\n");
+    }
+}
+
+// Blends the outputs of the biome graphs over the project graph by biome weight.
+// The weights are clamped and normalized first, since smoothed distributions can go slightly negative.
+// Layer textures can't be blended, each layer takes the texture of the graph with the strongest mask there.
+fn compile_biome_graph_blend(out: &mut String, biomes: &Biomes, target: CompilationTarget) {
+    let graph_biomes: Vec<usize> = biomes.biomes.iter().enumerate().filter(|(_, biome)| biome.graph.is_some()).map(|(idx, _)| idx).collect();
+    if graph_biomes.is_empty() {
+        return;
     }
 
-    let _ = writeln!(out, "}}");
+    let (var, biome_w, layers_loop, rules_loop) = match target {
+        CompilationTarget::WGSL => ("var", "biome_w", "for(var i = 0u; i < MATERIAL_LAYERS; i++)", "for(var i = 0u; i < BIOME_RULE_INPUTS; i++)"),
+        CompilationTarget::UnrealHLSL => ("float", "biome_w.w", "for(int i = 0; i < MATERIAL_LAYERS<ProjectName>; i++)", "for(int i = 0; i < BIOME_RULE_INPUTS<ProjectName>; i++)"),
+    };
+    let clamped_sum = |idxs: &[usize]| idxs.iter().map(|idx| format!("max({}[{}], 0.0)", biome_w, idx)).collect::<Vec<_>>().join(" + ");
+    let all_biomes: Vec<usize> = (0..biomes.biomes.len()).collect();
+    let _ = writeln!(out, "\t{} biome_w_sum = max({}, 0.000001);", var, clamped_sum(&all_biomes));
+    let graph_w = clamped_sum(&graph_biomes);
+    let _ = writeln!(out, "\t{} biome_graph_w = ({}) / biome_w_sum;", var, graph_w);
+    let _ = writeln!(out, "\tterrain_out.terrain.sdf *= 1.0 - biome_graph_w;");
+    let _ = match target {
+        CompilationTarget::WGSL => writeln!(out, "\tvar layer_tex_w: array<f32, MATERIAL_LAYERS>;"),
+        CompilationTarget::UnrealHLSL => writeln!(out, "\tfloat layer_tex_w[MATERIAL_LAYERS<ProjectName>];"),
+    };
+    let _ = writeln!(out, "\t{} {{", layers_loop);
+    let _ = writeln!(out, "\t\tterrain_out.layer_w[i] *= 1.0 - biome_graph_w;");
+    let _ = writeln!(out, "\t\tlayer_tex_w[i] = terrain_out.layer_w[i];");
+    let _ = writeln!(out, "\t}}");
+    let _ = writeln!(out, "\t{} {{", rules_loop);
+    let _ = writeln!(out, "\t\tterrain_out.rule_values[i] *= 1.0 - biome_graph_w;");
+    let _ = writeln!(out, "\t}}");
+
+    for idx in graph_biomes {
+        let _ = writeln!(out, "\tif ({}[{}] > 0.0) {{", biome_w, idx);
+        let _ = match target {
+            CompilationTarget::WGSL => writeln!(out, "\t\tvar biome_out = biome_sdf{}(pos, seed, biome_w);", idx),
+            CompilationTarget::UnrealHLSL => writeln!(out, "\t\tTerrainOutput<ProjectName> biome_out = biome_sdf{}<ProjectName>(pos, seed, biome_w);", idx),
+        };
+        let _ = writeln!(out, "\t\t{} w = {}[{}] / biome_w_sum;", var, biome_w, idx);
+        let _ = writeln!(out, "\t\tterrain_out.terrain.sdf += w * biome_out.terrain.sdf;");
+        let _ = writeln!(out, "\t\t{} {{", layers_loop);
+        let _ = writeln!(out, "\t\t\tterrain_out.layer_w[i] += w * biome_out.layer_w[i];");
+        let _ = writeln!(out, "\t\t\tif (w * biome_out.layer_w[i] > layer_tex_w[i]) {{");
+        let _ = writeln!(out, "\t\t\t\tlayer_tex_w[i] = w * biome_out.layer_w[i];");
+        let _ = writeln!(out, "\t\t\t\tterrain_out.layer_tex[i] = biome_out.layer_tex[i];");
+        let _ = writeln!(out, "\t\t\t}}");
+        let _ = writeln!(out, "\t\t}}");
+        let _ = writeln!(out, "\t\t{} {{", rules_loop);
+        let _ = writeln!(out, "\t\t\tterrain_out.rule_values[i] += w * biome_out.rule_values[i];");
+        let _ = writeln!(out, "\t\t}}");
+        let _ = writeln!(out, "\t}}");
+    }
 }
 
-pub fn compile(graph: &TerrainGraph, biomes: &Biomes, textures: &TextureLoader, target: CompilationTarget, project_name : Option<&str>) -> String {
+// The Unreal material indices of the layer textures, after the biomes' indices
+fn compile_layer_textures(out: &mut String, layer_textures: &[PathBuf]) {
+    let _ = writeln!(out, "// Material layer textures by Unreal material index");
+    for (idx, path) in layer_textures.iter().enumerate() {
+        let _ = writeln!(out, "// N_BIOMES + {}: {}", idx, path.to_string_lossy());
+    }
+    let _ = writeln!(out, "#define N_LAYER_TEXTURES<ProjectName> {}", layer_textures.len());
+    let _ = writeln!(out);
+}
+
+fn compile_heightmap_textures(out: &mut String, graph: &TerrainGraph, biomes: &Biomes) {
+    for path in referenced_heightmaps(graph, biomes) {
+        let name = heightmap_texture_name(&path);
+        let _ = writeln!(out, "// {}", path.to_string_lossy());
+        let _ = writeln!(out, "Texture2D {}<ProjectName>;", name);
+        let _ = writeln!(out, "SamplerState {}Sampler<ProjectName>;", name);
+    }
+    let _ = writeln!(out);
+}
+
+pub fn compile(graph: &TerrainGraph, biomes: &Biomes, textures: &TextureLoader, heightmaps: &HeightmapLoader, target: CompilationTarget, project_name : Option<&str>) -> String {
     let mut out = target.preamble(biomes);
 
+    let layer_textures: Vec<PathBuf> = referenced_layer_textures(graph, biomes).into_iter().collect();
+    if target == CompilationTarget::UnrealHLSL {
+        compile_heightmap_textures(&mut out, graph, biomes);
+        compile_layer_textures(&mut out, &layer_textures);
+    }
+
     compile_biome_distribution(&mut out, biomes, target);
 
+    let heightmap_sizes: Vec<[u32; 2]> = heightmaps.heightmaps().iter().map(|heightmap| [heightmap.width, heightmap.height]).collect();
+    let info = GraphProjectInfo {
+        biomes,
+        heightmaps: &heightmaps.paths,
+        heightmap_sizes: &heightmap_sizes,
+        heightmap_files: &heightmaps.files,
+        layer_textures: &layer_textures,
+        textures
+    };
+    compile_biome_graphs(&mut out, biomes, target, &info);
+
     let _ = writeln!(out, "{}", match target {
         CompilationTarget::WGSL => include_str!("compiler/preamble.wgsl"),
         CompilationTarget::UnrealHLSL => include_str!("compiler/preamble.ush"),
@@ -143,12 +272,14 @@ pub fn compile(graph: &TerrainGraph, biomes: &Biomes, textures: &TextureLoader,
 
     compile_biome_parameters(&mut out, biomes, target);
 
-    compile_graph(&mut out, graph, target, &GraphProjectInfo {
-        biomes,
-    });
+    compile_graph(&mut out, graph, target, &info);
+
+    compile_biome_graph_blend(&mut out, biomes, target);
 
     out.push_str(&target.postamble());
 
+    compile_biome_rules(&mut out, biomes, target);
+
     if target == CompilationTarget::WGSL {
         compile_texture_reducer(&mut out, biomes, textures);
         out = out.replace("<ProjectName>", "");
@@ -160,3 +291,25 @@ pub fn compile(graph: &TerrainGraph, biomes: &Biomes, textures: &TextureLoader,
 
     out
 }
+
+// Tells the Unreal side how the project was set up to be meshed. Goes before the compiled sdf, whose normals use VOXEL_SIZE.
+pub fn compile_unreal_mesh_settings(settings: &MeshSettings, project_name: Option<&str>) -> String {
+    format!("
+// 0 = marching cubes, 1 = surface nets, 2 = dual contouring. The Unreal mesher picks its algorithm by it, so its meshes match the app's.
+// Meshes share vertices between triangles. Vertex normals come from the gradient, sdf_normal{0}, which also places dual contouring vertices.
+#define MESHER{0} {1}
+// Voxels along each axis of a chunk at the finest lod. Each coarser lod doubles the voxel size, not the voxel count.
+#define CHUNK_SIZE{0} {2}
+// World units between voxels at the finest lod. The sdf is sampled at Unreal positions, so this is in Unreal units.
+#define VOXEL_SIZE{0} {3:?}
+
+// Voxel size and chunk size at a lod, chunks of each lod tile the world from the origin
+float LodVoxelSize{0}(int lod) {{
+    return VOXEL_SIZE{0} * float(1 << lod);
+}}
+
+float LodChunkSize{0}(int lod) {{
+    return CHUNK_SIZE{0} * LodVoxelSize{0}(lod);
+}}
+", project_name.unwrap_or("DefaultProject"), settings.mesher.id(), settings.chunk_size, settings.voxel_size)
+}
@@ -1,7 +1,9 @@
 
 use std::fmt::Write;
 
-use crate::biome::Biomes;
+use crate::{biome::{Biome, BiomeDistribution, BiomeParamBlend, BiomeParamKind, BiomeRuleSource, Biomes}, terrain::MATERIAL_SLOTS};
+
+use super::BIOME_RULE_INPUTS;
 
 use super::CompilationTarget;
 
@@ -40,6 +42,13 @@ fn vec2_typename(target: CompilationTarget) -> &'static str {
     }
 }
 
+fn vec3_typename(target: CompilationTarget) -> &'static str {
+    match target {
+        CompilationTarget::WGSL => "vec3",
+        CompilationTarget::UnrealHLSL => "float3",
+    }
+}
+
 fn mix_fn_name(target: CompilationTarget) -> &'static str {
     match target {
         CompilationTarget::WGSL => "mix",
@@ -54,7 +63,187 @@ fn fract_fn_name(target: CompilationTarget) -> &'static str {
     }
 }
 
-fn compile_biome_distribution_layer(out: &mut String, biomes: &Biomes, min_depth: i32, max_depth: i32, target: CompilationTarget) {
+fn seed_vec(target: CompilationTarget) -> &'static str {
+    match target {
+        CompilationTarget::WGSL => "vec2(f32(seed), f32(seed))",
+        CompilationTarget::UnrealHLSL => "float2(seed, seed)",
+    }
+}
+
+fn biome_thresholds(biomes_in_layer: &[(usize, &Biome)]) -> Vec<(usize, f32, f32)> {
+    let total_biome_freq: f32 = biomes_in_layer.iter().map(|(_, biome)| biome.frequency()).sum(); 
+    let mut curr_biome_min = 0.0;
+    let mut thresholds = Vec::new();
+    for (biome_idx, biome) in biomes_in_layer {
+        let min_biome = curr_biome_min / total_biome_freq;
+        let max_biome = (curr_biome_min + biome.frequency()) / total_biome_freq;
+        curr_biome_min += biome.frequency();
+        thresholds.push((*biome_idx, min_biome, max_biome));
+    }
+    thresholds
+}
+
+fn biomes_in_layer(biomes: &Biomes, min_depth: i32, max_depth: i32) -> Vec<(usize, &Biome)> {
+    let mut biomes_in_layer = Vec::new();
+    for (idx, biome) in biomes.biomes.iter().enumerate() {
+        let intersection_min = biome.min_depth.max(min_depth);
+        let intersection_max = biome.max_depth.min(max_depth);
+        if intersection_max <= intersection_min {
+            continue;
+        }
+        biomes_in_layer.push((idx, biome));
+    }
+    biomes_in_layer
+}
+
+// Packs Biomes::can_touch into a bitmask indexed by a * N_BIOMES + b
+fn compile_biome_adjacency(out: &mut String, biomes: &Biomes, target: CompilationTarget) {
+
+    let n = biomes.biomes.len();
+    let mut words = vec![0u32; (n * n).div_ceil(32)];
+    for a in 0..n {
+        for b in 0..n {
+            if biomes.can_touch(a, b) {
+                words[(a * n + b) / 32] |= 1 << ((a * n + b) % 32);
+            }
+        }
+    }
+    let words = words.iter().map(|word| format!("{}u", word)).collect::<Vec<_>>().join(", ");
+
+    match target {
+        CompilationTarget::WGSL => {
+            let _ = writeln!(out, "fn biome_can_touch(a: i32, b: i32) -> bool {{");
+            let _ = writeln!(out, "	if (a < 0 || b < 0) {{");
+            let _ = writeln!(out, "		return true;");
+            let _ = writeln!(out, "	}}");
+            let _ = writeln!(out, "	var adjacency = array<u32, {}>({});", (n * n).div_ceil(32), words);
+            let _ = writeln!(out, "	let idx = u32(a * {} + b);", n);
+        },
+        CompilationTarget::UnrealHLSL => {
+            let _ = writeln!(out, "bool biome_can_touch<ProjectName>(int a, int b) {{");
+            let _ = writeln!(out, "	if (a < 0 || b < 0) {{");
+            let _ = writeln!(out, "		return true;");
+            let _ = writeln!(out, "	}}");
+            let _ = writeln!(out, "	uint adjacency[{}] = {{ {} }};", (n * n).div_ceil(32), words);
+            let _ = writeln!(out, "	uint idx = (uint)(a * {} + b);", n);
+        }
+    }
+    let _ = writeln!(out, "	return ((adjacency[idx / 32u] >> (idx % 32u)) & 1u) != 0u;");
+    let _ = writeln!(out, "}}\n");
+
+}
+
+const BIOME_REROLLS: usize = 4;
+
+// Deterministic per-cell biome assignment for a layer. A cell rerolls its biome when the initial roll 
+// of one of its neighbours is a biome it can't touch. Neighbours reroll independently, so rare violations remain.
+fn compile_biome_cell(out: &mut String, layer: usize, biomes_in_layer: &[(usize, &Biome)], target: CompilationTarget) {
+
+    match target {
+        CompilationTarget::WGSL => { let _ = writeln!(out, "fn biome_roll{}(seed: i32, cell: vec2<f32>, attempt: i32) -> i32 {{", layer); },
+        CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "int biome_roll{}<ProjectName>(int seed, float2 cell, int attempt) {{", layer); }
+    }
+    let _ = writeln!(out, "	{0} roll = hash2(cell + {1} + {2}(attempt) * {3}(37.0, 17.0)).x;", f32_var_decl(target), seed_vec(target), f32_typename(target), vec2_typename(target));
+    for (biome_idx, min_biome, max_biome) in biome_thresholds(biomes_in_layer) {
+        let _ = writeln!(out, "	if (roll >= {} && roll < {}) {{", min_biome, max_biome);
+        let _ = writeln!(out, "		return {};", biome_idx);
+        let _ = writeln!(out, "	}}");
+    }
+    let _ = writeln!(out, "	return -1;");
+    let _ = writeln!(out, "}}\n");
+
+    match target {
+        CompilationTarget::WGSL => { let _ = writeln!(out, "fn biome_cell{}(seed: i32, cell: vec2<f32>) -> i32 {{", layer); },
+        CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "int biome_cell{}<ProjectName>(int seed, float2 cell) {{", layer); }
+    }
+    let _ = writeln!(out, "	for ({} attempt = 0; attempt < {}; attempt++) {{", i32_var_decl(target), BIOME_REROLLS);
+    let _ = writeln!(out, "		{} biome = biome_roll{}<ProjectName>(seed, cell, attempt);", i32_var_decl(target), layer);
+    let _ = writeln!(out, "		{} allowed = true;", match target {
+        CompilationTarget::WGSL => "var",
+        CompilationTarget::UnrealHLSL => "bool",
+    });
+    let _ = writeln!(out, "		for ({} i = -1; i <= 1; i++) {{", i32_var_decl(target));
+    let _ = writeln!(out, "			for ({} j = -1; j <= 1; j++) {{", i32_var_decl(target));
+    let _ = writeln!(out, "				if ((i != 0 || j != 0) && !biome_can_touch<ProjectName>(biome, biome_roll{0}<ProjectName>(seed, cell + {1}({2}(i), {2}(j)), 0))) {{", layer, vec2_typename(target), f32_typename(target));
+    let _ = writeln!(out, "					allowed = false;");
+    let _ = writeln!(out, "				}}");
+    let _ = writeln!(out, "			}}");
+    let _ = writeln!(out, "		}}");
+    let _ = writeln!(out, "		if (allowed) {{");
+    let _ = writeln!(out, "			return biome;");
+    let _ = writeln!(out, "		}}");
+    let _ = writeln!(out, "	}}");
+    let _ = writeln!(out, "	return biome_roll{}<ProjectName>(seed, cell, 0);", layer);
+    let _ = writeln!(out, "}}\n");
+
+}
+
+fn compile_voronoi_distribution(out: &mut String, biomes: &Biomes, layer: usize, biomes_in_layer: &[(usize, &Biome)], target: CompilationTarget) {
+
+    // https://www.shadertoy.com/view/ldB3zc
+    let _ = writeln!(out, "\t{} biome_scl = {};", f32_var_decl(target), 1.0 / biomes.biome_size); 
+    let _ = writeln!(out, "\t{} biome_smoothing = {};", f32_var_decl(target), biomes.biome_blending); 
+    let _ = writeln!(out, "\t{0} biome_uv = {1}(pos.x * biome_scl, pos.z * biome_scl);", vec2_var_decl(target), vec2_typename(target)); 
+    let _ = writeln!(out, "\t{} biome_uv_n = floor(biome_uv);", vec2_var_decl(target)); 
+    let _ = writeln!(out, "\t{} biome_uv_f = {}(biome_uv);", vec2_var_decl(target), fract_fn_name(target)); 
+    let _ = writeln!(out, "\t{} dist = 8.0;", f32_var_decl(target));
+    let _ = writeln!(out, "\tfor({} i = -2; i <= 2; i++) {{", i32_var_decl(target));
+    let _ = writeln!(out, "\t\tfor({} j = -2; j <= 2; j++) {{", i32_var_decl(target));
+    let _ = writeln!(out, "\t\t\t{0} g = {1}({2}(i), {2}(j));", vec2_var_decl(target), vec2_typename(target), f32_typename(target));
+    let _ = writeln!(out, "\t\t\t{} o = hash2(biome_uv_n + g);", vec2_var_decl(target));
+    let _ = writeln!(out, "\t\t\t{} d = length(g - biome_uv_f + o);", f32_var_decl(target));
+    if biomes.adjacency.is_empty() {
+        let _ = writeln!(out, "\t\t\t{} biome = hash2(biome_uv_n + g + {}).x;", f32_var_decl(target), seed_vec(target));
+    } else {
+        let _ = writeln!(out, "\t\t\t{} biome = biome_cell{}<ProjectName>(seed, biome_uv_n + g);", i32_var_decl(target), layer);
+    }
+    let _ = writeln!(out, "\t\t\t{} h = smoothstep(-1.0, 1.0, (dist - d) / biome_smoothing);", f32_var_decl(target));
+    let _ = writeln!(out, "\t\t\tdist = {}(dist, d, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", mix_fn_name(target));
+
+    for (biome_idx, min_biome, max_biome) in biome_thresholds(biomes_in_layer) {
+        if biomes.adjacency.is_empty() {
+            let _ = writeln!(out, "\t\t\tif (biome >= {} && biome < {}) {{", min_biome, max_biome);
+        } else {
+            let _ = writeln!(out, "\t\t\tif (biome == {}) {{", biome_idx);
+        }
+        let _ = writeln!(out, "\t\t\t\tbiome_w[{0}] = {1}(biome_w[{0}], 1.0, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", biome_idx, mix_fn_name(target));
+        let _ = writeln!(out, "\t\t\t}} else {{");
+        let _ = writeln!(out, "\t\t\t\tbiome_w[{0}] = {1}(biome_w[{0}], 0.0, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", biome_idx, mix_fn_name(target));
+        let _ = writeln!(out, "\t\t\t}}");
+    }
+    let _ = writeln!(out, "\t\t}}");
+    let _ = writeln!(out, "\t}}");
+
+}
+
+fn compile_climate_distribution(out: &mut String, biomes: &Biomes, biomes_in_layer: &[(usize, &Biome)], target: CompilationTarget) {
+
+    for (axis_idx, axis) in biomes.climate_axes.iter().enumerate() {
+        let _ = writeln!(out, "\t{} climate_{} = {}({:?}, {:?}, clamp(noise01(seed + {}, {}(pos.x, 0.0, pos.z) / {:?}), 0.0, 1.0));", f32_var_decl(target), axis_idx, mix_fn_name(target), axis.min, axis.max, 1000 + axis_idx, vec3_typename(target), axis.scale.max(1.0));
+    }
+
+    // biomes fade out over a distance proportional to the blending, measured in fractions of the axes
+    let _ = writeln!(out, "\t{} biome_smoothing = {:?};", f32_var_decl(target), (biomes.biome_blending * 0.25).max(0.0001));
+    // -1 while no biome of the layer has been found
+    let _ = writeln!(out, "\t{} nearest_biome = -1;", i32_var_decl(target));
+    let _ = writeln!(out, "\t{} nearest_d = 1000000.0;", f32_var_decl(target));
+    for (biome_idx, biome) in biomes_in_layer {
+        let _ = write!(out, "\t{} biome_d{} = sqrt(0.0", f32_var_decl(target), biome_idx);
+        for (axis_idx, axis) in biomes.climate_axes.iter().enumerate() {
+            let [lo, hi] = biome.climate_range(axis_idx, axis);
+            let _ = write!(out, " + climate_distance(climate_{}, {:?}, {:?}, {:?})", axis_idx, lo, hi, axis.max - axis.min);
+        }
+        let _ = writeln!(out, ");");
+        let _ = writeln!(out, "\tbiome_w[{0}] = 1.0 - smoothstep(0.0, biome_smoothing, biome_d{0});", biome_idx);
+        let _ = writeln!(out, "\tif (biome_d{0} < nearest_d) {{", biome_idx);
+        let _ = writeln!(out, "\t\tnearest_d = biome_d{};", biome_idx);
+        let _ = writeln!(out, "\t\tnearest_biome = {};", biome_idx);
+        let _ = writeln!(out, "\t}}");
+    }
+
+}
+
+fn compile_biome_distribution_layer(out: &mut String, biomes: &Biomes, layer: usize, min_depth: i32, max_depth: i32, target: CompilationTarget) {
 
     match target {
         CompilationTarget::WGSL => { let _ = writeln!(out, "\tvar biome_w: array<f32, {}>;", biomes.biomes.len()); },
@@ -68,59 +257,27 @@ fn compile_biome_distribution_layer(out: &mut String, biomes: &Biomes, min_depth
     if biomes.biomes.len() == 1 {
         let _ = writeln!(out, "\tbiome_w[0] = 1.0;");
     } else {
-        
-        let mut biomes_in_layer = Vec::new();
-        for (idx, biome) in biomes.biomes.iter().enumerate() {
-            let intersection_min = biome.min_depth.max(min_depth);
-            let intersection_max = biome.max_depth.min(max_depth);
-            if intersection_max <= intersection_min {
-                continue;
-            }
-            biomes_in_layer.push((idx, biome));
-        }
-
-        // https://www.shadertoy.com/view/ldB3zc
-        let _ = writeln!(out, "\t{} biome_scl = {};", f32_var_decl(target), 1.0 / biomes.biome_size); 
-        let _ = writeln!(out, "\t{} biome_smoothing = {};", f32_var_decl(target), biomes.biome_blending); 
-        let _ = writeln!(out, "\t{0} biome_uv = {1}(pos.x * biome_scl, pos.z * biome_scl);", vec2_var_decl(target), vec2_typename(target)); 
-        let _ = writeln!(out, "\t{} biome_uv_n = floor(biome_uv);", vec2_var_decl(target)); 
-        let _ = writeln!(out, "\t{} biome_uv_f = {}(biome_uv);", vec2_var_decl(target), fract_fn_name(target)); 
-        let _ = writeln!(out, "\t{} dist = 8.0;", f32_var_decl(target));
-        let _ = writeln!(out, "\tfor({} i = -2; i <= 2; i++) {{", i32_var_decl(target));
-        let _ = writeln!(out, "\t\tfor({} j = -2; j <= 2; j++) {{", i32_var_decl(target));
-        let _ = writeln!(out, "\t\t\t{0} g = {1}({2}(i), {2}(j));", vec2_var_decl(target), vec2_typename(target), f32_typename(target));
-        let _ = writeln!(out, "\t\t\t{} o = hash2(biome_uv_n + g);", vec2_var_decl(target));
-        let _ = writeln!(out, "\t\t\t{} d = length(g - biome_uv_f + o);", f32_var_decl(target));
-        let seed_vec = match target {
-            CompilationTarget::WGSL => "vec2(f32(seed), f32(seed))",
-            CompilationTarget::UnrealHLSL => "float2(seed, seed)",
-        };
-        let _ = writeln!(out, "\t\t\t{} biome = hash2(biome_uv_n + g + {}).x;", f32_var_decl(target), seed_vec);
-        let _ = writeln!(out, "\t\t\t{} h = smoothstep(-1.0, 1.0, (dist - d) / biome_smoothing);", f32_var_decl(target));
-        let _ = writeln!(out, "\t\t\tdist = {}(dist, d, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", mix_fn_name(target));
 
-        let total_biome_freq: f32 = biomes_in_layer.iter().map(|(_, biome)| biome.frequency()).sum(); 
-        let mut curr_biome_min = 0.0;
-        for i in 0..biomes_in_layer.len() {
-            let min_biome = curr_biome_min / total_biome_freq;
-            let max_biome = (curr_biome_min + biomes_in_layer[i].1.frequency()) / total_biome_freq;
-            curr_biome_min += biomes_in_layer[i].1.frequency();
+        let biomes_in_layer = biomes_in_layer(biomes, min_depth, max_depth);
 
-            let _ = writeln!(out, "\t\t\tif (biome >= {} && biome < {}) {{", min_biome, max_biome);
-            let _ = writeln!(out, "\t\t\t\tbiome_w[{0}] = {1}(biome_w[{0}], 1.0, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", biomes_in_layer[i].0, mix_fn_name(target));
-            let _ = writeln!(out, "\t\t\t}} else {{");
-            let _ = writeln!(out, "\t\t\t\tbiome_w[{0}] = {1}(biome_w[{0}], 0.0, h) - h * (1.0 - h) * biome_smoothing / (1.0 + 3.0 * biome_smoothing);", biomes_in_layer[i].0, mix_fn_name(target));
-            let _ = writeln!(out, "\t\t\t}}");
+        match biomes.distribution {
+            BiomeDistribution::Voronoi => compile_voronoi_distribution(out, biomes, layer, &biomes_in_layer, target),
+            BiomeDistribution::Climate => compile_climate_distribution(out, biomes, &biomes_in_layer, target),
         }
-        let _ = writeln!(out, "\t\t}}");
-        let _ = writeln!(out, "\t}}");
 
         let _ = writeln!(out, "\t{} biome_w_sum = 0.0;", f32_var_decl(target));
         let _ = writeln!(out, "\tfor ({} i = 0; i < {}; i++) {{", i32_var_decl(target), biomes.biomes.len());
         let _ = writeln!(out, "\t\tbiome_w_sum += biome_w[i];");
         let _ = writeln!(out, "\t}}");
+        if biomes.distribution == BiomeDistribution::Climate {
+            // climates not covered by any biome go to the closest one, if the layer has any biomes
+            let _ = writeln!(out, "\tif (biome_w_sum <= 0.0 && nearest_biome >= 0) {{");
+            let _ = writeln!(out, "\t\tbiome_w[nearest_biome] = 1.0;");
+            let _ = writeln!(out, "\t\tbiome_w_sum = 1.0;");
+            let _ = writeln!(out, "\t}}");
+        }
         let _ = writeln!(out, "\tfor ({} i = 0; i < {}; i++) {{", i32_var_decl(target), biomes.biomes.len());
-        let _ = writeln!(out, "\t\tbiome_w[i] /= biome_w_sum;");
+        let _ = writeln!(out, "\t\tbiome_w[i] /= max(biome_w_sum, 0.000001);");
         let _ = writeln!(out, "\t}}");
     }
 
@@ -154,13 +311,12 @@ pub fn compile_biome_distribution(out: &mut String, biomes: &Biomes, target: Com
     }
     let depth_cuts = depth_cuts_deduplicated;
 
+    if !biomes.adjacency.is_empty() {
+        compile_biome_adjacency(out, biomes, target);
+    }
+
     // Per-layer biome distribution 
     for i in 0..=depth_cuts.len() {
-        match target {
-            CompilationTarget::WGSL => { let _ = writeln!(out, "fn biome_distribution{}(seed: i32, pos: vec3<f32>) -> array<f32, {}> {{", i, biomes.biomes.len()); },
-            CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "BiomeWeights<ProjectName> biome_distribution{}<ProjectName>(int seed, float3 pos) {{", i); }
-        }
-
         let (min_depth, max_depth) = if i == 0 {
             (i32::MIN, depth_cuts[0] + 1)
         } else if i == depth_cuts.len() {
@@ -169,7 +325,16 @@ pub fn compile_biome_distribution(out: &mut String, biomes: &Biomes, target: Com
             (depth_cuts[i - 1], depth_cuts[i])
         };
 
-        compile_biome_distribution_layer(out, biomes, min_depth, max_depth, target);
+        if !biomes.adjacency.is_empty() && biomes.distribution == BiomeDistribution::Voronoi && biomes.biomes.len() > 1 {
+            compile_biome_cell(out, i, &biomes_in_layer(biomes, min_depth, max_depth), target);
+        }
+
+        match target {
+            CompilationTarget::WGSL => { let _ = writeln!(out, "fn biome_distribution{}(seed: i32, pos: vec3<f32>) -> array<f32, {}> {{", i, biomes.biomes.len()); },
+            CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "BiomeWeights<ProjectName> biome_distribution{}<ProjectName>(int seed, float3 pos) {{", i); }
+        }
+
+        compile_biome_distribution_layer(out, biomes, i, min_depth, max_depth, target);
         let _ = writeln!(out, "}}\n");
     }
 
@@ -206,33 +371,175 @@ pub fn compile_biome_distribution(out: &mut String, biomes: &Biomes, target: Com
 
 }
 
+fn param_literal(val: [f32; 3], kind: BiomeParamKind, target: CompilationTarget) -> String {
+    match kind {
+        BiomeParamKind::Scalar => format!("{:?}", val[0]),
+        BiomeParamKind::Vector | BiomeParamKind::Color => format!("{}({:?}, {:?}, {:?})", vec3_typename(target), val[0], val[1], val[2]),
+    }
+}
+
 pub fn compile_biome_parameters(out: &mut String, biomes: &Biomes, target: CompilationTarget) {
 
-    for (param_idx, name) in biomes.biome_params.iter().enumerate() {
-        let _ = write!(out, "\t{} b_{} = ", f32_var_decl(target), param_idx);
-        for (biome_idx, biome) in biomes.biomes.iter().enumerate() {
-            if biome_idx > 0 {
-                let _ = write!(out, " + ");
+    let biome_w = match target {
+        CompilationTarget::WGSL => "biome_w",
+        CompilationTarget::UnrealHLSL => "biome_w.w",
+    };
+
+    for (param_idx, param) in biomes.biome_params.iter().enumerate() {
+        let decl = match (target, param.kind) {
+            (CompilationTarget::WGSL, _) => "var",
+            (CompilationTarget::UnrealHLSL, BiomeParamKind::Scalar) => "float",
+            (CompilationTarget::UnrealHLSL, _) => "float3",
+        };
+        let vals: Vec<[f32; 3]> = biomes.biomes.iter().map(|biome| biome.param(param).to_vector()).collect();
+        let lit = |val: [f32; 3]| param_literal(val, param.kind, target);
+
+        // per component bounds, so max and min blending can fade values out towards the other extreme
+        let mut lo = [f32::INFINITY; 3];
+        let mut hi = [f32::NEG_INFINITY; 3];
+        for val in &vals {
+            for c in 0..3 {
+                lo[c] = lo[c].min(val[c]);
+                hi[c] = hi[c].max(val[c]);
             }
-            let _ = write!(out, "biome_w{}[{}] * {}", match target {
-                CompilationTarget::WGSL => "",
-                CompilationTarget::UnrealHLSL => ".w",
-            }, biome_idx, biome.params.get(name).unwrap_or(&0.0));
-        } 
-        let _ = writeln!(out, ";");
+        }
+        let sharpness = param.sharpness.max(0.001);
+
+        match param.blend {
+            BiomeParamBlend::Linear if param.sharpness == 1.0 => {
+                let _ = write!(out, "\t{} b_{} = ", decl, param_idx);
+                for (biome_idx, val) in vals.iter().enumerate() {
+                    if biome_idx > 0 {
+                        let _ = write!(out, " + ");
+                    }
+                    let _ = write!(out, "{}[{}] * {}", biome_w, biome_idx, lit(*val));
+                } 
+                let _ = writeln!(out, ";");
+            },
+            BiomeParamBlend::Linear => {
+                let _ = writeln!(out, "\t{} b_{}_w_sum = 0.0;", f32_var_decl(target), param_idx);
+                let _ = writeln!(out, "\t{} b_{} = {};", decl, param_idx, lit([0.0; 3]));
+                for (biome_idx, val) in vals.iter().enumerate() {
+                    let _ = writeln!(out, "\t{{");
+                    let _ = writeln!(out, "\t\t{} w = pow(max({}[{}], 0.0), {:?});", f32_var_decl(target), biome_w, biome_idx, sharpness);
+                    let _ = writeln!(out, "\t\tb_{}_w_sum += w;", param_idx);
+                    let _ = writeln!(out, "\t\tb_{} += w * {};", param_idx, lit(*val));
+                    let _ = writeln!(out, "\t}}");
+                }
+                let _ = writeln!(out, "\tb_{0} /= max(b_{0}_w_sum, 0.000001);", param_idx);
+            },
+            BiomeParamBlend::Max | BiomeParamBlend::Min => {
+                let (func, base) = if param.blend == BiomeParamBlend::Max { ("max", lo) } else { ("min", hi) };
+                let _ = writeln!(out, "\t{} b_{} = {};", decl, param_idx, lit(base));
+                for (biome_idx, val) in vals.iter().enumerate() {
+                    let _ = writeln!(out, "\tb_{0} = {1}(b_{0}, {2}({3}, {4}, clamp({5}[{6}] * {7:?}, 0.0, 1.0)));", param_idx, func, mix_fn_name(target), lit(base), lit(*val), biome_w, biome_idx, sharpness);
+                }
+            },
+            BiomeParamBlend::SmoothMax => {
+                // log-sum-exp of the values normalized to 0..1. Smoothed weights can dip below 0, so they're clamped
+                // and divided by their sum, otherwise the result drops below every value where they don't add up to 1.
+                let range = [0, 1, 2].map(|c| (hi[c] - lo[c]).max(0.000001));
+                let _ = write!(out, "\t{} b_{}_w_sum = 0.0", f32_var_decl(target), param_idx);
+                for biome_idx in 0..vals.len() {
+                    let _ = write!(out, " + max({}[{}], 0.0)", biome_w, biome_idx);
+                }
+                let _ = writeln!(out, ";");
+                let _ = write!(out, "\t{} b_{} = {} + {} * log(max(", decl, param_idx, lit(lo), lit(range));
+                for (biome_idx, val) in vals.iter().enumerate() {
+                    if biome_idx > 0 {
+                        let _ = write!(out, " + ");
+                    }
+                    let normalized = [0, 1, 2].map(|c| (val[c] - lo[c]) / range[c]);
+                    let _ = write!(out, "max({}[{}], 0.0) * exp({:?} * {})", biome_w, biome_idx, sharpness, lit(normalized));
+                }
+                let _ = writeln!(out, ", {}) / max(b_{}_w_sum, 0.000001)) / {:?};", lit([0.000001; 3]), param_idx, sharpness);
+            },
+            BiomeParamBlend::Dominant => {
+                let _ = writeln!(out, "\t{} b_{} = {};", decl, param_idx, lit(vals[0]));
+                let _ = writeln!(out, "\t{} b_{}_w = {}[0];", f32_var_decl(target), param_idx, biome_w);
+                for (biome_idx, val) in vals.iter().enumerate().skip(1) {
+                    let _ = writeln!(out, "\tif ({}[{}] > b_{}_w) {{", biome_w, biome_idx, param_idx);
+                    let _ = writeln!(out, "\t\tb_{} = {};", param_idx, lit(*val));
+                    let _ = writeln!(out, "\t\tb_{}_w = {}[{}];", param_idx, biome_w, biome_idx);
+                    let _ = writeln!(out, "\t}}");
+                }
+            },
+        }
     }
 
 }
 
+// Biome rules depend on the surface, so they're applied to the biome weights of mesh vertices rather than inside sdf().
+pub fn compile_biome_rules(out: &mut String, biomes: &Biomes, target: CompilationTarget) {
+
+    let uses_slope = biomes.biomes.iter().any(|biome| biome.rules.iter().any(|rule| rule.source == BiomeRuleSource::Slope));
+
+    let biome_w = match target {
+        CompilationTarget::WGSL => {
+            let _ = writeln!(out, "fn apply_biome_rules(biome_w_: array<f32, {}>, rule_values_: array<f32, {}>, height: f32, norm: vec3<f32>) -> array<f32, {}> {{", biomes.biomes.len(), BIOME_RULE_INPUTS, biomes.biomes.len());
+            let _ = writeln!(out, "\tvar unruled = biome_w_;");
+            let _ = writeln!(out, "\tvar biome_w = biome_w_;");
+            let _ = writeln!(out, "\tvar rule_values = rule_values_;");
+            "biome_w"
+        },
+        CompilationTarget::UnrealHLSL => {
+            let _ = writeln!(out, "#define BIOME_SLOPE_RULES<ProjectName> {}", uses_slope as i32);
+            let _ = writeln!(out, "BiomeWeights<ProjectName> apply_biome_rules<ProjectName>(BiomeWeights<ProjectName> biome_w_, float rule_values[BIOME_RULE_INPUTS<ProjectName>], float height, float3 norm) {{");
+            let _ = writeln!(out, "\tBiomeWeights<ProjectName> unruled = biome_w_;");
+            let _ = writeln!(out, "\tBiomeWeights<ProjectName> biome_w = biome_w_;");
+            "biome_w.w"
+        }
+    };
+    let unruled = match target {
+        CompilationTarget::WGSL => "unruled",
+        CompilationTarget::UnrealHLSL => "unruled.w",
+    };
+    if uses_slope {
+        let _ = writeln!(out, "\t{} slope = degrees(acos(clamp(norm.y, -1.0, 1.0)));", f32_var_decl(target));
+    }
+
+    for (biome_idx, biome) in biomes.biomes.iter().enumerate() {
+        for rule in &biome.rules {
+            let source = match rule.source {
+                BiomeRuleSource::Height => "height".to_owned(),
+                BiomeRuleSource::Slope => "slope".to_owned(),
+                BiomeRuleSource::Graph(idx) => format!("rule_values[{}]", idx.min(BIOME_RULE_INPUTS - 1)),
+            };
+            let _ = writeln!(out, "\t{}[{}] *= biome_rule({}, {:?}, {:?}, {:?});", biome_w, biome_idx, source, rule.min, rule.max, rule.smoothness);
+        }
+    }
+
+    // the weights are renormalized, but where the rules leave little weight they fade back to the weights
+    // without rules over the falloff, so a barely present biome doesn't suddenly take over
+    let _ = writeln!(out, "\t{} rule_w_sum = 0.0;", f32_var_decl(target));
+    let _ = writeln!(out, "\tfor ({} i = 0; i < {}; i++) {{", i32_var_decl(target), biomes.biomes.len());
+    let _ = writeln!(out, "\t\trule_w_sum += {}[i];", biome_w);
+    let _ = writeln!(out, "\t}}");
+    let _ = writeln!(out, "\tif (rule_w_sum <= 0.0) {{");
+    let _ = writeln!(out, "\t\treturn biome_w_;");
+    let _ = writeln!(out, "\t}}");
+    let _ = writeln!(out, "\t{} rule_t = smoothstep(0.0, {:?}, rule_w_sum);", f32_var_decl(target), biomes.rule_falloff.max(0.0001));
+    let _ = writeln!(out, "\tfor ({} i = 0; i < {}; i++) {{", i32_var_decl(target), biomes.biomes.len());
+    let _ = writeln!(out, "\t\t{0}[i] = {1}({2}[i], {0}[i] / rule_w_sum, rule_t);", biome_w, mix_fn_name(target), unruled);
+    let _ = writeln!(out, "\t}}");
+    let _ = writeln!(out, "\treturn biome_w;");
+    let _ = writeln!(out, "}}\n");
+
+}
+
+pub fn biome_inspect_len(biomes: &Biomes) -> usize {
+    (biomes.biomes.len() + 3 * biomes.biome_params.len()).max(1)
+}
+
 pub fn compile_biome_preview(biomes: &Biomes) -> String {
 
-    let mut out = format!("const N_BIOMES = {}u;\n", biomes.biomes.len()); 
+    let mut out = format!("const N_BIOMES = {}u;\nconst MATERIAL_SLOTS = {}u;\n", biomes.biomes.len(), MATERIAL_SLOTS); 
 
     compile_biome_distribution(&mut out, biomes, CompilationTarget::WGSL); 
 
     let _ = writeln!(out, "fn preview_color(pos: vec3<f32>) -> vec3<f32> {{");
-    let _ = writeln!(out, "let biome_w = biome_distribution(666, pos);");
-    let _ = write!(&mut out, "\treturn ");
+    let _ = writeln!(out, "\tvar biome_w = biome_distribution(666, pos);");
+    let _ = write!(&mut out, "\tlet color = ");
 
     for (biome_idx, biome) in biomes.biomes.iter().enumerate() {
         if biome_idx > 0 {
@@ -242,6 +549,44 @@ pub fn compile_biome_preview(biomes: &Biomes) -> String {
     }
 
     let _ = writeln!(&mut out, ";");
+
+    // biomes that can't touch but blend into each other are striped, adjacency only applies to voronoi cells
+    if !biomes.adjacency.is_empty() && biomes.distribution == BiomeDistribution::Voronoi {
+        let _ = writeln!(&mut out, "\tvar violation = false;");
+        let _ = writeln!(&mut out, "\tfor (var a = 0; a < {}; a++) {{", biomes.biomes.len());
+        let _ = writeln!(&mut out, "\t\tfor (var b = a + 1; b < {}; b++) {{", biomes.biomes.len());
+        let _ = writeln!(&mut out, "\t\t\tif (biome_w[a] > 0.01 && biome_w[b] > 0.01 && !biome_can_touch(a, b)) {{");
+        let _ = writeln!(&mut out, "\t\t\t\tviolation = true;");
+        let _ = writeln!(&mut out, "\t\t\t}}");
+        let _ = writeln!(&mut out, "\t\t}}");
+        let _ = writeln!(&mut out, "\t}}");
+        let _ = writeln!(&mut out, "\tif (violation && fract((pos.x + pos.z) / 40.0) < 0.5) {{");
+        let _ = writeln!(&mut out, "\t\treturn mix(color, vec3(1.0, 0.0, 1.0), 0.75);");
+        let _ = writeln!(&mut out, "\t}}");
+    }
+    let _ = writeln!(&mut out, "\treturn color;");
+    let _ = writeln!(&mut out, "}}");
+
+    // biome weights followed by 3 floats per parameter, read back for hover inspection
+    let _ = writeln!(&mut out, "const INSPECT_LEN = {}u;", biome_inspect_len(biomes));
+    let _ = writeln!(&mut out, "fn preview_inspect(pos: vec3<f32>) -> array<f32, INSPECT_LEN> {{");
+    let _ = writeln!(&mut out, "\tvar values: array<f32, INSPECT_LEN>;");
+    let _ = writeln!(&mut out, "\tvar biome_w = biome_distribution(666, pos);");
+    compile_biome_parameters(&mut out, biomes, CompilationTarget::WGSL);
+    for biome_idx in 0..biomes.biomes.len() {
+        let _ = writeln!(&mut out, "\tvalues[{}] = biome_w[{}];", biome_idx, biome_idx);
+    }
+    for (param_idx, param) in biomes.biome_params.iter().enumerate() {
+        let val = match param.kind {
+            BiomeParamKind::Scalar => format!("vec3(b_{})", param_idx),
+            _ => format!("b_{}", param_idx),
+        };
+        let offset = biomes.biomes.len() + 3 * param_idx;
+        for comp in 0..3 {
+            let _ = writeln!(&mut out, "\tvalues[{}] = {}[{}];", offset + comp, val, comp);
+        }
+    }
+    let _ = writeln!(&mut out, "\treturn values;");
     let _ = writeln!(&mut out, "}}");
 
     (out + include_str!("common.wgsl") + include_str!("fnl.wgsl")).replace("<ProjectName>", "")
@@ -59,17 +59,57 @@ fn smoothmax(a: f32, b: f32, k: f32) -> f32 {
     return 0.5 * (a + b + sqrt(x * x + 4.0 * k * k));
 }
 
-fn make_terrain(sdf: f32) -> Terrain {
-    return Terrain(sdf);
+fn modulo(a: f32, b: f32) -> f32 {
+    return a - b * floor(a / b);
 }
 
-fn terrain_union(a: Terrain, b: Terrain) -> Terrain {
-    let sdf = smoothmin(a.sdf, b.sdf, 0.5);
-    return make_terrain(sdf);
+// CSG blend modes, k is the blend radius
+// https://iquilezles.org/articles/smin/ and https://mercury.sexy/hg_sdf/
+
+fn polysmoothmin(a: f32, b: f32, k_: f32) -> f32 {
+    let k = max(k_, 0.0001);
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+
+fn expsmoothmin(a: f32, b: f32, k_: f32) -> f32 {
+    let k = max(k_, 0.0001);
+    let m = min(a, b);
+    return m - k * log2(exp2((m - a) / k) + exp2((m - b) / k));
 }
 
-fn terrain_intersect(a: Terrain, b: Terrain) -> Terrain {
-    let sdf = smoothmax(a.sdf, b.sdf, 0.5);
+fn chamfermin(a: f32, b: f32, k: f32) -> f32 {
+    return min(min(a, b), (a + b - k) * sqrt(0.5));
+}
+
+fn stairsmin(a: f32, b: f32, k_: f32) -> f32 {
+    let k = max(k_, 0.0001);
+    let s = k / 4.0;
+    let u = b - k;
+    return min(min(a, b), 0.5 * (u + a + abs(modulo(u - a + s, 2.0 * s) - s)));
+}
+
+fn polysmoothmax(a: f32, b: f32, k: f32) -> f32 {
+    return -polysmoothmin(-a, -b, k);
+}
+
+fn expsmoothmax(a: f32, b: f32, k: f32) -> f32 {
+    return -expsmoothmin(-a, -b, k);
+}
+
+fn chamfermax(a: f32, b: f32, k: f32) -> f32 {
+    return -chamfermin(-a, -b, k);
+}
+
+fn stairsmax(a: f32, b: f32, k: f32) -> f32 {
+    return -stairsmin(-a, -b, k);
+}
+
+fn make_terrain(sdf: f32) -> Terrain {
+    return Terrain(sdf);
+}
+
+fn blend_terrains(a: Terrain, b: Terrain, sdf: f32) -> Terrain {
     return make_terrain(sdf);
 }
 
@@ -121,10 +161,220 @@ fn ridge_height(seed: i32, pos: vec3<f32>, min: f32, max: f32, scale: f32) -> f3
     return map01(1.0 - abs(noise(seed, calc_heightmap_coord(pos, scale))), min, max);
 }
 
+// Integer hash, https://www.jcgt.org/published/0009/03/02/
+fn pcg3d(v_: vec3<u32>) -> vec3<u32> {
+    var v = v_ * 1664525u + 1013904223u;
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v ^= v >> vec3(16u);
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    return v;
+}
+
+// Random values in [0, 1] that are constant over each integer cell
+fn hash_cell3(seed: i32, p: vec3<f32>) -> vec3<f32> {
+    let cell = bitcast<vec3<u32>>(vec3<i32>(floor(p)));
+    return vec3<f32>(pcg3d(cell ^ vec3(u32(seed) * 2654435769u))) / 4294967295.0;
+}
+
+fn hash_cell(seed: i32, p: vec3<f32>) -> f32 {
+    return hash_cell3(seed, p).x;
+}
+
+// Value noise in [0, 1]
+fn value_noise(seed: i32, p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(hash_cell(seed, i), hash_cell(seed, i + vec3(1.0, 0.0, 0.0)), u.x),
+            mix(hash_cell(seed, i + vec3(0.0, 1.0, 0.0)), hash_cell(seed, i + vec3(1.0, 1.0, 0.0)), u.x),
+            u.y
+        ),
+        mix(
+            mix(hash_cell(seed, i + vec3(0.0, 0.0, 1.0)), hash_cell(seed, i + vec3(1.0, 0.0, 1.0)), u.x),
+            mix(hash_cell(seed, i + vec3(0.0, 1.0, 1.0)), hash_cell(seed, i + vec3(1.0, 1.0, 1.0)), u.x),
+            u.y
+        ),
+        u.z
+    );
+}
+
+// Random value in [0, 1] for an integer lattice point
+fn hash12(seed: i32, p: vec2<f32>) -> f32 {
+    let cell = bitcast<vec2<u32>>(vec2<i32>(floor(p)));
+    return f32(pcg3d(vec3(cell, bitcast<u32>(seed))).x) / 4294967295.0;
+}
+
+// Value noise in [-1, 1] with its analytic derivative, returned as (value, d/dx, d/dy)
+// https://iquilezles.org/articles/morenoise/
+fn value_noise_d(seed: i32, p: vec2<f32>) -> vec3<f32> {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let du = 6.0 * f * (1.0 - f);
+
+    let a = 2.0 * hash12(seed, i) - 1.0;
+    let b = 2.0 * hash12(seed, i + vec2(1.0, 0.0)) - 1.0;
+    let c = 2.0 * hash12(seed, i + vec2(0.0, 1.0)) - 1.0;
+    let d = 2.0 * hash12(seed, i + vec2(1.0, 1.0)) - 1.0;
+
+    let k1 = b - a;
+    let k2 = c - a;
+    let k4 = a - b - c + d;
+    return vec3(
+        a + k1 * u.x + k2 * u.y + k4 * u.x * u.y,
+        du * vec2(k1 + k4 * u.y, k2 + k4 * u.x)
+    );
+}
+
+// fBm where each octave is attenuated by the slope accumulated so far, so that detail
+// only survives in flat areas and steep areas get smooth, eroded looking gullies.
+// https://iquilezles.org/articles/fbm/
+fn eroded_fbm(seed: i32, p: vec2<f32>, erosion: f32) -> f32 {
+    var q = p;
+    var amp = 1.0;
+    var total_amp = 0.0;
+    var total_noise = 0.0;
+    var d = vec2(0.0);
+    for (var i = 0; i < 6; i++) {
+        let n = value_noise_d(seed + i, q);
+        d += n.yz;
+        total_noise += amp * n.x / (1.0 + max(erosion, 0.0) * dot(d, d));
+        total_amp += amp;
+        amp *= 0.5;
+        q = 2.0 * vec2(0.8 * q.x - 0.6 * q.y, 0.6 * q.x + 0.8 * q.y);
+    }
+    return total_noise / total_amp;
+}
+
+fn eroded_height(seed: i32, pos: vec3<f32>, min: f32, max: f32, scale: f32, erosion: f32) -> f32 {
+    return map01(0.5 + 0.5 * eroded_fbm(seed, calc_heightmap_coord(pos, scale).xz, erosion), min, max);
+}
+
+// Pulls the sdf towards the average of its horizontal neighbours where the slope is above the talus angle (in degrees)
+fn terrain_thermal_erode(t: Terrain, px: f32, nx: f32, pz: f32, nz: f32, radius: f32, talus: f32, strength: f32) -> Terrain {
+    let grad = vec2(px - nx, pz - nz) / (2.0 * dezero(radius));
+    let slope = degrees(atan(length(grad)));
+    let w = smoothstep(talus, talus + 10.0, slope) * clamp(strength, 0.0, 1.0);
+    var result = t;
+    result.sdf = mix(t.sdf, 0.25 * (px + nx + pz + nz), w);
+    return result;
+}
+
+fn sd_segment_2d(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 0.0001), 0.0, 1.0);
+    return length(pa - ba * h);
+}
+
+// Approximate distance to the zero-crossings of a low frequency noise, which form a network of winding rivers
+fn river_noise_distance(seed: i32, pos: vec3<f32>, scale: f32) -> f32 {
+    let freq = 0.002 * scale;
+    let p = pos.xz * freq + 100.0;
+    let a = value_noise_d(seed + 31, p);
+    let b = value_noise_d(seed + 37, 2.0 * p);
+    let n = a.x + 0.35 * b.x;
+    let grad = (a.yz + 0.7 * b.yz) * freq;
+    return abs(n) / max(length(grad), 0.0001);
+}
+
+// 1 inside the channel, fading out to 0 over the banks
+fn river_mask(dist: f32, width: f32, smoothness: f32) -> f32 {
+    return 1.0 - smoothstep(0.5 * width, 0.5 * width + max(smoothness, 0.001), dist);
+}
+
+fn terrace(h: f32, step_size: f32, sharpness: f32) -> f32 {
+    let t = h / dezero(step_size);
+    let w = max(0.5 * (1.0 - clamp(sharpness, 0.0, 1.0)), 0.001);
+    return (floor(t) + smoothstep(0.5 - w, 0.5 + w, fract(t))) * step_size;
+}
+
+fn terrace_pos(pos: vec3<f32>, step_size: f32, sharpness: f32) -> vec3<f32> {
+    return vec3(pos.x, terrace(pos.y, step_size, sharpness), pos.z);
+}
+
+fn strata_pos(seed: i32, pos: vec3<f32>, thickness: f32, strength: f32, scale: f32) -> vec3<f32> {
+    let layer = floor(pos.y / dezero(thickness)) * 7.31;
+    let p = vec3(pos.x * scale * 0.01, layer, pos.z * scale * 0.01);
+    return pos + strength * vec3(noise(seed + 7, p), 0.0, noise(seed + 13, p));
+}
+
+fn overhang_pos(pos: vec3<f32>, dir: vec3<f32>, amount: f32, base: f32, height: f32) -> vec3<f32> {
+    let t = clamp((pos.y - base) / dezero(height), 0.0, 1.0);
+    return pos - vec3(dir.x, 0.0, dir.z) * amount * t * t;
+}
+
+// Squared distance of the climate value c outside of lo..hi, relative to the size of the axis
+fn climate_distance(c: f32, lo: f32, hi: f32, size: f32) -> f32 {
+    let d = max(max(lo - c, c - hi), 0.0) / dezero(size);
+    return d * d;
+}
+
+// 1 inside of lo..hi, fading out to 0 over `smoothness` outside of it
+fn biome_rule(v: f32, lo: f32, hi: f32, smoothness: f32) -> f32 {
+    let d = max(max(lo - v, v - hi), 0.0);
+    return 1.0 - smoothstep(0.0, max(smoothness, 0.0001), d);
+}
+
+// The MATERIAL_SLOTS textures with the highest weights at a vertex
 struct ReducedMaterial {
-    tex1: u32,
-    tex2: u32,
-    w: f32
+    tex: array<u32, MATERIAL_SLOTS>,
+    w: array<f32, MATERIAL_SLOTS>
+}
+
+// Adds weight `w` to texture `tex`, keeping the heaviest textures when the slots are full.
+fn add_material(mat: ReducedMaterial, tex: u32, w: f32) -> ReducedMaterial {
+    var result = mat;
+    var lightest = 0u;
+    for(var i = 0u; i < MATERIAL_SLOTS; i++) {
+        if result.tex[i] == tex {
+            result.w[i] += w;
+            return result;
+        }
+        if result.w[i] < result.w[lightest] {
+            lightest = i;
+        }
+    }
+    if w > result.w[lightest] {
+        result.tex[lightest] = tex;
+        result.w[lightest] = w;
+    }
+    return result;
+}
+
+// Blends a material layer with mask `m_` over `mat`. Slot 0 means the layer has no texture.
+fn apply_material_layer(mat: ReducedMaterial, tex: u32, m_: f32) -> ReducedMaterial {
+    let m = clamp(m_, 0.0, 1.0);
+    if tex == 0u || m <= 0.0 {
+        return mat;
+    }
+    var result = mat;
+    for(var i = 0u; i < MATERIAL_SLOTS; i++) {
+        result.w[i] *= 1.0 - m;
+    }
+    return add_material(result, tex, m);
+}
+
+fn normalize_material(mat: ReducedMaterial) -> ReducedMaterial {
+    var result = mat;
+    var sum = 0.0;
+    for(var i = 0u; i < MATERIAL_SLOTS; i++) {
+        sum += result.w[i];
+    }
+    if sum <= 0.0 {
+        result.w[0] = 1.0;
+        return result;
+    }
+    for(var i = 0u; i < MATERIAL_SLOTS; i++) {
+        result.w[i] /= sum;
+    }
+    return result;
 }
 
 fn lerp_biome_w(a_: array<f32, N_BIOMES>, b_: array<f32, N_BIOMES>, w: f32) -> array<f32, N_BIOMES> {
@@ -1,45 +1,244 @@
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
 
-use crate::graph::{graph_toposort, GraphProjectInfo, NodeId, TerrainGraph};
+use crate::graph::{graph_toposort, Branch, GraphProjectInfo, NodeId, Resample, TerrainGraph, Type};
 
 use super::CompilationTarget;
 
-pub fn compile_graph(out: &mut String, graph: &TerrainGraph, target: CompilationTarget, info: &GraphProjectInfo) {
-    
-    let Some(sorted_nodes) = graph_toposort(graph) else {
-        return;
-    };
+impl Type {
+
+    fn typename(&self, target: CompilationTarget) -> &'static str {
+        match (self, target) {
+            (Type::Scalar, CompilationTarget::WGSL) => "f32",
+            (Type::Scalar, CompilationTarget::UnrealHLSL) => "float",
+            (Type::Vector, CompilationTarget::WGSL) => "vec3<f32>",
+            (Type::Vector, CompilationTarget::UnrealHLSL) => "float3",
+            (Type::Terrain, _) => "Terrain",
+        }
+    }
+
+}
+
+struct GraphCompiler<'a> {
+    graph: &'a TerrainGraph,
+    sorted_nodes: Vec<NodeId>,
+    target: CompilationTarget,
+    info: &'a GraphProjectInfo<'a>,
+    curr_output_idx: u32
+}
+
+impl<'a> GraphCompiler<'a> {
+
+    fn compile_nodes(&mut self, out: &mut String, nodes: &[NodeId]) -> HashMap<(NodeId, u32), String> {
+
+        let needed = self.needed_nodes(nodes);
+
+        let mut output_names: HashMap<(NodeId, u32), String> = HashMap::new();
+        for node_id in nodes {
+            let node_id = *node_id;
+            if !needed.contains(&node_id) {
+                continue;
+            }
+            let node = &self.graph.nodes[&node_id];
+
+            let skipped_inputs = self.skipped_inputs(node_id);
+            let mut args = HashMap::new();
+            for (arg_name, _ty, inp) in node.ty.inputs() {
+                let val = match inp.connection {
+                    Some(connection) => match output_names.get(&connection) {
+                        Some(name) => name.clone(),
+                        // inputs only evaluated inside branches or resampled have no value out here
+                        None if skipped_inputs.contains(&arg_name) => continue,
+                        None => panic!("input {} was used before its upstream node was compiled", arg_name),
+                    },
+                    None => inp.val.to_string(self.target),
+                };
+
+                args.insert(arg_name, val);
+            }
+
+            for resample in node.ty.resample(&args, self.target) {
+                // sampling at the original position reuses the value computed out here
+                let val = if resample.pos == "pos" {
+                    args[resample.input].clone()
+                } else {
+                    self.compile_resample(out, node_id, &resample)
+                };
+                args.insert(resample.name, val);
+            }
+
+            if let Some(branch) = node.ty.branch() {
+                let val = self.compile_branch(out, node_id, &branch, &args);
+                args.insert(branch.name, val);
+            }
+
+            let mut out_varnames = HashMap::new();
+            for (out_idx, (out_name, _output_id)) in node.ty.outputs().iter().enumerate() {
+                let name = self.next_varname("val");
+                out_varnames.insert(*out_name, name.clone());
+                output_names.insert((node_id, out_idx as u32), name);
+            }
+
+            let ty = &self.graph.nodes[&node_id].ty;
+            match self.target {
+                CompilationTarget::WGSL => ty.compile_wgsl(args, out_varnames, out, self.info),
+                CompilationTarget::UnrealHLSL => ty.compile_hlsl(args, out_varnames, out, self.info),
+            }
+        }
+
+        output_names
+    }
+
+    // Inputs of a node that aren't evaluated at the node's position: branch inputs, and resampled
+    // inputs that are never sampled at the original position.
+    fn skipped_inputs(&self, node_id: NodeId) -> Vec<&'static str> {
+        let node = &self.graph.nodes[&node_id];
+        let mut skipped = node.ty.branch().map(|branch| vec![branch.if_true, branch.if_false]).unwrap_or_default();
+
+        // only the inputs and positions of the resamples matter here, so the defaults do as args
+        let args = node.ty.inputs().into_iter().map(|(name, _, inp)| (name, inp.val.to_string(self.target))).collect();
+        let resamples = node.ty.resample(&args, self.target);
+        for resample in &resamples {
+            if !skipped.contains(&resample.input) && resamples.iter().all(|other| other.input != resample.input || other.pos != "pos") {
+                skipped.push(resample.input);
+            }
+        }
+        skipped
+    }
 
-    let mut output_names: HashMap<(NodeId, u32), String> = HashMap::new();
-    let mut curr_output_idx = 0;
-    for node_id in sorted_nodes {
-        let node = &graph.nodes[&node_id];
+    // Nodes that have to be evaluated to compile `nodes`: every node whose output is used by an
+    // evaluated node, except through a skipped input, plus the nodes whose outputs aren't used at all.
+    fn needed_nodes(&self, nodes: &[NodeId]) -> HashSet<NodeId> {
+        let mut needed = HashSet::new();
+        let mut has_consumer = HashSet::new();
+        for node_id in nodes.iter().rev() {
+            if !has_consumer.contains(node_id) {
+                needed.insert(*node_id);
+            }
+            let node = &self.graph.nodes[node_id];
+            let skipped_inputs = self.skipped_inputs(*node_id);
+            for (name, _, inp) in node.ty.inputs() {
+                let Some((dependency, _)) = inp.connection else { continue; };
+                has_consumer.insert(dependency);
+                if needed.contains(node_id) && !skipped_inputs.contains(&name) {
+                    needed.insert(dependency);
+                }
+            }
+        }
+        needed
+    }
+
+    // Compiles the part of the graph feeding into `input` on its own.
+    // Returns the input's type, the code and the name of the input's value.
+    fn compile_upstream(&mut self, node_id: NodeId, input: &str) -> Option<(Type, String, String)> {
+        let node = &self.graph.nodes[&node_id];
+        let (_, ty, inp) = node.ty.inputs().into_iter().find(|(name, _, _)| *name == input)?;
+        let Some((src_node, src_out)) = inp.connection else {
+            return Some((ty, String::new(), inp.val.to_string(self.target)));
+        };
+
+        let mut upstream = HashSet::new();
+        let mut to_visit = vec![src_node];
+        while let Some(id) = to_visit.pop() {
+            if !upstream.insert(id) {
+                continue;
+            }
+            for (_, _, inp) in self.graph.nodes[&id].ty.inputs() {
+                if let Some((dependency, _)) = inp.connection {
+                    to_visit.push(dependency);
+                }
+            }
+        }
+        let nodes: Vec<NodeId> = self.sorted_nodes.iter().copied().filter(|id| upstream.contains(id)).collect();
+
+        let mut block = String::new();
+        let output_names = self.compile_nodes(&mut block, &nodes);
+        Some((ty, block, output_names[&(src_node, src_out)].clone()))
+    }
 
-        let mut args = HashMap::new();
-        for (arg_name, _ty, inp) in node.ty.inputs() {
-            let val = if let Some((out_node_id, out_idx)) = &inp.connection {
-                output_names[&(*out_node_id, *out_idx)].clone()
-            } else {
-                inp.val.to_string(target)
-            };
-            
-            args.insert(arg_name, val);
+    fn declare_var(&self, out: &mut String, name: &str, ty: Type) {
+        let _ = match self.target {
+            CompilationTarget::WGSL => writeln!(out, "\tvar {}: {};", name, ty.typename(self.target)),
+            CompilationTarget::UnrealHLSL => writeln!(out, "\t{} {};", ty.typename(self.target), name),
+        };
+    }
+
+    fn write_block(out: &mut String, block: &str) {
+        for line in block.lines() {
+            let _ = writeln!(out, "\t{}", line);
         }
+    }
 
-        let mut out_varnames = HashMap::new();
-        for (out_idx, (out_name, _output_id)) in node.ty.outputs().iter().enumerate() {
-            let name = format!("val_{}", curr_output_idx);
-            curr_output_idx += 1;
-            out_varnames.insert(*out_name, name.clone());
-            output_names.insert((node_id, out_idx as u32), name);
+    // Compiles the part of the graph feeding into `resample.input` again, inside a block
+    // where `pos` is replaced by the resampling position. Returns the name of the result.
+    fn compile_resample(&mut self, out: &mut String, node_id: NodeId, resample: &Resample) -> String {
+        let Some((ty, block, val)) = self.compile_upstream(node_id, resample.input) else {
+            return String::new();
+        };
+        if block.is_empty() {
+            return val;
         }
 
-        let ty = &graph.nodes[&node_id].ty;
-        match target {
-            CompilationTarget::WGSL => ty.compile_wgsl(args, out_varnames, out, info),
-            CompilationTarget::UnrealHLSL => ty.compile_hlsl(args, out_varnames, out, info),
+        let pos_name = self.next_varname("pos");
+        let result_name = self.next_varname("val");
+        match self.target {
+            CompilationTarget::WGSL => { let _ = writeln!(out, "\tlet {} = {};", pos_name, resample.pos); },
+            CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "\tfloat3 {} = {};", pos_name, resample.pos); },
+        }
+        self.declare_var(out, &result_name, ty);
+        let _ = writeln!(out, "\t{{");
+        match self.target {
+            CompilationTarget::WGSL => { let _ = writeln!(out, "\t\tlet pos = {};", pos_name); },
+            CompilationTarget::UnrealHLSL => { let _ = writeln!(out, "\t\tfloat3 pos = {};", pos_name); },
         }
+        Self::write_block(out, &block);
+        let _ = writeln!(out, "\t\t{} = {};", result_name, val);
+        let _ = writeln!(out, "\t}}");
+
+        result_name
+    }
+
+    // Compiles the parts of the graph feeding into both branches inside an if/else. Returns the name of the result.
+    fn compile_branch(&mut self, out: &mut String, node_id: NodeId, branch: &Branch, args: &HashMap<&'static str, String>) -> String {
+        let (Some((ty, true_block, true_val)), Some((_, false_block, false_val))) = (self.compile_upstream(node_id, branch.if_true), self.compile_upstream(node_id, branch.if_false)) else {
+            return String::new();
+        };
+
+        let result_name = self.next_varname("val");
+        self.declare_var(out, &result_name, ty);
+        let _ = writeln!(out, "\tif ({} > 0.5) {{", args[branch.condition]);
+        Self::write_block(out, &true_block);
+        let _ = writeln!(out, "\t\t{} = {};", result_name, true_val);
+        let _ = writeln!(out, "\t}} else {{");
+        Self::write_block(out, &false_block);
+        let _ = writeln!(out, "\t\t{} = {};", result_name, false_val);
+        let _ = writeln!(out, "\t}}");
+
+        result_name
+    }
+
+    fn next_varname(&mut self, prefix: &str) -> String {
+        let name = format!("{}_{}", prefix, self.curr_output_idx);
+        self.curr_output_idx += 1;
+        name
     }
 
 }
+
+pub fn compile_graph(out: &mut String, graph: &TerrainGraph, target: CompilationTarget, info: &GraphProjectInfo) {
+
+    let Some(sorted_nodes) = graph_toposort(graph) else {
+        return;
+    };
+
+    let mut compiler = GraphCompiler {
+        graph,
+        sorted_nodes: sorted_nodes.clone(),
+        target,
+        info,
+        curr_output_idx: 0
+    };
+    compiler.compile_nodes(out, &sorted_nodes);
+
+}
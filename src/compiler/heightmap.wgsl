@@ -0,0 +1,24 @@
+
+@group(1) @binding(1)
+var heightmaps: texture_2d_array<f32>;
+
+fn heightmap_texel(layer: i32, p: vec2<i32>, size: vec2<i32>, wrap: bool) -> f32 {
+    var q = clamp(p, vec2(0), size - 1);
+    if wrap {
+        q = ((p % size) + size) % size;
+    }
+    return textureLoad(heightmaps, q, layer, 0).r;
+}
+
+// Bilinearly samples an imported heightmap of the given size, which is in the top left corner of its layer.
+// The image is centered on uv 0, and x in [-0.5, 0.5] covers its width. Along y it keeps the image's aspect.
+fn sample_heightmap(layer: i32, size: vec2<i32>, uv: vec2<f32>, wrap: bool) -> f32 {
+    let p = (vec2(uv.x, uv.y * f32(size.x) / f32(size.y)) + 0.5) * vec2<f32>(size) - 0.5;
+    let p0 = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let h00 = heightmap_texel(layer, p0, size, wrap);
+    let h10 = heightmap_texel(layer, p0 + vec2(1, 0), size, wrap);
+    let h01 = heightmap_texel(layer, p0 + vec2(0, 1), size, wrap);
+    let h11 = heightmap_texel(layer, p0 + vec2(1, 1), size, wrap);
+    return mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+}
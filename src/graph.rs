@@ -1,14 +1,14 @@
 
 pub mod node_types;
 
-use std::collections::{HashMap, HashSet};
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}};
 
 use node_types::NODE_TYPES;
 use serde_json::json;
 
-use crate::{app::action::{Action, ActionManager}, biome::Biomes};
+use crate::{app::{action::{Action, ActionManager}, texture_loader::TextureLoader}, biome::Biomes, compiler::CompilationTarget};
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct NodeId(u64);
 
@@ -59,8 +59,36 @@ impl From<Value> for NodeInput {
 
 }
 
+// Asks the compiler to evaluate everything connected to `input` again at position `pos`.
+// The result is passed to the node's compile functions as the arg `name`. The input itself is
+// only evaluated at the node's position if one of its resamples has the position `pos`.
+pub struct Resample {
+    pub input: &'static str,
+    pub name: &'static str,
+    pub pos: String
+}
+
+// Asks the compiler to only evaluate what's connected to `if_true` when the `condition` input is
+// above 0.5, and what's connected to `if_false` otherwise. Nodes only needed by one of the branches
+// are not evaluated outside of it. The selected value is passed as the arg `name`.
+pub struct Branch {
+    pub condition: &'static str,
+    pub if_true: &'static str,
+    pub if_false: &'static str,
+    pub name: &'static str
+}
+
 pub struct GraphProjectInfo<'a> {
-    pub biomes: &'a Biomes
+    pub biomes: &'a Biomes,
+    // layers of the heightmap texture
+    pub heightmaps: &'a [PathBuf],
+    // width and height of each layer's heightmap
+    pub heightmap_sizes: &'a [[u32; 2]],
+    // images that can be picked as heightmaps
+    pub heightmap_files: &'a [PathBuf],
+    // textures of the material layers, in the order of their Unreal material indices after the biomes
+    pub layer_textures: &'a [PathBuf],
+    pub textures: &'a TextureLoader
 } 
 
 pub trait NodeType {
@@ -74,7 +102,25 @@ pub trait NodeType {
     fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo);
     fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo);
 
-    fn custom_ui_height() -> f32 {
+    fn resample(&self, _args: &HashMap<&'static str, String>, _target: CompilationTarget) -> Vec<Resample> {
+        vec![]
+    }
+
+    fn branch(&self) -> Option<Branch> {
+        None
+    }
+
+    // the image file sampled by the node, relative to the project directory
+    fn heightmap(&self) -> Option<&Path> {
+        None
+    }
+
+    // the textures the node paints material layers with
+    fn layer_textures(&self) -> Vec<&Path> {
+        vec![]
+    }
+
+    fn custom_ui_height(&self) -> f32 {
         return 0.0;
     }
 
@@ -100,6 +146,10 @@ pub trait NodeTypeDyn {
     fn outputs(&self) -> Vec<(&'static str, Type)>;
     fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo);
     fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo);
+    fn resample(&self, args: &HashMap<&'static str, String>, target: CompilationTarget) -> Vec<Resample>;
+    fn branch(&self) -> Option<Branch>;
+    fn heightmap(&self) -> Option<&Path>;
+    fn layer_textures(&self) -> Vec<&Path>;
     fn custom_ui_height(&self) -> f32;
     fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo);
     fn custom_serialize(&self) -> serde_json::Value;
@@ -133,8 +183,24 @@ impl<T: NodeType> NodeTypeDyn for T {
         self.compile_hlsl(args, out_varnames, out, info);
     }
 
+    fn resample(&self, args: &HashMap<&'static str, String>, target: CompilationTarget) -> Vec<Resample> {
+        self.resample(args, target)
+    }
+
+    fn branch(&self) -> Option<Branch> {
+        self.branch()
+    }
+
+    fn heightmap(&self) -> Option<&Path> {
+        self.heightmap()
+    }
+
+    fn layer_textures(&self) -> Vec<&Path> {
+        self.layer_textures()
+    }
+
     fn custom_ui_height(&self) -> f32 {
-        Self::custom_ui_height()
+        NodeType::custom_ui_height(self)
     }
 
     fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo) {
@@ -291,6 +357,16 @@ impl TerrainGraph {
         }
     }
 
+    // the images sampled by the graph's Image Heightmap nodes
+    pub fn heightmaps(&self) -> impl Iterator<Item = &Path> {
+        self.nodes.values().filter_map(|node| node.ty.heightmap())
+    }
+
+    // the textures of the graph's material layers
+    pub fn layer_textures(&self) -> impl Iterator<Item = &Path> {
+        self.nodes.values().flat_map(|node| node.ty.layer_textures())
+    }
+
     pub fn to_json(&self) -> serde_json::Value {
         json!({
             "transform": self.transform,
@@ -388,12 +464,15 @@ pub fn graph_toposort(graph: &TerrainGraph) -> Option<Vec<NodeId>> {
 
     }
 
-    for node in graph.nodes.keys() {
-        if perm_mark.contains(node) {
+    // visited by id, so the compiled code and the mesh cache's hash of it are the same every run
+    let mut nodes: Vec<NodeId> = graph.nodes.keys().copied().collect();
+    nodes.sort();
+    for node in nodes {
+        if perm_mark.contains(&node) {
             continue;
         }
 
-        visit(*node, &mut perm_mark, &mut temp_mark, &mut contains_cycle, &graph, &mut sorted_nodes);
+        visit(node, &mut perm_mark, &mut temp_mark, &mut contains_cycle, &graph, &mut sorted_nodes);
     }
 
     if contains_cycle {
@@ -22,6 +22,15 @@ use trig::*;
 pub mod noise;
 use noise::*;
 
+pub mod curve;
+use curve::*;
+
+pub mod river;
+use river::*;
+
+pub mod logic;
+use logic::*;
+
 pub struct NodeKind {
     pub label: &'static str,
     pub make: fn() -> Box<dyn NodeTypeDyn> 
@@ -43,14 +52,24 @@ pub const NODE_TYPES: &[(&'static str, &[NodeKind])] = &[
         make_node_kind::<SpaghettiCaveTerrain>(),
         make_node_kind::<InvertTerrain>(),
         make_node_kind::<ErodeTerrain>(),
+        make_node_kind::<ThermalErodeTerrain>(),
+        make_node_kind::<TerraceTerrain>(),
+        make_node_kind::<StrataTerrain>(),
+        make_node_kind::<OverhangTerrain>(),
+        make_node_kind::<RiverTerrain>(),
         make_node_kind::<TerrainUnion>(),
         make_node_kind::<TerrainIntersection>(),
+        make_node_kind::<TerrainSubtract>(),
         make_node_kind::<TerrainToSDF>(),
         make_node_kind::<SDFToTerrain>(),
+        make_node_kind::<TerrainGradient>(),
     ]),
     ("Heightmap", &[
         make_node_kind::<NoiseHeightmap>(),
-        make_node_kind::<RidgeHeightmap>()
+        make_node_kind::<RidgeHeightmap>(),
+        make_node_kind::<ErodedHeightmap>(),
+        make_node_kind::<ImageHeightmap>(),
+        make_node_kind::<TerraceHeightmap>(),
     ]),
     ("Math", &[
         make_node_kind::<Add>(),
@@ -73,6 +92,15 @@ pub const NODE_TYPES: &[(&'static str, &[NodeKind])] = &[
         make_node_kind::<Clamp>(),
         make_node_kind::<Lerp>(),
         make_node_kind::<MapRange>(),
+        make_node_kind::<Curve>(),
+    ]),
+    ("Logic", &[
+        make_node_kind::<Select>(),
+        make_node_kind::<SelectVector>(),
+        make_node_kind::<SelectTerrain>(),
+        make_node_kind::<And>(),
+        make_node_kind::<Or>(),
+        make_node_kind::<Not>(),
     ]),
     ("Vector Math", &[
         make_node_kind::<CombineXYZ>(),
@@ -91,6 +119,10 @@ pub const NODE_TYPES: &[(&'static str, &[NodeKind])] = &[
     ("Noise", &[
         make_node_kind::<Noise3D>(),
         make_node_kind::<Noise2D>(),
+        make_node_kind::<ValueNoise>(),
+        make_node_kind::<CellCoordinates>(),
+        make_node_kind::<Hash>(),
+        make_node_kind::<HashVector>(),
     ]),
     ("Trig", &[
         make_node_kind::<Sin>(),
@@ -102,6 +134,7 @@ pub const NODE_TYPES: &[(&'static str, &[NodeKind])] = &[
     ]),
     ("Biome", &[
         make_node_kind::<BiomeParameter>(),
+        make_node_kind::<BiomeVectorParameter>(),
         make_node_kind::<BiomeWeight>()
     ])
 ];
@@ -1,6 +1,6 @@
 
 use std::collections::HashMap;
-use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, graph::{GraphProjectInfo, NodeInput, NodeType, Type}};
+use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, biome::BiomeParamKind, graph::{GraphProjectInfo, NodeInput, NodeType, Type}};
 use std::fmt::Write;
 
 pub struct BiomeParameter {
@@ -29,7 +29,7 @@ impl NodeType for BiomeParameter {
     }
 
     fn compile_wgsl(&self, _args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
-        if let Some(idx) = info.biomes.biome_params.iter().position(|param| param == &self.param) {
+        if let Some(idx) = find_param(info, &self.param, false) {
             let _ = writeln!(out, "\tlet {} = b_{};", out_varnames["x"], idx); 
         } else {
             let _ = writeln!(out, "\tlet {} = 0.0;", out_varnames["x"]);
@@ -37,38 +37,19 @@ impl NodeType for BiomeParameter {
     }
 
     fn compile_hlsl(&self, _args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
-        if let Some(idx) = info.biomes.biome_params.iter().position(|param| param == &self.param) {
+        if let Some(idx) = find_param(info, &self.param, false) {
             let _ = writeln!(out, "\tfloat {} = b_{};", out_varnames["x"], idx); 
         } else {
             let _ = writeln!(out, "\tfloat {} = 0.0;", out_varnames["x"]);
         }
     }
 
-    fn custom_ui_height() -> f32 {
+    fn custom_ui_height(&self) -> f32 {
         13.0
     }
 
     fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo) {
-        ui.horizontal_centered(|ui| {
-            ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
-
-            let idx = info.biomes.biome_params.iter().position(|param| param == &self.param);
-            egui::ComboBox::new("biome_parameter", "")
-                .selected_text(idx.map(|_| self.param.as_str()).unwrap_or("Select..."))
-                .width(100.0)
-                .truncate()
-                .show_ui(ui, |ui| {
-                    if info.biomes.biome_params.len() == 0 {
-                        ui.label("No parameters available.");
-                    } else {
-                        for param in &info.biomes.biome_params {
-                            if ui.selectable_label(&self.param == param, param).clicked() {
-                                self.param = param.clone();
-                            }
-                        }
-                    } 
-                });
-        });
+        param_combobox(ui, &mut self.param, info, false);
     }
 
     fn custom_serialize(&self) -> serde_json::Value {
@@ -85,6 +66,98 @@ impl NodeType for BiomeParameter {
 
 }
 
+pub struct BiomeVectorParameter {
+    param: String 
+}
+
+impl NodeType for BiomeVectorParameter {
+    const LABEL: &'static str = "Biome Vector Parameter";
+
+    fn make() -> Self {
+        Self {
+            param: String::new()
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("vec", Type::Vector)]
+    }
+
+    fn compile_wgsl(&self, _args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
+        if let Some(idx) = find_param(info, &self.param, true) {
+            let _ = writeln!(out, "\tlet {} = b_{};", out_varnames["vec"], idx); 
+        } else {
+            let _ = writeln!(out, "\tlet {} = vec3(0.0);", out_varnames["vec"]);
+        }
+    }
+
+    fn compile_hlsl(&self, _args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
+        if let Some(idx) = find_param(info, &self.param, true) {
+            let _ = writeln!(out, "\tfloat3 {} = b_{};", out_varnames["vec"], idx); 
+        } else {
+            let _ = writeln!(out, "\tfloat3 {} = float3(0.0, 0.0, 0.0);", out_varnames["vec"]);
+        }
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        13.0
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo) {
+        param_combobox(ui, &mut self.param, info, true);
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "param": self.param
+        })
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        if let Some(param) = data.get("param").and_then(|param| param.as_str()) {
+            self.param = param.to_owned(); 
+        } 
+    }
+
+}
+
+// Index of the parameter called `name`, if it has the right kind. Color parameters are vectors.
+fn find_param(info: &GraphProjectInfo, name: &str, vector: bool) -> Option<usize> {
+    info.biomes.biome_params.iter().position(|param| param.name == name && (param.kind != BiomeParamKind::Scalar) == vector)
+}
+
+fn param_combobox(ui: &mut egui::Ui, selected: &mut String, info: &GraphProjectInfo, vector: bool) {
+    ui.horizontal_centered(|ui| {
+        ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+
+        let idx = find_param(info, selected, vector);
+        egui::ComboBox::new("biome_parameter", "")
+            .selected_text(idx.map(|_| selected.as_str()).unwrap_or("Select..."))
+            .width(100.0)
+            .truncate()
+            .show_ui(ui, |ui| {
+                let params: Vec<_> = info.biomes.biome_params.iter().filter(|param| (param.kind != BiomeParamKind::Scalar) == vector).collect();
+                if params.is_empty() {
+                    ui.label("No parameters available.");
+                } else {
+                    for param in params {
+                        if ui.selectable_label(selected == &param.name, &param.name).clicked() {
+                            *selected = param.name.clone();
+                        }
+                    }
+                } 
+            });
+    });
+}
+
 pub struct BiomeWeight {
     biome_idx: usize
 }
@@ -128,7 +201,7 @@ impl NodeType for BiomeWeight {
         }
     }
 
-    fn custom_ui_height() -> f32 {
+    fn custom_ui_height(&self) -> f32 {
         13.0
     }
 
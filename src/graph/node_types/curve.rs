@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use egui::{pos2, vec2, Color32, Rect, Rounding, Sense, Stroke};
+
+use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, graph::{GraphProjectInfo, NodeInput, NodeType, Type, Value}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum CurveInterpolation {
+    Linear,
+    Smooth,
+    Step
+}
+
+impl CurveInterpolation {
+
+    const ALL: [CurveInterpolation; 3] = [CurveInterpolation::Linear, CurveInterpolation::Smooth, CurveInterpolation::Step];
+
+    fn label(&self) -> &'static str {
+        match self {
+            CurveInterpolation::Linear => "Linear",
+            CurveInterpolation::Smooth => "Smooth",
+            CurveInterpolation::Step => "Step",
+        }
+    }
+
+}
+
+// `interpolation` controls the segment between this key and the next one
+#[derive(Clone, Copy)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CurveKey {
+    pub x: f32,
+    pub y: f32,
+    pub interpolation: CurveInterpolation
+}
+
+const CURVE_EDITOR_H: f32 = 110.0;
+const CURVE_KEY_RADIUS: f32 = 4.0;
+
+pub struct Curve {
+    x: NodeInput,
+    min: NodeInput,
+    max: NodeInput,
+    keys: Vec<CurveKey>,
+
+    // Editor data
+    selected_key: Option<usize>
+}
+
+impl Curve {
+
+    fn sort_keys(&mut self) {
+        self.keys.sort_by(|a, b| a.x.total_cmp(&b.x));
+    }
+
+    fn eval(&self, t: f32) -> f32 {
+        let Some(first) = self.keys.first() else { return t; };
+        let mut y = first.y;
+        for (i, key) in self.keys.iter().enumerate() {
+            if t < key.x {
+                break;
+            }
+            y = match (key.interpolation, self.keys.get(i + 1)) {
+                (CurveInterpolation::Step, _) | (_, None) => key.y,
+                (CurveInterpolation::Linear, Some(next)) => {
+                    let s = (t - key.x) / (next.x - key.x).max(0.0001);
+                    key.y + (next.y - key.y) * s.min(1.0)
+                },
+                (CurveInterpolation::Smooth, Some(next)) => {
+                    let s = ((t - key.x) / (next.x - key.x).max(0.0001)).min(1.0);
+                    key.y + (next.y - key.y) * s * s * (3.0 - 2.0 * s)
+                }
+            };
+        }
+        y
+    }
+
+    // The curve is compiled to a chain of polynomial segments, each overriding the previous one once t passes its key.
+    fn compile(&self, args: &HashMap<&'static str, String>, out_varnames: &HashMap<&'static str, String>, out: &mut String, var_decl: &str, let_decl: &str) {
+        let y = &out_varnames["y"];
+        let _ = writeln!(out, "\t{} {}_t = clamp(({} - {}) / dezero({} - {}), 0.0, 1.0);", let_decl, y, args["x"], args["min"], args["max"], args["min"]);
+        let _ = writeln!(out, "\t{} {}_c = {:?};", var_decl, y, self.keys.first().map(|key| key.y).unwrap_or(0.0));
+        if self.keys.is_empty() {
+            let _ = writeln!(out, "\t{}_c = {}_t;", y, y);
+        }
+        for (i, key) in self.keys.iter().enumerate() {
+            let segment = match (key.interpolation, self.keys.get(i + 1)) {
+                (CurveInterpolation::Step, _) | (_, None) => format!("{:?}", key.y),
+                (CurveInterpolation::Linear, Some(next)) => {
+                    let s = format!("min(({}_t - {:?}) / {:?}, 1.0)", y, key.x, (next.x - key.x).max(0.0001));
+                    format!("{:?} + {:?} * {}", key.y, next.y - key.y, s)
+                },
+                (CurveInterpolation::Smooth, Some(next)) => {
+                    let s = format!("min(({}_t - {:?}) / {:?}, 1.0)", y, key.x, (next.x - key.x).max(0.0001));
+                    format!("{:?} + {:?} * {s} * {s} * (3.0 - 2.0 * {s})", key.y, next.y - key.y)
+                }
+            };
+            let _ = writeln!(out, "\tif ({}_t >= {:?}) {{ {}_c = {}; }}", y, key.x, y, segment);
+        }
+        let _ = writeln!(out, "\t{} {} = {} + {}_c * ({} - {});", let_decl, y, args["min"], y, args["max"], args["min"]);
+    }
+
+}
+
+impl NodeType for Curve {
+    const LABEL: &'static str = "Curve";
+
+    fn make() -> Self {
+        Self {
+            x: Value::scalar(0.0).into(),
+            min: Value::scalar(0.0).into(),
+            max: Value::scalar(1.0).into(),
+            keys: vec![
+                CurveKey { x: 0.0, y: 0.0, interpolation: CurveInterpolation::Smooth },
+                CurveKey { x: 1.0, y: 1.0, interpolation: CurveInterpolation::Smooth },
+            ],
+            selected_key: None
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("x", Type::Scalar, &self.x),
+            ("min", Type::Scalar, &self.min),
+            ("max", Type::Scalar, &self.max),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("x", Type::Scalar, &mut self.x),
+            ("min", Type::Scalar, &mut self.min),
+            ("max", Type::Scalar, &mut self.max),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("y", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        self.compile(&args, &out_varnames, out, "var", "let");
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        self.compile(&args, &out_varnames, out, "float", "float");
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        CURVE_EDITOR_H
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, _info: &GraphProjectInfo) {
+        let rect = Rect::from_min_size(ui.max_rect().min + vec2(PARAM_H_MARGIN, 0.0), vec2(PARAM_SIZE.x, CURVE_EDITOR_H));
+        let resp = ui.allocate_rect(rect, Sense::click_and_drag());
+
+        let to_screen = |x: f32, y: f32| pos2(rect.left() + x * rect.width(), rect.bottom() - y * rect.height());
+        let from_screen = |pos: egui::Pos2| (
+            ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
+            ((rect.bottom() - pos.y) / rect.height()).clamp(0.0, 1.0)
+        );
+        let key_at = |keys: &[CurveKey], pos: egui::Pos2| keys.iter().position(|key| to_screen(key.x, key.y).distance(pos) < 2.0 * CURVE_KEY_RADIUS);
+
+        // editing
+        if resp.drag_started() || resp.secondary_clicked() {
+            self.selected_key = resp.interact_pointer_pos().and_then(|pos| key_at(&self.keys, pos));
+        }
+        if resp.dragged() {
+            if let (Some(idx), Some(pos)) = (self.selected_key, resp.interact_pointer_pos()) {
+                if let Some(key) = self.keys.get_mut(idx) {
+                    (key.x, key.y) = from_screen(pos);
+                }
+            }
+        }
+        if resp.drag_stopped() {
+            self.sort_keys();
+        }
+        if resp.double_clicked() {
+            if let Some(pos) = resp.interact_pointer_pos() {
+                if key_at(&self.keys, pos).is_none() {
+                    let (x, y) = from_screen(pos);
+                    self.keys.push(CurveKey { x, y, interpolation: CurveInterpolation::Smooth });
+                    self.sort_keys();
+                }
+            }
+        }
+        resp.context_menu(|ui| {
+            let Some(idx) = self.selected_key.filter(|idx| *idx < self.keys.len()) else {
+                ui.label("Double click to add a key.");
+                return;
+            };
+            for interpolation in CurveInterpolation::ALL {
+                if ui.selectable_label(self.keys[idx].interpolation == interpolation, interpolation.label()).clicked() {
+                    self.keys[idx].interpolation = interpolation;
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            if ui.button("Delete").clicked() {
+                self.keys.remove(idx);
+                self.selected_key = None;
+                ui.close_menu();
+            }
+        });
+
+        // drawing
+        let painter = ui.painter();
+        painter.rect(rect, Rounding::same(2.0), Color32::from_gray(20), Stroke::new(1.0, Color32::from_gray(50)));
+        for i in 1..4 {
+            let f = i as f32 / 4.0;
+            painter.vline(to_screen(f, 0.0).x, rect.y_range(), Stroke::new(1.0, Color32::from_gray(30)));
+            painter.hline(rect.x_range(), to_screen(0.0, f).y, Stroke::new(1.0, Color32::from_gray(30)));
+        }
+        let points = (0..=64).map(|i| {
+            let t = i as f32 / 64.0;
+            to_screen(t, self.eval(t).clamp(0.0, 1.0))
+        }).collect();
+        painter.add(egui::Shape::line(points, Stroke::new(2.0, Color32::from_rgb(114, 198, 247))));
+        for (idx, key) in self.keys.iter().enumerate() {
+            let color = if self.selected_key == Some(idx) { Color32::WHITE } else { Color32::from_gray(180) };
+            painter.circle_filled(to_screen(key.x, key.y), CURVE_KEY_RADIUS, color);
+        }
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "keys": self.keys
+        })
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        if let Some(keys) = data.as_object().and_then(|data| data.get("keys")).and_then(|keys| serde_json::from_value(keys.clone()).ok()) {
+            self.keys = keys;
+            self.sort_keys();
+        }
+    }
+
+}
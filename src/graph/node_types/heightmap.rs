@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::{Path, PathBuf}};
 
-use crate::graph::{GraphProjectInfo, NodeInput, NodeType, Type, Value};
+use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, graph::{GraphProjectInfo, NodeInput, NodeType, Type, Value}, terrain::meshgen::StableHasher};
 
 
 pub struct NoiseHeightmap {
@@ -98,3 +98,254 @@ impl NodeType for RidgeHeightmap {
     }
 
 }
+
+pub struct ErodedHeightmap {
+    pub scale: NodeInput,
+    pub min: NodeInput,
+    pub max: NodeInput,
+    pub erosion: NodeInput
+}
+
+impl NodeType for ErodedHeightmap {
+
+    const LABEL: &'static str = "Eroded Heightmap";
+
+    fn make() -> Self {
+        Self {
+            scale: Value::scalar(1.0).into(),
+            min: Value::scalar(0.0).into(),
+            max: Value::scalar(50.0).into(),
+            erosion: Value::scalar(1.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("scale", Type::Scalar, &self.scale),
+            ("min", Type::Scalar, &self.min),
+            ("max", Type::Scalar, &self.max),
+            ("erosion", Type::Scalar, &self.erosion),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("scale", Type::Scalar, &mut self.scale),
+            ("min", Type::Scalar, &mut self.min),
+            ("max", Type::Scalar, &mut self.max),
+            ("erosion", Type::Scalar, &mut self.erosion),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("height", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = eroded_height(seed, pos, {}, {}, {}, {});\n", out_varnames["height"], args["min"], args["max"], args["scale"], args["erosion"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = eroded_height(seed, pos, {}, {}, {}, {});\n", out_varnames["height"], args["min"], args["max"], args["scale"], args["erosion"]).as_str());
+    }
+
+}
+
+pub struct TerraceHeightmap {
+    pub height: NodeInput,
+    pub step: NodeInput,
+    pub sharpness: NodeInput
+}
+
+impl NodeType for TerraceHeightmap {
+
+    const LABEL: &'static str = "Terrace Heightmap";
+
+    fn make() -> Self {
+        Self {
+            height: Value::scalar(0.0).into(),
+            step: Value::scalar(10.0).into(),
+            sharpness: Value::scalar(0.8).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("height", Type::Scalar, &self.height),
+            ("step", Type::Scalar, &self.step),
+            ("sharpness", Type::Scalar, &self.sharpness),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("height", Type::Scalar, &mut self.height),
+            ("step", Type::Scalar, &mut self.step),
+            ("sharpness", Type::Scalar, &mut self.sharpness),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("height", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = terrace({}, {}, {});\n", out_varnames["height"], args["height"], args["step"], args["sharpness"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = terrace({}, {}, {});\n", out_varnames["height"], args["height"], args["step"], args["sharpness"]).as_str());
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum HeightmapWrap {
+    Clamp,
+    Wrap
+}
+
+// The Unreal texture parameter of a heightmap, named after its file so it doesn't change when other heightmaps are added.
+// Paths that only differ in characters the name can't hold are told apart by a hash of the whole path.
+pub fn heightmap_texture_name(path: &Path) -> String {
+    let name: String = path.with_extension("").to_string_lossy().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let mut hasher = StableHasher::new();
+    hasher.write_str(&path.to_string_lossy().replace('\\', "/"));
+    format!("Heightmap_{}_{:08x}", name, hasher.finish() as u32)
+}
+
+pub struct ImageHeightmap {
+    pub size: NodeInput,
+    pub min: NodeInput,
+    pub max: NodeInput,
+    pub path: PathBuf,
+    pub wrap: HeightmapWrap
+}
+
+impl ImageHeightmap {
+
+    // the image is centered on the origin and covers `size` world units along x, along z it keeps its aspect
+    fn uv(&self, args: &HashMap<&'static str, String>) -> String {
+        format!("pos.xz / {}", args["size"])
+    }
+
+}
+
+impl NodeType for ImageHeightmap {
+
+    const LABEL: &'static str = "Image Heightmap";
+
+    fn make() -> Self {
+        Self {
+            size: Value::scalar(256.0).into(),
+            min: Value::scalar(0.0).into(),
+            max: Value::scalar(50.0).into(),
+            path: PathBuf::new(),
+            wrap: HeightmapWrap::Clamp
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("size", Type::Scalar, &self.size),
+            ("min", Type::Scalar, &self.min),
+            ("max", Type::Scalar, &self.max),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("size", Type::Scalar, &mut self.size),
+            ("min", Type::Scalar, &mut self.min),
+            ("max", Type::Scalar, &mut self.max),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("height", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
+        let Some(layer) = info.heightmaps.iter().position(|path| path == &self.path) else {
+            out.push_str(format!("\tlet {} = {};\n", out_varnames["height"], args["min"]).as_str());
+            return;
+        };
+        let [width, height] = info.heightmap_sizes[layer];
+        out.push_str(format!("\tlet {} = mix({}, {}, sample_heightmap({}, vec2({}, {}), {}, {}));\n",
+            out_varnames["height"], args["min"], args["max"], layer, width, height, self.uv(&args), self.wrap == HeightmapWrap::Wrap).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let Some(path) = self.heightmap() else {
+            out.push_str(format!("\tfloat {} = {};\n", out_varnames["height"], args["min"]).as_str());
+            return;
+        };
+        out.push_str(format!("\tfloat {} = lerp({}, {}, sample_heightmap({3}<ProjectName>, {3}Sampler<ProjectName>, {4}, {5}));\n",
+            out_varnames["height"], args["min"], args["max"], heightmap_texture_name(path), self.uv(&args), self.wrap == HeightmapWrap::Wrap).as_str());
+    }
+
+    fn heightmap(&self) -> Option<&Path> {
+        (!self.path.as_os_str().is_empty()).then_some(self.path.as_path())
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        40.0
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo) {
+        ui.horizontal(|ui| {
+            ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+
+            egui::ComboBox::new("heightmap_image", "")
+                .selected_text(if self.heightmap().is_some() { self.path.to_string_lossy().to_string() } else { "Select...".to_owned() })
+                .width(100.0)
+                .truncate()
+                .show_ui(ui, |ui| {
+                    if info.heightmap_files.is_empty() {
+                        ui.label("No PNG images in the project folder.");
+                    } else {
+                        for path in info.heightmap_files {
+                            if ui.selectable_label(&self.path == path, path.to_string_lossy()).clicked() {
+                                self.path = path.clone();
+                            }
+                        }
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+
+            egui::ComboBox::new("heightmap_wrap", "")
+                .selected_text(match self.wrap {
+                    HeightmapWrap::Clamp => "Clamp",
+                    HeightmapWrap::Wrap => "Wrap",
+                })
+                .width(100.0)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.wrap, HeightmapWrap::Clamp, "Clamp");
+                    ui.selectable_value(&mut self.wrap, HeightmapWrap::Wrap, "Wrap");
+                });
+        });
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path,
+            "wrap": self.wrap
+        })
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        let Some(data) = data.as_object() else { return; };
+        if let Some(path) = data.get("path").and_then(|path| path.as_str()) {
+            self.path = path.into();
+        }
+        if let Some(wrap) = data.get("wrap").and_then(|wrap| serde_json::from_value(wrap.clone()).ok()) {
+            self.wrap = wrap;
+        }
+    }
+
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use crate::graph::{Branch, GraphProjectInfo, NodeInput, NodeType, Type, Value};
+
+pub struct Select {
+    condition: NodeInput,
+    if_true: NodeInput,
+    if_false: NodeInput
+}
+
+impl NodeType for Select {
+    const LABEL: &'static str = "Select";
+
+    fn make() -> Self {
+        Self {
+            condition: Value::scalar(1.0).into(),
+            if_true: Value::scalar(0.0).into(),
+            if_false: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &self.condition),
+            ("if true", Type::Scalar, &self.if_true),
+            ("if false", Type::Scalar, &self.if_false)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &mut self.condition),
+            ("if true", Type::Scalar, &mut self.if_true),
+            ("if false", Type::Scalar, &mut self.if_false)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("result", Type::Scalar)
+        ]
+    }
+
+    fn branch(&self) -> Option<Branch> {
+        Some(Branch {
+            condition: "condition",
+            if_true: "if true",
+            if_false: "if false",
+            name: "selected"
+        })
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+}
+
+pub struct SelectVector {
+    condition: NodeInput,
+    if_true: NodeInput,
+    if_false: NodeInput
+}
+
+impl NodeType for SelectVector {
+    const LABEL: &'static str = "Select Vector";
+
+    fn make() -> Self {
+        Self {
+            condition: Value::scalar(1.0).into(),
+            if_true: Value::vector(0.0, 0.0, 0.0).into(),
+            if_false: Value::vector(0.0, 0.0, 0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &self.condition),
+            ("if true", Type::Vector, &self.if_true),
+            ("if false", Type::Vector, &self.if_false)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &mut self.condition),
+            ("if true", Type::Vector, &mut self.if_true),
+            ("if false", Type::Vector, &mut self.if_false)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("result", Type::Vector)
+        ]
+    }
+
+    fn branch(&self) -> Option<Branch> {
+        Some(Branch {
+            condition: "condition",
+            if_true: "if true",
+            if_false: "if false",
+            name: "selected"
+        })
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat3 {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+}
+
+pub struct SelectTerrain {
+    condition: NodeInput,
+    if_true: NodeInput,
+    if_false: NodeInput
+}
+
+impl NodeType for SelectTerrain {
+    const LABEL: &'static str = "Select Terrain";
+
+    fn make() -> Self {
+        Self {
+            condition: Value::scalar(1.0).into(),
+            if_true: Value::terrain().into(),
+            if_false: Value::terrain().into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &self.condition),
+            ("if true", Type::Terrain, &self.if_true),
+            ("if false", Type::Terrain, &self.if_false)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("condition", Type::Scalar, &mut self.condition),
+            ("if true", Type::Terrain, &mut self.if_true),
+            ("if false", Type::Terrain, &mut self.if_false)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("result", Type::Terrain)
+        ]
+    }
+
+    fn branch(&self) -> Option<Branch> {
+        Some(Branch {
+            condition: "condition",
+            if_true: "if true",
+            if_false: "if false",
+            name: "selected"
+        })
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tTerrain {} = {};\n", out_varnames["result"], args["selected"]).as_str());
+    }
+
+}
+
+pub struct And {
+    a: NodeInput,
+    b: NodeInput
+}
+
+impl NodeType for And {
+    const LABEL: &'static str = "And";
+
+    fn make() -> Self {
+        Self {
+            a: Value::scalar(0.0).into(),
+            b: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &self.a),
+            ("b", Type::Scalar, &self.b)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &mut self.a),
+            ("b", Type::Scalar, &mut self.b)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("c", Type::Scalar)
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = select(0.0, 1.0, {} > 0.5 && {} > 0.5);\n", out_varnames["c"], args["a"], args["b"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = {} > 0.5 && {} > 0.5 ? 1.0 : 0.0;\n", out_varnames["c"], args["a"], args["b"]).as_str());
+    }
+
+}
+
+pub struct Or {
+    a: NodeInput,
+    b: NodeInput
+}
+
+impl NodeType for Or {
+    const LABEL: &'static str = "Or";
+
+    fn make() -> Self {
+        Self {
+            a: Value::scalar(0.0).into(),
+            b: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &self.a),
+            ("b", Type::Scalar, &self.b)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &mut self.a),
+            ("b", Type::Scalar, &mut self.b)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("c", Type::Scalar)
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = select(0.0, 1.0, {} > 0.5 || {} > 0.5);\n", out_varnames["c"], args["a"], args["b"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = {} > 0.5 || {} > 0.5 ? 1.0 : 0.0;\n", out_varnames["c"], args["a"], args["b"]).as_str());
+    }
+
+}
+
+pub struct Not {
+    a: NodeInput
+}
+
+impl NodeType for Not {
+    const LABEL: &'static str = "Not";
+
+    fn make() -> Self {
+        Self {
+            a: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &self.a)
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("a", Type::Scalar, &mut self.a)
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("b", Type::Scalar)
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tlet {} = select(1.0, 0.0, {} > 0.5);\n", out_varnames["b"], args["a"]).as_str());
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        out.push_str(format!("\tfloat {} = {} > 0.5 ? 0.0 : 1.0;\n", out_varnames["b"], args["a"]).as_str());
+    }
+
+}
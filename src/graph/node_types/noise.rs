@@ -143,3 +143,182 @@ impl NodeType for Noise2D {
     }
     
 }
+
+pub struct ValueNoise {
+    pos: NodeInput,
+    cell_size: NodeInput,
+    seed: NodeInput,
+}
+
+impl NodeType for ValueNoise {
+    const LABEL: &'static str = "Value Noise";
+
+    fn make() -> Self {
+        Self {
+            pos: Value::vector(0.0, 0.0, 0.0).into(),
+            cell_size: Value::scalar(16.0).into(),
+            seed: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, crate::graph::Type, &NodeInput)> {
+        vec![
+            ("pos", Type::Vector, &self.pos),
+            ("cell size", Type::Scalar, &self.cell_size),
+            ("seed", Type::Scalar, &self.seed),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, crate::graph::Type, &mut NodeInput)> {
+        vec![
+            ("pos", Type::Vector, &mut self.pos),
+            ("cell size", Type::Scalar, &mut self.cell_size),
+            ("seed", Type::Scalar, &mut self.seed),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, crate::graph::Type)> {
+        vec![("noise", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = value_noise(seed + i32(f32({})), {} / dezero({}));", out_varnames["noise"], args["seed"], args["pos"], args["cell size"]);
+    }
+
+    fn compile_hlsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tfloat {} = value_noise(seed + int({}), {} / dezero({}));", out_varnames["noise"], args["seed"], args["pos"], args["cell size"]);
+    }
+
+}
+
+pub struct CellCoordinates {
+    pos: NodeInput,
+    cell_size: NodeInput,
+}
+
+impl NodeType for CellCoordinates {
+    const LABEL: &'static str = "Cell Coordinates";
+
+    fn make() -> Self {
+        Self {
+            pos: Value::vector(0.0, 0.0, 0.0).into(),
+            cell_size: Value::scalar(16.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, crate::graph::Type, &NodeInput)> {
+        vec![
+            ("pos", Type::Vector, &self.pos),
+            ("cell size", Type::Scalar, &self.cell_size),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, crate::graph::Type, &mut NodeInput)> {
+        vec![
+            ("pos", Type::Vector, &mut self.pos),
+            ("cell size", Type::Scalar, &mut self.cell_size),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, crate::graph::Type)> {
+        vec![("cell", Type::Vector), ("local", Type::Vector)]
+    }
+
+    fn compile_wgsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        // `local` is the position relative to the center of the cell
+        let _ = writeln!(out, "\tlet {} = floor({} / dezero({}));", out_varnames["cell"], args["pos"], args["cell size"]);
+        let _ = writeln!(out, "\tlet {} = {} - ({} + 0.5) * {};", out_varnames["local"], args["pos"], out_varnames["cell"], args["cell size"]);
+    }
+
+    fn compile_hlsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tfloat3 {} = floor({} / dezero({}));", out_varnames["cell"], args["pos"], args["cell size"]);
+        let _ = writeln!(out, "\tfloat3 {} = {} - ({} + 0.5) * {};", out_varnames["local"], args["pos"], out_varnames["cell"], args["cell size"]);
+    }
+
+}
+
+pub struct Hash {
+    cell: NodeInput,
+    seed: NodeInput,
+}
+
+impl NodeType for Hash {
+    const LABEL: &'static str = "Hash";
+
+    fn make() -> Self {
+        Self {
+            cell: Value::vector(0.0, 0.0, 0.0).into(),
+            seed: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, crate::graph::Type, &NodeInput)> {
+        vec![
+            ("cell", Type::Vector, &self.cell),
+            ("seed", Type::Scalar, &self.seed),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, crate::graph::Type, &mut NodeInput)> {
+        vec![
+            ("cell", Type::Vector, &mut self.cell),
+            ("seed", Type::Scalar, &mut self.seed),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, crate::graph::Type)> {
+        vec![("random", Type::Scalar)]
+    }
+
+    fn compile_wgsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = hash_cell(seed + i32(f32({})), {});", out_varnames["random"], args["seed"], args["cell"]);
+    }
+
+    fn compile_hlsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tfloat {} = hash_cell(seed + int({}), {});", out_varnames["random"], args["seed"], args["cell"]);
+    }
+
+}
+
+pub struct HashVector {
+    cell: NodeInput,
+    seed: NodeInput,
+}
+
+impl NodeType for HashVector {
+    const LABEL: &'static str = "Hash Vector";
+
+    fn make() -> Self {
+        Self {
+            cell: Value::vector(0.0, 0.0, 0.0).into(),
+            seed: Value::scalar(0.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, crate::graph::Type, &NodeInput)> {
+        vec![
+            ("cell", Type::Vector, &self.cell),
+            ("seed", Type::Scalar, &self.seed),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, crate::graph::Type, &mut NodeInput)> {
+        vec![
+            ("cell", Type::Vector, &mut self.cell),
+            ("seed", Type::Scalar, &mut self.seed),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, crate::graph::Type)> {
+        vec![("random", Type::Vector)]
+    }
+
+    fn compile_wgsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = hash_cell3(seed + i32(f32({})), {});", out_varnames["random"], args["seed"], args["cell"]);
+    }
+
+    fn compile_hlsl(&self, args: std::collections::HashMap<&'static str, String>, out_varnames: std::collections::HashMap<&'static str, String>, out: &mut String, _info: &crate::graph::GraphProjectInfo) {
+        let _ = writeln!(out, "\tfloat3 {} = hash_cell3(seed + int({}), {});", out_varnames["random"], args["seed"], args["cell"]);
+    }
+
+}
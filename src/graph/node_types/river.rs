@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use egui::{vec2, Color32, Rect, Rounding, Sense, Stroke};
+
+use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, graph::{GraphProjectInfo, NodeInput, NodeType, Type, Value}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum RiverPath {
+    Spline,
+    Noise
+}
+
+impl RiverPath {
+
+    fn label(&self) -> &'static str {
+        match self {
+            RiverPath::Spline => "Control Points",
+            RiverPath::Noise => "Noise Network",
+        }
+    }
+
+}
+
+const RIVER_MODE_H: f32 = 20.0;
+const RIVER_VIEW_SIZE_H: f32 = 25.0;
+const RIVER_POINT_RADIUS: f32 = 4.0;
+// Straight segments each span of the spline is made of
+const RIVER_SPLINE_SEGMENTS: usize = 8;
+
+// Catmull-Rom spline through the control points, as a polyline. The end points are repeated so the spline reaches them.
+fn catmull_rom(points: &[[f32; 2]]) -> Vec<[f32; 2]> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let point = |idx: isize| glam::Vec2::from(points[idx.clamp(0, points.len() as isize - 1) as usize]);
+    let mut out = vec![points[0]];
+    for i in 0..points.len() as isize - 1 {
+        let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+        for step in 1..=RIVER_SPLINE_SEGMENTS {
+            let t = step as f32 / RIVER_SPLINE_SEGMENTS as f32;
+            let p = 0.5 * (2.0 * p1 + (p2 - p0) * t + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t);
+            out.push(p.into());
+        }
+    }
+    out
+}
+
+pub struct RiverTerrain {
+    terrain: NodeInput,
+    width: NodeInput,
+    depth: NodeInput,
+    smoothness: NodeInput,
+    scale: NodeInput,
+    path: RiverPath,
+    // control points on the xz plane, in world units
+    points: Vec<[f32; 2]>,
+    // width of the area shown by the control point editor
+    view_size: f32,
+
+    // Editor data
+    selected_point: Option<usize>
+}
+
+impl RiverTerrain {
+
+    fn compile(&self, args: &HashMap<&'static str, String>, out_varnames: &HashMap<&'static str, String>, out: &mut String, var_decl: &str, let_decl: &str, vec2: &str) {
+        let dist = format!("{}_dist", out_varnames["terrain"]);
+        match self.path {
+            RiverPath::Spline => {
+                let _ = writeln!(out, "\t{} {} = 1000000.0;", var_decl, dist);
+                let points = catmull_rom(&self.points);
+                for (i, a) in points.iter().enumerate() {
+                    let b = points.get(i + 1).unwrap_or(a);
+                    if i > 0 && b == a {
+                        continue;
+                    }
+                    let _ = writeln!(out, "\t{0} = min({0}, sd_segment_2d(pos.xz, {1}({2:?}, {3:?}), {1}({4:?}, {5:?})));", dist, vec2, a[0], a[1], b[0], b[1]);
+                }
+            },
+            RiverPath::Noise => {
+                let _ = writeln!(out, "\t{} {} = river_noise_distance(seed, pos, {});", let_decl, dist, args["scale"]);
+            }
+        }
+        let _ = writeln!(out, "\t{} {} = river_mask({}, {}, {});", let_decl, out_varnames["water"], dist, args["width"], args["smoothness"]);
+    }
+
+    fn point_editor(&mut self, ui: &mut egui::Ui) {
+        let rect = Rect::from_min_size(ui.cursor().min + vec2(PARAM_H_MARGIN, 0.0), vec2(PARAM_SIZE.x, PARAM_SIZE.x));
+        let resp = ui.allocate_rect(rect, Sense::click_and_drag());
+
+        let view_size = self.view_size.max(1.0);
+        let to_screen = |p: [f32; 2]| rect.center() + vec2(p[0], p[1]) * rect.width() / view_size;
+        let from_screen = |pos: egui::Pos2| {
+            let p = (pos - rect.center()) * view_size / rect.width();
+            [p.x, p.y]
+        };
+        let point_at = |points: &[[f32; 2]], pos: egui::Pos2| points.iter().position(|p| to_screen(*p).distance(pos) < 2.0 * RIVER_POINT_RADIUS);
+
+        // editing
+        if resp.drag_started() || resp.secondary_clicked() {
+            self.selected_point = resp.interact_pointer_pos().and_then(|pos| point_at(&self.points, pos));
+        }
+        if resp.dragged() {
+            if let (Some(idx), Some(pos)) = (self.selected_point, resp.interact_pointer_pos()) {
+                if let Some(point) = self.points.get_mut(idx) {
+                    *point = from_screen(rect.clamp(pos));
+                }
+            }
+        }
+        if resp.double_clicked() {
+            if let Some(pos) = resp.interact_pointer_pos() {
+                if point_at(&self.points, pos).is_none() {
+                    self.points.push(from_screen(pos));
+                }
+            }
+        }
+        resp.context_menu(|ui| {
+            let Some(idx) = self.selected_point.filter(|idx| *idx < self.points.len()) else {
+                ui.label("Double click to add a control point.");
+                return;
+            };
+            if ui.button("Delete").clicked() {
+                self.points.remove(idx);
+                self.selected_point = None;
+                ui.close_menu();
+            }
+        });
+
+        // drawing
+        let painter = ui.painter().with_clip_rect(rect);
+        painter.rect(rect, Rounding::same(2.0), Color32::from_gray(20), Stroke::new(1.0, Color32::from_gray(50)));
+        painter.hline(rect.x_range(), rect.center().y, Stroke::new(1.0, Color32::from_gray(35)));
+        painter.vline(rect.center().x, rect.y_range(), Stroke::new(1.0, Color32::from_gray(35)));
+        let spline = catmull_rom(&self.points).into_iter().map(to_screen).collect();
+        painter.add(egui::Shape::line(spline, Stroke::new(2.0, Color32::from_rgb(70, 130, 220))));
+        for (idx, point) in self.points.iter().map(|p| to_screen(*p)).enumerate() {
+            let color = if self.selected_point == Some(idx) { Color32::WHITE } else { Color32::from_gray(180) };
+            painter.circle_filled(point, RIVER_POINT_RADIUS, color);
+        }
+
+        ui.horizontal(|ui| {
+            ui.add_space(PARAM_H_MARGIN);
+            ui.label("View Size");
+            ui.add(egui::DragValue::new(&mut self.view_size).speed(1.0).range(1.0..=f32::INFINITY));
+        });
+    }
+
+}
+
+impl NodeType for RiverTerrain {
+
+    const LABEL: &'static str = "River Terrain";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            width: Value::scalar(8.0).into(),
+            depth: Value::scalar(6.0).into(),
+            smoothness: Value::scalar(4.0).into(),
+            scale: Value::scalar(1.0).into(),
+            path: RiverPath::Spline,
+            points: vec![[-100.0, 0.0], [100.0, 0.0]],
+            view_size: 256.0,
+            selected_point: None
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("width", Type::Scalar, &self.width),
+            ("depth", Type::Scalar, &self.depth),
+            ("smoothness", Type::Scalar, &self.smoothness),
+            ("scale", Type::Scalar, &self.scale),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("width", Type::Scalar, &mut self.width),
+            ("depth", Type::Scalar, &mut self.depth),
+            ("smoothness", Type::Scalar, &mut self.smoothness),
+            ("scale", Type::Scalar, &mut self.scale),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("terrain", Type::Terrain),
+            ("water", Type::Scalar)
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        self.compile(&args, &out_varnames, out, "var", "let", "vec2");
+        let _ = writeln!(out, "\tlet {} = terrain_erode({}, {} * {});", out_varnames["terrain"], args["terrain"], args["depth"], out_varnames["water"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        self.compile(&args, &out_varnames, out, "float", "float", "float2");
+        let _ = writeln!(out, "\tTerrain {} = terrain_erode({}, {} * {});", out_varnames["terrain"], args["terrain"], args["depth"], out_varnames["water"]);
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        match self.path {
+            RiverPath::Spline => RIVER_MODE_H + PARAM_SIZE.x + RIVER_VIEW_SIZE_H,
+            RiverPath::Noise => RIVER_MODE_H,
+        }
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, _info: &GraphProjectInfo) {
+        ui.horizontal(|ui| {
+            ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+            egui::ComboBox::new("river_path", "")
+                .selected_text(self.path.label())
+                .width(100.0)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.path, RiverPath::Spline, RiverPath::Spline.label());
+                    ui.selectable_value(&mut self.path, RiverPath::Noise, RiverPath::Noise.label());
+                });
+        });
+        if self.path == RiverPath::Spline {
+            self.point_editor(ui);
+        }
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path,
+            "points": self.points,
+            "view_size": self.view_size
+        })
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        let Some(data) = data.as_object() else { return; };
+        if let Some(path) = data.get("path").and_then(|path| serde_json::from_value(path.clone()).ok()) {
+            self.path = path;
+        }
+        if let Some(points) = data.get("points").and_then(|points| serde_json::from_value(points.clone()).ok()) {
+            self.points = points;
+        }
+        if let Some(view_size) = data.get("view_size").and_then(|view_size| view_size.as_f64()) {
+            self.view_size = view_size as f32;
+        }
+    }
+
+}
@@ -1,6 +1,6 @@
 
-use std::collections::HashMap;
-use crate::graph::{GraphProjectInfo, NodeInput, NodeType, Type, Value};
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use crate::{app::graph::ui::{PARAM_H_MARGIN, PARAM_SIZE}, compiler::{CompilationTarget, BIOME_RULE_INPUTS, MATERIAL_LAYERS}, graph::{GraphProjectInfo, NodeInput, NodeType, Resample, Type, Value}};
 use std::fmt::Write;
 
 pub struct HeightmapTerrain {
@@ -169,8 +169,26 @@ impl NodeType for SpaghettiCaveTerrain {
 
 }
 
+const MATERIAL_LAYER_NAMES: [&str; MATERIAL_LAYERS] = ["layer 1", "layer 2", "layer 3", "layer 4"];
+const MATERIAL_LAYER_H: f32 = 20.0;
+const BIOME_RULE_NAMES: [&str; BIOME_RULE_INPUTS] = ["biome rule 1", "biome rule 2"];
+
+// Each material layer paints its texture over the biome textures wherever its mask is above 0.
+// Layers without a texture are ignored.
+// The biome rule inputs are values that biome rules can compare against.
 pub struct TerrainOutput {
-    pub terrain: NodeInput
+    pub terrain: NodeInput,
+    pub layer_masks: [NodeInput; MATERIAL_LAYERS],
+    pub layer_textures: [PathBuf; MATERIAL_LAYERS],
+    pub rule_inputs: [NodeInput; BIOME_RULE_INPUTS]
+}
+
+impl TerrainOutput {
+
+    fn active_layers(&self) -> impl Iterator<Item = (usize, &PathBuf)> {
+        self.layer_textures.iter().enumerate().filter(|(_, texture)| !texture.as_os_str().is_empty())
+    }
+
 }
 
 impl NodeType for TerrainOutput {
@@ -180,27 +198,105 @@ impl NodeType for TerrainOutput {
     fn make() -> Self {
         Self {
             terrain: Value::terrain().into(),
+            layer_masks: std::array::from_fn(|_| Value::scalar(0.0).into()),
+            layer_textures: std::array::from_fn(|_| PathBuf::new()),
+            rule_inputs: std::array::from_fn(|_| Value::scalar(0.0).into())
         }
     }
     
     fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
-        vec![("terrain", Type::Terrain, &self.terrain)]
+        let mut inputs = vec![("terrain", Type::Terrain, &self.terrain)];
+        for (name, mask) in MATERIAL_LAYER_NAMES.iter().zip(self.layer_masks.iter()) {
+            inputs.push((name, Type::Scalar, mask));
+        }
+        for (name, rule_input) in BIOME_RULE_NAMES.iter().zip(self.rule_inputs.iter()) {
+            inputs.push((name, Type::Scalar, rule_input));
+        }
+        inputs
     }
 
     fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
-        vec![("terrain", Type::Terrain, &mut self.terrain)]
+        let mut inputs = vec![("terrain", Type::Terrain, &mut self.terrain)];
+        for (name, mask) in MATERIAL_LAYER_NAMES.iter().zip(self.layer_masks.iter_mut()) {
+            inputs.push((name, Type::Scalar, mask));
+        }
+        for (name, rule_input) in BIOME_RULE_NAMES.iter().zip(self.rule_inputs.iter_mut()) {
+            inputs.push((name, Type::Scalar, rule_input));
+        }
+        inputs
     }
 
     fn outputs() -> Vec<(&'static str, Type)> {
         vec![]
     }
     
-    fn compile_wgsl(&self, args: HashMap<&'static str, String>, _out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, _out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
         out.push_str(format!("\tterrain_out.terrain = {};\n", args["terrain"]).as_str());
+        for (idx, texture) in self.active_layers() {
+            let _ = writeln!(out, "\tterrain_out.layer_w[{}] = clamp({}, 0.0, 1.0);", idx, args[MATERIAL_LAYER_NAMES[idx]]);
+            let _ = writeln!(out, "\tterrain_out.layer_tex[{}] = {}u;", idx, info.textures.get(texture));
+        }
+        for (idx, name) in BIOME_RULE_NAMES.iter().enumerate() {
+            let _ = writeln!(out, "\tterrain_out.rule_values[{}] = {};", idx, args[name]);
+        }
     }
 
-    fn compile_hlsl(&self, args: HashMap<&'static str, String>, _out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
-        out.push_str(format!("\tterrain_out = {};\n", args["terrain"]).as_str());
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, _out_varnames: HashMap<&'static str, String>, out: &mut String, info: &GraphProjectInfo) {
+        out.push_str(format!("\tterrain_out.terrain = {};\n", args["terrain"]).as_str());
+        for (idx, texture) in self.active_layers() {
+            let Some(material) = info.layer_textures.iter().position(|path| path == texture) else { continue; };
+            let _ = writeln!(out, "\tterrain_out.layer_w[{}] = saturate({});", idx, args[MATERIAL_LAYER_NAMES[idx]]);
+            let _ = writeln!(out, "\tterrain_out.layer_tex[{}] = N_BIOMES<ProjectName> + {};", idx, material);
+        }
+        for (idx, name) in BIOME_RULE_NAMES.iter().enumerate() {
+            let _ = writeln!(out, "\tterrain_out.rule_values[{}] = {};", idx, args[name]);
+        }
+    }
+
+    fn layer_textures(&self) -> Vec<&Path> {
+        self.active_layers().map(|(_, texture)| texture.as_path()).collect()
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        MATERIAL_LAYERS as f32 * MATERIAL_LAYER_H
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, info: &GraphProjectInfo) {
+        for (idx, layer_texture) in self.layer_textures.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+                let selected = if layer_texture.as_os_str().is_empty() {
+                    format!("Layer {}: None", idx + 1)
+                } else {
+                    format!("Layer {}: {}", idx + 1, layer_texture.to_string_lossy())
+                };
+                egui::ComboBox::new(("material_layer", idx), "")
+                    .selected_text(selected)
+                    .width(100.0)
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_label(layer_texture.as_os_str().is_empty(), "None").clicked() {
+                            *layer_texture = PathBuf::new();
+                        }
+                        for path in info.textures.textures.keys() {
+                            if ui.selectable_label(path == layer_texture, path.to_string_lossy()).clicked() {
+                                *layer_texture = path.clone();
+                            }
+                        }
+                    });
+            });
+        }
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "layer_textures": self.layer_textures
+        })
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        if let Some(layer_textures) = data.as_object().and_then(|data| data.get("layer_textures")).and_then(|textures| serde_json::from_value(textures.clone()).ok()) {
+            self.layer_textures = layer_textures;
+        }
     }
 
 }
@@ -288,9 +384,348 @@ impl NodeType for ErodeTerrain {
 
 }
 
+pub struct ThermalErodeTerrain {
+    pub terrain: NodeInput,
+    pub radius: NodeInput,
+    pub talus: NodeInput,
+    pub strength: NodeInput
+}
+
+impl NodeType for ThermalErodeTerrain {
+
+    const LABEL: &'static str = "Thermal Erode Terrain";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            radius: Value::scalar(2.0).into(),
+            talus: Value::scalar(35.0).into(),
+            strength: Value::scalar(1.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("radius", Type::Scalar, &self.radius),
+            ("talus", Type::Scalar, &self.talus),
+            ("strength", Type::Scalar, &self.strength),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("radius", Type::Scalar, &mut self.radius),
+            ("talus", Type::Scalar, &mut self.talus),
+            ("strength", Type::Scalar, &mut self.strength),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("terrain", Type::Terrain)]
+    }
+
+    // The slope is estimated from the terrain's horizontal neighbours
+    fn resample(&self, args: &HashMap<&'static str, String>, target: CompilationTarget) -> Vec<Resample> {
+        let vec3 = match target {
+            CompilationTarget::WGSL => "vec3",
+            CompilationTarget::UnrealHLSL => "float3",
+        };
+        let r = &args["radius"];
+        vec![
+            Resample { input: "terrain", name: "center", pos: "pos".to_owned() },
+            Resample { input: "terrain", name: "px", pos: format!("pos + {}({}, 0.0, 0.0)", vec3, r) },
+            Resample { input: "terrain", name: "nx", pos: format!("pos - {}({}, 0.0, 0.0)", vec3, r) },
+            Resample { input: "terrain", name: "pz", pos: format!("pos + {}(0.0, 0.0, {})", vec3, r) },
+            Resample { input: "terrain", name: "nz", pos: format!("pos - {}(0.0, 0.0, {})", vec3, r) },
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = terrain_thermal_erode({}, {}.sdf, {}.sdf, {}.sdf, {}.sdf, {}, {}, {});", out_varnames["terrain"],
+            args["center"], args["px"], args["nx"], args["pz"], args["nz"], args["radius"], args["talus"], args["strength"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tTerrain {} = terrain_thermal_erode({}, {}.sdf, {}.sdf, {}.sdf, {}.sdf, {}, {}, {});", out_varnames["terrain"],
+            args["center"], args["px"], args["nx"], args["pz"], args["nz"], args["radius"], args["talus"], args["strength"]);
+    }
+
+}
+
+pub struct TerraceTerrain {
+    pub terrain: NodeInput,
+    pub step: NodeInput,
+    pub sharpness: NodeInput
+}
+
+impl NodeType for TerraceTerrain {
+
+    const LABEL: &'static str = "Terrace Terrain";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            step: Value::scalar(10.0).into(),
+            sharpness: Value::scalar(0.8).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("step", Type::Scalar, &self.step),
+            ("sharpness", Type::Scalar, &self.sharpness),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("step", Type::Scalar, &mut self.step),
+            ("sharpness", Type::Scalar, &mut self.sharpness),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("terrain", Type::Terrain)]
+    }
+
+    fn resample(&self, args: &HashMap<&'static str, String>, _target: CompilationTarget) -> Vec<Resample> {
+        vec![Resample {
+            input: "terrain",
+            name: "terraced",
+            pos: format!("terrace_pos(pos, {}, {})", args["step"], args["sharpness"])
+        }]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = {};", out_varnames["terrain"], args["terraced"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tTerrain {} = {};", out_varnames["terrain"], args["terraced"]);
+    }
+
+}
+
+pub struct StrataTerrain {
+    pub terrain: NodeInput,
+    pub thickness: NodeInput,
+    pub strength: NodeInput,
+    pub scale: NodeInput
+}
+
+impl NodeType for StrataTerrain {
+
+    const LABEL: &'static str = "Strata Terrain";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            thickness: Value::scalar(4.0).into(),
+            strength: Value::scalar(2.0).into(),
+            scale: Value::scalar(1.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("thickness", Type::Scalar, &self.thickness),
+            ("strength", Type::Scalar, &self.strength),
+            ("scale", Type::Scalar, &self.scale),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("thickness", Type::Scalar, &mut self.thickness),
+            ("strength", Type::Scalar, &mut self.strength),
+            ("scale", Type::Scalar, &mut self.scale),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("terrain", Type::Terrain)]
+    }
+
+    fn resample(&self, args: &HashMap<&'static str, String>, _target: CompilationTarget) -> Vec<Resample> {
+        vec![Resample {
+            input: "terrain",
+            name: "displaced",
+            pos: format!("strata_pos(seed, pos, {}, {}, {})", args["thickness"], args["strength"], args["scale"])
+        }]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = {};", out_varnames["terrain"], args["displaced"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tTerrain {} = {};", out_varnames["terrain"], args["displaced"]);
+    }
+
+}
+
+pub struct OverhangTerrain {
+    pub terrain: NodeInput,
+    pub direction: NodeInput,
+    pub amount: NodeInput,
+    pub base: NodeInput,
+    pub height: NodeInput
+}
+
+impl NodeType for OverhangTerrain {
+
+    const LABEL: &'static str = "Overhang Terrain";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            direction: Value::vector(1.0, 0.0, 0.0).into(),
+            amount: Value::scalar(10.0).into(),
+            base: Value::scalar(0.0).into(),
+            height: Value::scalar(50.0).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("direction", Type::Vector, &self.direction),
+            ("amount", Type::Scalar, &self.amount),
+            ("base", Type::Scalar, &self.base),
+            ("height", Type::Scalar, &self.height),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("direction", Type::Vector, &mut self.direction),
+            ("amount", Type::Scalar, &mut self.amount),
+            ("base", Type::Scalar, &mut self.base),
+            ("height", Type::Scalar, &mut self.height),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("terrain", Type::Terrain)]
+    }
+
+    fn resample(&self, args: &HashMap<&'static str, String>, _target: CompilationTarget) -> Vec<Resample> {
+        vec![Resample {
+            input: "terrain",
+            name: "displaced",
+            pos: format!("overhang_pos(pos, {}, {}, {}, {})", args["direction"], args["amount"], args["base"], args["height"])
+        }]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = {};", out_varnames["terrain"], args["displaced"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tTerrain {} = {};", out_varnames["terrain"], args["displaced"]);
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum BlendMode {
+    Hard,
+    Smooth,
+    Polynomial,
+    Exponential,
+    Chamfer,
+    Stairs
+}
+
+impl BlendMode {
+
+    const ALL: [BlendMode; 6] = [BlendMode::Hard, BlendMode::Smooth, BlendMode::Polynomial, BlendMode::Exponential, BlendMode::Chamfer, BlendMode::Stairs];
+
+    fn label(&self) -> &'static str {
+        match self {
+            BlendMode::Hard => "Hard",
+            BlendMode::Smooth => "Smooth",
+            BlendMode::Polynomial => "Polynomial",
+            BlendMode::Exponential => "Exponential",
+            BlendMode::Chamfer => "Chamfer",
+            BlendMode::Stairs => "Stairs",
+        }
+    }
+
+    // name of the shader function blending two sdfs with this mode, shared by both targets
+    fn min_fn(&self) -> &'static str {
+        match self {
+            BlendMode::Hard => "min",
+            BlendMode::Smooth => "smoothmin",
+            BlendMode::Polynomial => "polysmoothmin",
+            BlendMode::Exponential => "expsmoothmin",
+            BlendMode::Chamfer => "chamfermin",
+            BlendMode::Stairs => "stairsmin",
+        }
+    }
+
+    fn max_fn(&self) -> &'static str {
+        match self {
+            BlendMode::Hard => "max",
+            BlendMode::Smooth => "smoothmax",
+            BlendMode::Polynomial => "polysmoothmax",
+            BlendMode::Exponential => "expsmoothmax",
+            BlendMode::Chamfer => "chamfermax",
+            BlendMode::Stairs => "stairsmax",
+        }
+    }
+
+    // the blend radius argument of the blend function, hard blends don't take one
+    fn radius_arg(&self, k: &str) -> String {
+        match self {
+            BlendMode::Hard => String::new(),
+            _ => format!(", {}", k)
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_centered(|ui| {
+            ui.add_space((PARAM_SIZE.x - 100.0) / 2.0 + PARAM_H_MARGIN);
+            egui::ComboBox::new("blend_mode", "")
+                .selected_text(self.label())
+                .width(100.0)
+                .show_ui(ui, |ui| {
+                    for mode in BlendMode::ALL {
+                        if ui.selectable_label(*self == mode, mode.label()).clicked() {
+                            *self = mode;
+                        }
+                    }
+                });
+        });
+    }
+
+    fn serialize(&self) -> serde_json::Value {
+        serde_json::json!({
+            "blend_mode": self
+        })
+    }
+
+    fn deserialize(&mut self, data: &serde_json::Value) {
+        if let Some(mode) = data.as_object().and_then(|data| data.get("blend_mode")).and_then(|mode| serde_json::from_value(mode.clone()).ok()) {
+            *self = mode;
+        }
+    }
+
+}
+
 pub struct TerrainUnion {
     pub a: NodeInput,
     pub b: NodeInput,
+    pub blend: NodeInput,
+    pub mode: BlendMode
 }
 
 impl NodeType for TerrainUnion {
@@ -301,6 +736,8 @@ impl NodeType for TerrainUnion {
         Self {
             a: Value::terrain().into(),
             b: Value::terrain().into(),
+            blend: Value::scalar(0.5).into(),
+            mode: BlendMode::Smooth
         }
     }
 
@@ -308,6 +745,7 @@ impl NodeType for TerrainUnion {
         vec![
             ("a", Type::Terrain, &self.a),
             ("b", Type::Terrain, &self.b),
+            ("blend", Type::Scalar, &self.blend),
         ]
     }
 
@@ -315,6 +753,7 @@ impl NodeType for TerrainUnion {
         vec![
             ("a", Type::Terrain, &mut self.a),
             ("b", Type::Terrain, &mut self.b),
+            ("blend", Type::Scalar, &mut self.blend),
         ]
     }
 
@@ -323,11 +762,27 @@ impl NodeType for TerrainUnion {
     }
 
     fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
-        out.push_str(format!("\tlet {} = terrain_union({}, {});\n", out_varnames["terrain"], args["a"], args["b"]).as_str());
+        let _ = writeln!(out, "\tlet {} = blend_terrains({}, {}, {}({}.sdf, {}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.min_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
     }
 
     fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
-        out.push_str(format!("\tTerrain {} = terrain_union({}, {});\n", out_varnames["terrain"], args["a"], args["b"]).as_str());
+        let _ = writeln!(out, "\tTerrain {} = blend_terrains({}, {}, {}({}.sdf, {}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.min_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        13.0
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, _info: &GraphProjectInfo) {
+        self.mode.ui(ui);
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        self.mode.serialize()
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        self.mode.deserialize(data);
     }
 
 }
@@ -335,6 +790,8 @@ impl NodeType for TerrainUnion {
 pub struct TerrainIntersection {
     pub a: NodeInput,
     pub b: NodeInput,
+    pub blend: NodeInput,
+    pub mode: BlendMode
 }
 
 impl NodeType for TerrainIntersection {
@@ -345,6 +802,8 @@ impl NodeType for TerrainIntersection {
         Self {
             a: Value::terrain().into(),
             b: Value::terrain().into(),
+            blend: Value::scalar(0.5).into(),
+            mode: BlendMode::Smooth
         }
     }
 
@@ -352,6 +811,7 @@ impl NodeType for TerrainIntersection {
         vec![
             ("a", Type::Terrain, &self.a),
             ("b", Type::Terrain, &self.b),
+            ("blend", Type::Scalar, &self.blend),
         ]
     }
 
@@ -359,6 +819,7 @@ impl NodeType for TerrainIntersection {
         vec![
             ("a", Type::Terrain, &mut self.a),
             ("b", Type::Terrain, &mut self.b),
+            ("blend", Type::Scalar, &mut self.blend),
         ]
     }
 
@@ -367,11 +828,93 @@ impl NodeType for TerrainIntersection {
     }
 
     fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
-        out.push_str(format!("\tlet {} = terrain_intersect({}, {});\n", out_varnames["terrain"], args["a"], args["b"]).as_str());
+        let _ = writeln!(out, "\tlet {} = blend_terrains({}, {}, {}({}.sdf, {}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.max_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
     }
 
     fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
-        out.push_str(format!("\tTerrain {} = terrain_intersect({}, {});\n", out_varnames["terrain"], args["a"], args["b"]).as_str());
+        let _ = writeln!(out, "\tTerrain {} = blend_terrains({}, {}, {}({}.sdf, {}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.max_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        13.0
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, _info: &GraphProjectInfo) {
+        self.mode.ui(ui);
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        self.mode.serialize()
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        self.mode.deserialize(data);
+    }
+
+}
+
+pub struct TerrainSubtract {
+    pub a: NodeInput,
+    pub b: NodeInput,
+    pub blend: NodeInput,
+    pub mode: BlendMode
+}
+
+impl NodeType for TerrainSubtract {
+
+    const LABEL: &'static str = "Terrain Subtract";
+
+    fn make() -> Self {
+        Self {
+            a: Value::terrain().into(),
+            b: Value::terrain().into(),
+            blend: Value::scalar(0.5).into(),
+            mode: BlendMode::Smooth
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("a", Type::Terrain, &self.a),
+            ("b", Type::Terrain, &self.b),
+            ("blend", Type::Scalar, &self.blend),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("a", Type::Terrain, &mut self.a),
+            ("b", Type::Terrain, &mut self.b),
+            ("blend", Type::Scalar, &mut self.blend),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![("terrain", Type::Terrain)]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = blend_terrains({}, {}, {}({}.sdf, -{}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.max_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tTerrain {} = blend_terrains({}, {}, {}({}.sdf, -{}.sdf{}));", out_varnames["terrain"], args["a"], args["b"], self.mode.max_fn(), args["a"], args["b"], self.mode.radius_arg(&args["blend"]));
+    }
+
+    fn custom_ui_height(&self) -> f32 {
+        13.0
+    }
+
+    fn custom_ui(&mut self, ui: &mut egui::Ui, _info: &GraphProjectInfo) {
+        self.mode.ui(ui);
+    }
+
+    fn custom_serialize(&self) -> serde_json::Value {
+        self.mode.serialize()
+    }
+
+    fn custom_deserialize(&mut self, data: &serde_json::Value) {
+        self.mode.deserialize(data);
     }
 
 }
@@ -444,4 +987,75 @@ impl NodeType for SDFToTerrain {
         let _ = writeln!(out, "\tTerrain {};", out_varnames["terrain"]);
         let _ = writeln!(out, "\t{}.sdf = {};", out_varnames["terrain"], args["sdf"]);
     }
-}
\ No newline at end of file
+}
+
+pub struct TerrainGradient {
+    pub terrain: NodeInput,
+    pub epsilon: NodeInput
+}
+
+impl NodeType for TerrainGradient {
+
+    const LABEL: &'static str = "Terrain Gradient";
+
+    fn make() -> Self {
+        Self {
+            terrain: Value::terrain().into(),
+            epsilon: Value::scalar(0.5).into(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&'static str, Type, &NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &self.terrain),
+            ("epsilon", Type::Scalar, &self.epsilon),
+        ]
+    }
+
+    fn inputs_mut(&mut self) -> Vec<(&'static str, Type, &mut NodeInput)> {
+        vec![
+            ("terrain", Type::Terrain, &mut self.terrain),
+            ("epsilon", Type::Scalar, &mut self.epsilon),
+        ]
+    }
+
+    fn outputs() -> Vec<(&'static str, Type)> {
+        vec![
+            ("gradient", Type::Vector),
+            ("normal", Type::Vector),
+            ("slope", Type::Scalar),
+        ]
+    }
+
+    // Central differences along every axis
+    fn resample(&self, args: &HashMap<&'static str, String>, target: CompilationTarget) -> Vec<Resample> {
+        let vec3 = match target {
+            CompilationTarget::WGSL => "vec3",
+            CompilationTarget::UnrealHLSL => "float3",
+        };
+        let e = &args["epsilon"];
+        vec![
+            Resample { input: "terrain", name: "px", pos: format!("pos + {}({}, 0.0, 0.0)", vec3, e) },
+            Resample { input: "terrain", name: "nx", pos: format!("pos - {}({}, 0.0, 0.0)", vec3, e) },
+            Resample { input: "terrain", name: "py", pos: format!("pos + {}(0.0, {}, 0.0)", vec3, e) },
+            Resample { input: "terrain", name: "ny", pos: format!("pos - {}(0.0, {}, 0.0)", vec3, e) },
+            Resample { input: "terrain", name: "pz", pos: format!("pos + {}(0.0, 0.0, {})", vec3, e) },
+            Resample { input: "terrain", name: "nz", pos: format!("pos - {}(0.0, 0.0, {})", vec3, e) },
+        ]
+    }
+
+    fn compile_wgsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tlet {} = vec3({}.sdf - {}.sdf, {}.sdf - {}.sdf, {}.sdf - {}.sdf) / (2.0 * dezero({}));", out_varnames["gradient"],
+            args["px"], args["nx"], args["py"], args["ny"], args["pz"], args["nz"], args["epsilon"]);
+        let _ = writeln!(out, "\tlet {} = {} / max(length({}), 0.00001);", out_varnames["normal"], out_varnames["gradient"], out_varnames["gradient"]);
+        let _ = writeln!(out, "\tlet {} = degrees(acos(clamp({}.y, -1.0, 1.0)));", out_varnames["slope"], out_varnames["normal"]);
+    }
+
+    fn compile_hlsl(&self, args: HashMap<&'static str, String>, out_varnames: HashMap<&'static str, String>, out: &mut String, _info: &GraphProjectInfo) {
+        let _ = writeln!(out, "\tfloat3 {} = float3({}.sdf - {}.sdf, {}.sdf - {}.sdf, {}.sdf - {}.sdf) / (2.0 * dezero({}));", out_varnames["gradient"],
+            args["px"], args["nx"], args["py"], args["ny"], args["pz"], args["nz"], args["epsilon"]);
+        let _ = writeln!(out, "\tfloat3 {} = {} / max(length({}), 0.00001);", out_varnames["normal"], out_varnames["gradient"], out_varnames["gradient"]);
+        let _ = writeln!(out, "\tfloat {} = degrees(acos(clamp({}.y, -1.0, 1.0)));", out_varnames["slope"], out_varnames["normal"]);
+    }
+
+}
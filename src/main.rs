@@ -5,6 +5,7 @@ mod app;
 mod project;
 mod biome;
 mod compiler; 
+mod cli;
 pub mod util;
 
 use app::App;
@@ -12,6 +13,14 @@ use app::App;
 fn main() {
 
     let args: Vec<_> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("export-maps") {
+        if let Err(err) = cli::export_maps(&args[2..]) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let proj_path = args[1].clone();
     let texture_path = args.get(2).unwrap_or(&proj_path).clone();
 
@@ -1,19 +1,31 @@
 
+use std::{collections::BTreeSet, path::PathBuf};
+
 use serde_json::json;
 
-use crate::{biome::Biomes, graph::TerrainGraph};
+use crate::{biome::Biomes, graph::TerrainGraph, terrain::meshgen::MeshSettings};
 
 pub struct Project {
     pub terrain_graph: TerrainGraph,
-    pub biomes: Biomes
+    pub biomes: Biomes,
+    pub meshing: MeshSettings,
+    // chunk meshes are cached in the project directory, so reopening it doesn't mesh them again
+    pub cache_chunks_on_disk: bool
 }
 
 impl Project {
 
+    // the heightmap images sampled by the project graph and the biome graphs
+    pub fn heightmaps(&self) -> BTreeSet<PathBuf> {
+        referenced_heightmaps(&self.terrain_graph, &self.biomes)
+    }
+
     pub fn to_json(&self) -> serde_json::Value {
         json!({
             "graph": self.terrain_graph.to_json(),
-            "biomes": self.biomes.to_json()
+            "biomes": self.biomes.to_json(),
+            "meshing": self.meshing,
+            "cache_chunks_on_disk": self.cache_chunks_on_disk
         })
     }
 
@@ -31,6 +43,30 @@ impl Project {
 
         self.terrain_graph = graph;
         self.biomes = biomes;
+        self.meshing = data.get("meshing").and_then(|meshing| serde_json::from_value(meshing.clone()).ok()).unwrap_or_default();
+        self.cache_chunks_on_disk = data.get("cache_chunks_on_disk").and_then(|cache| cache.as_bool()).unwrap_or(false);
+        // projects from before chunk and voxel size were settings only stored the mesher
+        if let Some(mesher) = data.get("mesher").and_then(|mesher| serde_json::from_value(mesher.clone()).ok()) {
+            self.meshing.mesher = mesher;
+        }
     }
 
 }
+
+pub fn referenced_layer_textures(graph: &TerrainGraph, biomes: &Biomes) -> BTreeSet<PathBuf> {
+    biomes.biomes.iter()
+        .filter_map(|biome| biome.graph.as_ref())
+        .chain(std::iter::once(graph))
+        .flat_map(|graph| graph.layer_textures())
+        .map(|path| path.to_path_buf())
+        .collect()
+}
+
+pub fn referenced_heightmaps(graph: &TerrainGraph, biomes: &Biomes) -> BTreeSet<PathBuf> {
+    biomes.biomes.iter()
+        .filter_map(|biome| biome.graph.as_ref())
+        .chain(std::iter::once(graph))
+        .flat_map(|graph| graph.heightmaps())
+        .map(|path| path.to_path_buf())
+        .collect()
+}
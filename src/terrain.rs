@@ -1,54 +1,116 @@
 
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::{collections::HashMap, sync::{Arc, Mutex, OnceLock}};
 
 use glam::Vec3;
 use eframe::egui_wgpu::wgpu;
+use meshgen::MeshSettings;
 
 pub mod renderer;
 pub mod meshgen;
 pub mod texture_atlas;
 pub mod biome_preview;
+pub mod map_export;
 
+// Number of textures blended at each vertex. The vertex stores them in a single vec4, so at most 4.
+pub const MATERIAL_SLOTS: usize = 4;
+
+// The renderer reads it from a storage buffer as 32 bit words, see VERTEX_WORDS in renderer/shader.wgsl
 #[repr(C)]
 #[derive(Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct TerrainVertex {
     pos: Vec3,
     norm: Vec3,
-    mat1: u32,
-    mat2: u32,
-    mat_weight: f32
+    mats: [u32; 4],
+    mat_weights: [f32; 4],
+    // the heaviest biomes at the vertex, for biome shading in the viewport
+    biomes: [u32; 4],
+    biome_weights: [f32; 4]
+}
+
+// A node of the chunk octree. It has chunk_size voxels along each axis, each 2^lod voxel sizes wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub loc: glam::I64Vec3,
+    pub lod: u32
 }
 
-impl TerrainVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Uint32, 3 => Uint32, 4 => Float32];
+impl ChunkKey {
+
+    pub fn scale(&self, settings: &MeshSettings) -> f32 {
+        settings.voxel_size * (1u64 << self.lod) as f32
+    }
+
+    pub fn size(&self, settings: &MeshSettings) -> f32 {
+        settings.chunk_size as f32 * self.scale(settings)
+    }
 
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
+    pub fn begin(&self, settings: &MeshSettings) -> Vec3 {
+        self.loc.as_vec3() * self.size(settings)
+    }
 
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::ATTRIBS,
+    pub fn center(&self, settings: &MeshSettings) -> Vec3 {
+        self.begin(settings) + Vec3::splat(self.size(settings) / 2.0)
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = ChunkKey> + '_ {
+        (0..8).map(|i| ChunkKey {
+            loc: self.loc * 2 + glam::i64vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1),
+            lod: self.lod - 1
+        })
+    }
+
+    // whether the two chunks cover some of the same space
+    pub fn overlaps(&self, other: &ChunkKey) -> bool {
+        if self.lod >= other.lod {
+            (other.loc >> (self.lod - other.lod) as i64) == self.loc
+        } else {
+            (self.loc >> (other.lod - self.lod) as i64) == other.loc
         }
     }
 
 }
 
-pub const CHUNK_SIZE: u32 = 64;
+// Vertices are shared between the triangles around them
+struct ChunkMesh {
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    // made by the renderer the first time the mesh is drawn
+    render_bind_group: OnceLock<wgpu::BindGroup>
+}
+
+impl ChunkMesh {
+
+    fn tris(&self) -> u32 {
+        (self.indices.size() / (3 * std::mem::size_of::<u32>() as u64)) as u32
+    }
+
+}
 
 struct TerrainChunk {
-    mesh: Option<wgpu::Buffer>,
+    // shared with the mesh cache
+    mesh: Option<Arc<ChunkMesh>>,
     tris: u32,
+    // chunk faces with skirts, bits are -x, +x, -y, +y, -z, +z
+    skirts: u32,
+    center: Vec3,
+    // octants already covered by finer chunks replacing this one, bits are the indices of ChunkKey::children
+    hidden_octants: u32,
+    // the mesh was too large for the device's buffers
+    too_large: bool,
     tri_counter: Arc<Mutex<u64>>
 }
 
 impl TerrainChunk {
 
-    pub(crate) fn new(mesh: Option<wgpu::Buffer>, tris: u32, tri_counter: Arc<Mutex<u64>>) -> Self {
+    pub(crate) fn new(mesh: Option<Arc<ChunkMesh>>, tris: u32, skirts: u32, center: Vec3, tri_counter: Arc<Mutex<u64>>) -> Self {
         *tri_counter.lock().unwrap() += tris as u64;
         Self {
             mesh,
             tris,
+            skirts,
+            center,
+            hidden_octants: 0,
+            too_large: false,
             tri_counter,
         }
     }
@@ -63,8 +125,27 @@ impl Drop for TerrainChunk {
 
 }
 
+// The box of the world shown in the viewport and where it's seen from
+pub struct TerrainView {
+    pub center: Vec3,
+    pub size: f32,
+    pub cam_pos: Vec3
+}
+
+impl TerrainView {
+
+    pub fn min(&self) -> Vec3 {
+        self.center - Vec3::splat(self.size / 2.0)
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.center + Vec3::splat(self.size / 2.0)
+    }
+
+}
+
 pub struct Terrain {
-    chunks: HashMap<glam::I64Vec3, TerrainChunk>
+    chunks: HashMap<ChunkKey, TerrainChunk>
 }
 
 impl Terrain {
@@ -79,4 +160,9 @@ impl Terrain {
         self.chunks.clear();
     }
 
+    // chunks left empty because their meshes didn't fit in a buffer
+    pub fn too_large_chunks(&self) -> usize {
+        self.chunks.values().filter(|chunk| chunk.too_large).count()
+    }
+
 }
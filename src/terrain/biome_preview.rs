@@ -1,22 +1,46 @@
 
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
 use eframe::wgpu::{self, util::DeviceExt};
 
 pub struct BiomePreviewRenderer {
     pipeline: wgpu::RenderPipeline,
     uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup
+    uniform_bind_group: wgpu::BindGroup,
+
+    inspect_pipeline: wgpu::ComputePipeline,
+    inspect_bind_group: wgpu::BindGroup,
+    inspect_pos_buffer: wgpu::Buffer,
+    inspect_buffer: wgpu::Buffer,
+    inspect_read_buffer: wgpu::Buffer,
+    inspect_len: usize,
+    // position being read back and whether it's mapped yet
+    inspect_pending: Option<(glam::Vec3, Arc<AtomicBool>)>,
+    // the last values read back and where they were evaluated
+    inspect_result: Option<(glam::Vec3, Vec<f32>)>
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
 struct Uniforms {
+    center: [f32; 2],
+    scale: f32,
     aspect: f32,
-    depth: f32
+    depth: f32,
+    _padding: f32
+}
+
+// The part of the world shown by the preview
+pub struct BiomePreviewView {
+    pub center: glam::Vec2,
+    pub scale: f32,
+    pub aspect: f32,
+    pub depth: f32
 }
 
 impl BiomePreviewRenderer {
 
-    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, biome_preview_code: String) -> Self {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, biome_preview_code: String, inspect_len: usize) -> Self {
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("biome_preview"),
@@ -27,8 +51,11 @@ impl BiomePreviewRenderer {
             label: Some("biome_uniform_buffer"),
             contents: bytemuck::cast_slice(&[
                 Uniforms {
+                    center: [0.0, 0.0],
+                    scale: 1000.0,
                     aspect: 1.0,
                     depth: 0.0,
+                    _padding: 0.0
                 }
             ]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
@@ -39,7 +66,7 @@ impl BiomePreviewRenderer {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -112,22 +139,160 @@ impl BiomePreviewRenderer {
             multiview: None,
         });
 
+        let inspect_pos_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("biome_inspect_pos_buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let inspect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("biome_inspect_buffer"),
+            size: (inspect_len * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false
+        });
+
+        let inspect_read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("biome_inspect_read_buffer"),
+            size: (inspect_len * 4) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let inspect_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("biome_inspect_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                }
+            ],
+        });
+
+        let inspect_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("biome_inspect_bind_group"),
+            layout: &inspect_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: inspect_buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: inspect_pos_buffer.as_entire_binding()
+                }
+            ]
+        });
+
+        let inspect_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("biome_inspect_pipeline_layout"),
+            bind_group_layouts: &[&bind_ground_layout, &inspect_bind_group_layout],
+            push_constant_ranges: &[]
+        });
+
+        let inspect_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("biome_inspect_pipeline"),
+            layout: Some(&inspect_pipeline_layout),
+            module: &shader,
+            entry_point: "inspect_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default()
+        });
+
         Self {
             pipeline,
             uniform_bind_group,
-            uniform_buffer
+            uniform_buffer,
+            inspect_pipeline,
+            inspect_bind_group,
+            inspect_pos_buffer,
+            inspect_buffer,
+            inspect_read_buffer,
+            inspect_len,
+            inspect_pending: None,
+            inspect_result: None
         }
     }
 
-    pub fn prepare(&self, queue: &wgpu::Queue, aspect: f32, depth: f32) {
+    pub fn prepare(&self, queue: &wgpu::Queue, view: &BiomePreviewView) {
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[
             Uniforms {
-                aspect,
-                depth
+                center: view.center.into(),
+                scale: view.scale,
+                aspect: view.aspect,
+                depth: view.depth,
+                _padding: 0.0
             }
         ]));
     }
 
+    // Evaluates the biome weights and parameter values at a single position, see compile_biome_preview.
+    // The values are read back asynchronously, until then inspect_result keeps the ones of the previous position.
+    pub fn inspect(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pos: glam::Vec3) {
+        device.poll(wgpu::Maintain::Poll);
+        if let Some((pending_pos, mapped)) = &self.inspect_pending {
+            if mapped.load(Ordering::Acquire) {
+                let values = bytemuck::cast_slice(&self.inspect_read_buffer.slice(..).get_mapped_range()).to_vec();
+                self.inspect_read_buffer.unmap();
+                self.inspect_result = Some((*pending_pos, values));
+                self.inspect_pending = None;
+            }
+        }
+
+        if self.inspect_pending.is_none() && self.inspect_result.as_ref().map(|(prev_pos, _)| *prev_pos != pos).unwrap_or(true) {
+            self.dispatch_inspect(device, queue, pos);
+        }
+    }
+
+    // The last values read back and where they were evaluated
+    pub fn inspect_result(&self) -> Option<&(glam::Vec3, Vec<f32>)> {
+        self.inspect_result.as_ref()
+    }
+
+    // Whether values are still being read back
+    pub fn inspecting(&self) -> bool {
+        self.inspect_pending.is_some()
+    }
+
+    fn dispatch_inspect(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pos: glam::Vec3) {
+        queue.write_buffer(&self.inspect_pos_buffer, 0, bytemuck::cast_slice(&[pos.extend(0.0)]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("biome_inspect_encoder") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("biome_inspect_pass"),
+                timestamp_writes: None
+            });
+            pass.set_pipeline(&self.inspect_pipeline);
+            pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            pass.set_bind_group(1, &self.inspect_bind_group, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.inspect_buffer, 0, &self.inspect_read_buffer, 0, (self.inspect_len * 4) as u64);
+        queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(AtomicBool::new(false));
+        let callback_mapped = mapped.clone();
+        self.inspect_read_buffer.slice(..).map_async(wgpu::MapMode::Read, move |_| callback_mapped.store(true, Ordering::Release));
+        self.inspect_pending = Some((pos, mapped));
+    }
+
     pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, &self.uniform_bind_group, &[]);
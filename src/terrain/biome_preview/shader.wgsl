@@ -5,6 +5,10 @@ struct VertexOutput {
 };
 
 struct Uniforms {
+    // world x and z at the center of the view
+    center: vec2<f32>,
+    // world units from the center to the top of the view
+    scale: f32,
     aspect: f32,
     depth: f32
 };
@@ -12,6 +16,12 @@ struct Uniforms {
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms; 
 
+@group(1) @binding(0)
+var<storage, read_write> inspect_out: array<f32>;
+
+@group(1) @binding(1)
+var<uniform> inspect_pos: vec4<f32>;
+
 var<private> QUAD_VERTS: array<vec2<f32>, 6> = array(
     vec2(-1.0, -1.0),
     vec2( 1.0, -1.0),
@@ -27,8 +37,9 @@ fn vs_main(
     @builtin(vertex_index) vert_idx: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = vec4<f32>(QUAD_VERTS[vert_idx], 0.0, 1.0);
-    out.pos = 1000.0 * vec3(QUAD_VERTS[vert_idx].x, uniforms.depth, QUAD_VERTS[vert_idx].y) * vec3(uniforms.aspect, 1.0, 1.0);
+    let vert = QUAD_VERTS[vert_idx];
+    out.clip_position = vec4<f32>(vert, 0.0, 1.0);
+    out.pos = vec3(uniforms.center.x + vert.x * uniforms.aspect * uniforms.scale, uniforms.depth, uniforms.center.y + vert.y * uniforms.scale);
     return out;
 }
 
@@ -36,3 +47,11 @@ fn vs_main(
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return vec4(preview_color(in.pos), 1.0); 
 }
+
+@compute @workgroup_size(1)
+fn inspect_main() {
+    var values = preview_inspect(inspect_pos.xyz);
+    for (var i = 0u; i < INSPECT_LEN; i++) {
+        inspect_out[i] = values[i];
+    }
+}
@@ -0,0 +1,504 @@
+use std::{path::Path, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use eframe::wgpu::{self, util::DeviceExt};
+use serde_json::json;
+
+use crate::biome::Biomes;
+
+use super::meshgen::{upload_heightmaps, Heightmap};
+
+mod cpu;
+
+// Rows evaluated per dispatch, so big maps don't stall the GPU for too long
+const ROWS_PER_DISPATCH: u32 = 32;
+// Largest map width and height
+pub const MAX_MAP_RESOLUTION: u32 = 8192;
+// Directory in the project directory maps are exported to by default. Images in it aren't offered as heightmaps or textures.
+pub const MAP_EXPORT_DIR: &str = "maps";
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    min: [f32; 2],
+    pixel_size: [f32; 2],
+    resolution: [u32; 2],
+    first_row: u32,
+    steps: u32,
+    min_height: f32,
+    max_height: f32,
+    step: f32,
+    _padding: f32
+}
+
+// A top-down region of the world. Pixel rows go along +z.
+#[derive(Clone)]
+pub struct MapRegion {
+    // x and z of the corner with the lowest coordinates
+    pub min: [f32; 2],
+    pub size: [f32; 2],
+    pub resolution: [u32; 2],
+    // the surface is searched for from max_height down to min_height in steps of `step`
+    pub min_height: f32,
+    pub max_height: f32,
+    pub step: f32
+}
+
+impl Default for MapRegion {
+
+    fn default() -> Self {
+        Self {
+            min: [-512.0, -512.0],
+            size: [1024.0, 1024.0],
+            resolution: [512, 512],
+            min_height: -256.0,
+            max_height: 256.0,
+            step: 1.0
+        }
+    }
+
+}
+
+impl MapRegion {
+
+    fn pixel_size(&self) -> [f32; 2] {
+        [self.size[0] / self.resolution[0] as f32, self.size[1] / self.resolution[1] as f32]
+    }
+
+    fn uniforms(&self, first_row: u32) -> Uniforms {
+        let step = self.step.max(0.01);
+        Uniforms {
+            min: self.min,
+            pixel_size: self.pixel_size(),
+            resolution: self.resolution,
+            first_row,
+            steps: ((self.max_height - self.min_height) / step).ceil().max(0.0) as u32,
+            min_height: self.min_height,
+            max_height: self.max_height,
+            step,
+            _padding: 0.0
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HeightFormat {
+    // normalized from min_height..max_height
+    Png16,
+    // little endian f32 world space heights
+    RawF32
+}
+
+impl HeightFormat {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HeightFormat::Png16 => "16-bit PNG",
+            HeightFormat::RawF32 => "Raw Float",
+        }
+    }
+
+}
+
+// What the saved maps need of a biome, so they can be saved away from the project
+#[derive(Clone)]
+pub struct MapBiome {
+    pub name: String,
+    pub color: [f32; 3]
+}
+
+impl MapBiome {
+
+    pub fn from_biomes(biomes: &Biomes) -> Vec<Self> {
+        biomes.biomes.iter().map(|biome| Self { name: biome.name.clone(), color: biome.color }).collect()
+    }
+
+}
+
+pub struct MapData {
+    pub region: MapRegion,
+    pub n_biomes: usize,
+    pub heights: Vec<f32>,
+    // n_biomes weights per pixel
+    pub biome_w: Vec<f32>
+}
+
+// Evaluates the compiled sdf at every pixel of a region, a few rows at a time.
+// Each batch of rows is read back asynchronously, so the app can keep drawing while it runs.
+pub struct MapEvaluation {
+    region: MapRegion,
+    n_biomes: usize,
+    pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    map_bind_group: wgpu::BindGroup,
+    map_buffer: wgpu::Buffer,
+    read_buffer: wgpu::Buffer,
+    next_row: u32,
+    // size of the rows being read back and whether they're mapped yet
+    pending: Option<(u64, Arc<AtomicBool>)>,
+    data: Vec<f32>
+}
+
+impl MapEvaluation {
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, sdf_code: &str, heightmaps: &[Heightmap], n_biomes: usize, region: &MapRegion) -> Self {
+        let [width, height] = region.resolution;
+        let pixel_floats = n_biomes + 1;
+
+        let shader = device.create_shader_module(
+            wgpu::ShaderModuleDescriptor {
+                label: Some("map_export_shader"),
+                source: wgpu::ShaderSource::Wgsl((include_str!("map_export/map_export.wgsl").to_owned() + sdf_code).into())
+            }
+        );
+
+        let map_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("map_export_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None
+                        },
+                        count: None,
+                    }
+                ]
+            }
+        );
+
+        // same layout as the mesh generator's, since the sdf may sample the heightmaps
+        let uniform_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("map_export_uniform_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false
+                        },
+                        count: None,
+                    }
+                ]
+            }
+        );
+
+        let layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("map_export_pipeline_layout"),
+                bind_group_layouts: &[&map_bind_group_layout, &uniform_bind_group_layout],
+                push_constant_ranges: &[]
+            }
+        );
+
+        let pipeline = device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("map_export_pipeline"),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: "main",
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }
+        );
+
+        let uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("map_export_uniform_buffer"),
+                contents: bytemuck::cast_slice(&[Uniforms::default()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let heightmap_texture = upload_heightmaps(device, queue, heightmaps);
+        let heightmap_view = heightmap_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let uniform_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("map_export_uniform_bind_group"),
+                layout: &uniform_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&heightmap_view),
+                    }
+                ],
+            }
+        );
+
+        let rows_size = (ROWS_PER_DISPATCH as usize * width as usize * pixel_floats * std::mem::size_of::<f32>()) as u64;
+        let map_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("map_export_buffer"),
+                size: rows_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }
+        );
+        let read_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("map_export_read_buffer"),
+                size: rows_size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }
+        );
+
+        let map_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("map_export_bind_group"),
+                layout: &map_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: map_buffer.as_entire_binding(),
+                    }
+                ],
+            }
+        );
+
+        Self {
+            region: region.clone(),
+            n_biomes,
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            map_bind_group,
+            map_buffer,
+            read_buffer,
+            next_row: 0,
+            pending: None,
+            data: Vec::with_capacity(width as usize * height as usize * pixel_floats)
+        }
+    }
+
+    // Fraction of the rows read back so far
+    pub fn progress(&self) -> f32 {
+        let rows_done = self.data.len() / (self.region.resolution[0] as usize * (self.n_biomes + 1)).max(1);
+        rows_done as f32 / self.region.resolution[1].max(1) as f32
+    }
+
+    // Collects the rows read back and starts on the next ones. Returns the map once every row is done.
+    // With `wait`, blocks until the current rows are read back.
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, wait: bool) -> Option<MapData> {
+        if let Some((size, mapped)) = &self.pending {
+            device.poll(if wait { wgpu::Maintain::Wait } else { wgpu::Maintain::Poll });
+            if !mapped.load(Ordering::Acquire) {
+                return None;
+            }
+            self.data.extend_from_slice(bytemuck::cast_slice::<u8, f32>(&self.read_buffer.slice(..*size).get_mapped_range()));
+            self.read_buffer.unmap();
+            self.pending = None;
+        }
+
+        let height = self.region.resolution[1];
+        if self.next_row >= height {
+            return Some(self.finish());
+        }
+        self.dispatch(device, queue);
+        None
+    }
+
+    fn dispatch(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let [width, height] = self.region.resolution;
+        let pixel_floats = self.n_biomes + 1;
+        let first_row = self.next_row;
+        let rows = ROWS_PER_DISPATCH.min(height - first_row);
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.region.uniforms(first_row)]));
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("map_export_encoder"),
+            }
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(
+                &wgpu::ComputePassDescriptor {
+                    label: Some("map_export"),
+                    timestamp_writes: None
+                }
+            );
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.map_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+            compute_pass.dispatch_workgroups(width.div_ceil(8), rows.div_ceil(8), 1);
+        }
+        let size = (rows as usize * width as usize * pixel_floats * std::mem::size_of::<f32>()) as u64;
+        encoder.copy_buffer_to_buffer(&self.map_buffer, 0, &self.read_buffer, 0, size);
+        queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(AtomicBool::new(false));
+        let callback_mapped = mapped.clone();
+        self.read_buffer.slice(..size).map_async(wgpu::MapMode::Read, move |_| callback_mapped.store(true, Ordering::Release));
+        self.pending = Some((size, mapped));
+        self.next_row += rows;
+    }
+
+    fn finish(&mut self) -> MapData {
+        MapData::from_pixels(&self.region, self.n_biomes, &self.data)
+    }
+
+}
+
+impl MapData {
+
+    // Evaluates the compiled sdf at every pixel of the region, blocking until it's done
+    pub fn evaluate(device: &wgpu::Device, queue: &wgpu::Queue, sdf_code: &str, heightmaps: &[Heightmap], n_biomes: usize, region: &MapRegion) -> Self {
+        let mut evaluation = MapEvaluation::new(device, queue, sdf_code, heightmaps, n_biomes, region);
+        loop {
+            if let Some(map) = evaluation.poll(device, queue, true) {
+                return map;
+            }
+        }
+    }
+
+    // Same as `evaluate`, but interprets the shader on the CPU. Much slower, for when there's no GPU.
+    pub fn evaluate_cpu(sdf_code: &str, heightmaps: &[Heightmap], n_biomes: usize, region: &MapRegion) -> Result<Self, String> {
+        let shader = include_str!("map_export/map_export.wgsl").to_owned() + sdf_code;
+        let [width, height] = region.resolution;
+        let data = cpu::evaluate_rows(&shader, heightmaps, width, height, n_biomes + 1, |row| bytemuck::bytes_of(&region.uniforms(row)).to_vec())?;
+        Ok(Self::from_pixels(region, n_biomes, &data))
+    }
+
+    // Splits the height and biome weights the shader writes for each pixel
+    fn from_pixels(region: &MapRegion, n_biomes: usize, data: &[f32]) -> Self {
+        let pixel_floats = n_biomes + 1;
+        let pixels = data.len() / pixel_floats;
+        let mut heights = Vec::with_capacity(pixels);
+        let mut biome_w = Vec::with_capacity(pixels * n_biomes);
+        for pixel in data.chunks_exact(pixel_floats) {
+            heights.push(pixel[0]);
+            biome_w.extend_from_slice(&pixel[1..]);
+        }
+
+        Self {
+            region: region.clone(),
+            n_biomes,
+            heights,
+            biome_w
+        }
+    }
+
+    fn height_at(&self, x: i64, z: i64) -> f32 {
+        let [width, height] = self.region.resolution;
+        let x = x.clamp(0, width as i64 - 1) as usize;
+        let z = z.clamp(0, height as i64 - 1) as usize;
+        self.heights[z * width as usize + x]
+    }
+
+    // Height gradient along x and z in world units
+    fn gradient(&self, x: u32, z: u32) -> glam::Vec2 {
+        let [x, z] = [x as i64, z as i64];
+        let [pixel_x, pixel_z] = self.region.pixel_size();
+        glam::vec2(
+            (self.height_at(x + 1, z) - self.height_at(x - 1, z)) / (2.0 * pixel_x),
+            (self.height_at(x, z + 1) - self.height_at(x, z - 1)) / (2.0 * pixel_z)
+        )
+    }
+
+    pub fn dominant_biome(&self, pixel: usize) -> usize {
+        let weights = &self.biome_w[pixel * self.n_biomes..(pixel + 1) * self.n_biomes];
+        let mut dominant = 0;
+        for (idx, w) in weights.iter().enumerate() {
+            if *w > weights[dominant] {
+                dominant = idx;
+            }
+        }
+        dominant
+    }
+
+    // Surface angle in degrees, 0 is flat
+    pub fn slope(&self, x: u32, z: u32) -> f32 {
+        self.gradient(x, z).length().atan().to_degrees()
+    }
+
+    // Lambert shading with a light from the -x, -z corner
+    pub fn hillshade(&self, x: u32, z: u32) -> f32 {
+        let gradient = self.gradient(x, z);
+        let norm = glam::vec3(-gradient.x, 1.0, -gradient.y).normalize();
+        let light = glam::vec3(-1.0, 1.5, -1.0).normalize();
+        norm.dot(light).max(0.0)
+    }
+
+    // Writes biomes.png, biome_weight_<idx>.png, height.png or height.raw, slope.png, hillshade.png and maps.json to `dir`
+    pub fn save(&self, dir: &Path, biomes: &[MapBiome], height_format: HeightFormat) -> image::ImageResult<()> {
+        std::fs::create_dir_all(dir)?;
+        let [width, height] = self.region.resolution;
+        let pixel = |x: u32, z: u32| z as usize * width as usize + x as usize;
+
+        let palette: Vec<[u8; 3]> = biomes.iter().map(|biome| biome.color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8)).collect();
+        image::RgbImage::from_fn(width, height, |x, z| {
+            image::Rgb(palette.get(self.dominant_biome(pixel(x, z))).copied().unwrap_or([0; 3]))
+        }).save(dir.join("biomes.png"))?;
+
+        for biome_idx in 0..self.n_biomes {
+            image::GrayImage::from_fn(width, height, |x, z| {
+                image::Luma([(self.biome_w[pixel(x, z) * self.n_biomes + biome_idx].clamp(0.0, 1.0) * 255.0) as u8])
+            }).save(dir.join(format!("biome_weight_{}.png", biome_idx)))?;
+        }
+
+        match height_format {
+            HeightFormat::Png16 => {
+                let range = (self.region.max_height - self.region.min_height).max(0.0001);
+                image::ImageBuffer::<image::Luma<u16>, _>::from_fn(width, height, |x, z| {
+                    let h = (self.heights[pixel(x, z)] - self.region.min_height) / range;
+                    image::Luma([(h.clamp(0.0, 1.0) * u16::MAX as f32) as u16])
+                }).save(dir.join("height.png"))?;
+            },
+            HeightFormat::RawF32 => {
+                let bytes: Vec<u8> = self.heights.iter().flat_map(|h| h.to_le_bytes()).collect();
+                std::fs::write(dir.join("height.raw"), bytes)?;
+            }
+        }
+
+        image::GrayImage::from_fn(width, height, |x, z| {
+            image::Luma([(self.slope(x, z) / 90.0 * 255.0) as u8])
+        }).save(dir.join("slope.png"))?;
+
+        image::GrayImage::from_fn(width, height, |x, z| {
+            image::Luma([(self.hillshade(x, z) * 255.0) as u8])
+        }).save(dir.join("hillshade.png"))?;
+
+        // everything needed to map pixels back to the world
+        let info = json!({
+            "min": self.region.min,
+            "size": self.region.size,
+            "resolution": self.region.resolution,
+            "min_height": self.region.min_height,
+            "max_height": self.region.max_height,
+            "height_format": height_format.label(),
+            "biomes": biomes.iter().map(|biome| json!({
+                "name": biome.name,
+                "color": biome.color
+            })).collect::<Vec<_>>()
+        });
+        std::fs::write(dir.join("maps.json"), info.to_string())?;
+
+        Ok(())
+    }
+
+}
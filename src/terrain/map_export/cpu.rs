@@ -0,0 +1,865 @@
+use std::{rc::Rc, sync::{atomic::{AtomicU32, Ordering}, Mutex}};
+
+use naga::{AddressSpace, BinaryOperator, Binding, Block, BuiltIn, Expression, Function, Handle, ImageQuery, Literal, MathFunction, Module, RelationalFunction, ScalarKind, Statement, Type, TypeInner, UnaryOperator};
+
+use crate::terrain::meshgen::{heightmap_texture_size, Heightmap};
+
+// Longest chain of member and index accesses a pointer can hold
+const MAX_PATH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    F32,
+    I32,
+    U32,
+    Bool
+}
+
+#[derive(Clone, Copy)]
+enum Root {
+    Local(usize),
+    Global(usize)
+}
+
+#[derive(Clone, Copy)]
+struct Pointer {
+    root: Root,
+    path: [u32; MAX_PATH],
+    depth: u8
+}
+
+impl Pointer {
+
+    fn new(root: Root) -> Self {
+        Self { root, path: [0; MAX_PATH], depth: 0 }
+    }
+
+    fn push(mut self, index: u32) -> Result<Self, String> {
+        if self.depth as usize == MAX_PATH {
+            return Err("pointer access chain is too long".to_owned());
+        }
+        self.path[self.depth as usize] = index;
+        self.depth += 1;
+        Ok(self)
+    }
+
+    fn path(&self) -> &[u32] {
+        &self.path[..self.depth as usize]
+    }
+
+}
+
+#[derive(Clone)]
+enum Value {
+    // expressions not evaluated yet and handles like textures
+    Undef,
+    // scalars are vectors of one component, components are stored as bits
+    Vector(Kind, u8, [u32; 4]),
+    // arrays, structs and the columns of matrices
+    Composite(Rc<Vec<Value>>),
+    Pointer(Pointer)
+}
+
+type Components = (Kind, usize, [u32; 4]);
+
+impl Value {
+
+    fn f32(x: f32) -> Self {
+        Value::Vector(Kind::F32, 1, [x.to_bits(), 0, 0, 0])
+    }
+
+    fn vector(&self) -> Result<Components, String> {
+        match self {
+            Value::Vector(kind, len, comps) => Ok((*kind, *len as usize, *comps)),
+            _ => Err("expected a scalar or vector".to_owned())
+        }
+    }
+
+    fn index(&self) -> Result<u32, String> {
+        let (kind, _, comps) = self.vector()?;
+        Ok(match kind {
+            Kind::I32 => (comps[0] as i32).max(0) as u32,
+            _ => comps[0]
+        })
+    }
+
+    fn as_bool(&self) -> Result<bool, String> {
+        Ok(self.vector()?.2[0] != 0)
+    }
+
+}
+
+fn from_components((kind, len, comps): Components) -> Value {
+    Value::Vector(kind, len as u8, comps)
+}
+
+fn scalar_kind(scalar: naga::Scalar) -> Result<Kind, String> {
+    match (scalar.kind, scalar.width) {
+        (ScalarKind::Float, 4) => Ok(Kind::F32),
+        (ScalarKind::Sint, 4) => Ok(Kind::I32),
+        (ScalarKind::Uint, 4) => Ok(Kind::U32),
+        (ScalarKind::Bool, _) => Ok(Kind::Bool),
+        _ => Err("only 32 bit scalars are supported".to_owned())
+    }
+}
+
+fn literal(literal: &Literal) -> Result<Value, String> {
+    let (kind, bits) = match *literal {
+        Literal::F32(x) => (Kind::F32, x.to_bits()),
+        Literal::AbstractFloat(x) => (Kind::F32, (x as f32).to_bits()),
+        Literal::I32(x) => (Kind::I32, x as u32),
+        Literal::AbstractInt(x) => (Kind::I32, x as i32 as u32),
+        Literal::U32(x) => (Kind::U32, x),
+        Literal::Bool(x) => (Kind::Bool, x as u32),
+        _ => return Err("64 bit literals aren't supported".to_owned())
+    };
+    Ok(Value::Vector(kind, 1, [bits, 0, 0, 0]))
+}
+
+fn zero_value(module: &Module, ty: Handle<Type>) -> Result<Value, String> {
+    Ok(match &module.types[ty].inner {
+        TypeInner::Scalar(scalar) => Value::Vector(scalar_kind(*scalar)?, 1, [0; 4]),
+        TypeInner::Vector { size, scalar } => Value::Vector(scalar_kind(*scalar)?, *size as u8, [0; 4]),
+        TypeInner::Matrix { columns, rows, scalar } => {
+            let column = Value::Vector(scalar_kind(*scalar)?, *rows as u8, [0; 4]);
+            Value::Composite(Rc::new(vec![column; *columns as usize]))
+        },
+        TypeInner::Array { base, size: naga::ArraySize::Constant(len), .. } => Value::Composite(Rc::new(vec![zero_value(module, *base)?; len.get() as usize])),
+        TypeInner::Struct { members, .. } => Value::Composite(Rc::new(members.iter().map(|member| zero_value(module, member.ty)).collect::<Result<_, _>>()?)),
+        TypeInner::Image { .. } | TypeInner::Sampler { .. } => Value::Undef,
+        _ => return Err("unsupported type".to_owned())
+    })
+}
+
+// A value laid out in memory like a uniform or storage buffer
+fn value_from_bytes(module: &Module, ty: Handle<Type>, bytes: &[u8]) -> Result<Value, String> {
+    let word = |offset: usize| -> Result<u32, String> {
+        let word = bytes.get(offset..offset + 4).ok_or("buffer is smaller than its type")?;
+        Ok(u32::from_le_bytes(word.try_into().unwrap()))
+    };
+    Ok(match &module.types[ty].inner {
+        TypeInner::Scalar(scalar) => Value::Vector(scalar_kind(*scalar)?, 1, [word(0)?, 0, 0, 0]),
+        TypeInner::Vector { size, scalar } => {
+            let mut comps = [0; 4];
+            for (idx, comp) in comps.iter_mut().take(*size as usize).enumerate() {
+                *comp = word(idx * 4)?;
+            }
+            Value::Vector(scalar_kind(*scalar)?, *size as u8, comps)
+        },
+        TypeInner::Array { base, size: naga::ArraySize::Constant(len), stride } => Value::Composite(Rc::new(
+            (0..len.get() as usize).map(|idx| value_from_bytes(module, *base, &bytes[(idx * *stride as usize).min(bytes.len())..])).collect::<Result<_, _>>()?
+        )),
+        TypeInner::Struct { members, .. } => Value::Composite(Rc::new(
+            members.iter().map(|member| value_from_bytes(module, member.ty, &bytes[(member.offset as usize).min(bytes.len())..])).collect::<Result<_, _>>()?
+        )),
+        _ => return Err("unsupported buffer type".to_owned())
+    })
+}
+
+// Expressions naga keeps outside of emit statements, they're evaluated wherever they're used
+fn is_pre_emitted(expr: &Expression) -> bool {
+    matches!(expr, Expression::Literal(_) | Expression::Constant(_) | Expression::ZeroValue(_) | Expression::FunctionArgument(_)
+        | Expression::GlobalVariable(_) | Expression::LocalVariable(_) | Expression::CallResult(_))
+}
+
+fn component(comps: &[u32; 4], len: usize, idx: usize) -> u32 {
+    comps[if len == 1 { 0 } else { idx }]
+}
+
+fn unary(op: UnaryOperator, (kind, len, comps): Components) -> Result<Value, String> {
+    let mut out = comps;
+    for comp in out.iter_mut().take(len) {
+        *comp = match (op, kind) {
+            (UnaryOperator::Negate, Kind::F32) => (-f32::from_bits(*comp)).to_bits(),
+            (UnaryOperator::Negate, Kind::I32) => (*comp as i32).wrapping_neg() as u32,
+            (UnaryOperator::LogicalNot, Kind::Bool) | (UnaryOperator::BitwiseNot, Kind::Bool) => (*comp == 0) as u32,
+            (UnaryOperator::BitwiseNot, Kind::I32 | Kind::U32) => !*comp,
+            _ => return Err("unsupported unary operation".to_owned())
+        };
+    }
+    Ok(Value::Vector(kind, len as u8, out))
+}
+
+fn binary_scalar(op: BinaryOperator, kind: Kind, x: u32, y: u32) -> Result<(Kind, u32), String> {
+    use BinaryOperator as Op;
+    let compare = |ordering: Option<std::cmp::Ordering>| -> Option<(Kind, u32)> {
+        let result = match op {
+            Op::Equal => ordering == Some(std::cmp::Ordering::Equal),
+            Op::NotEqual => ordering != Some(std::cmp::Ordering::Equal),
+            Op::Less => ordering == Some(std::cmp::Ordering::Less),
+            Op::LessEqual => matches!(ordering, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+            Op::Greater => ordering == Some(std::cmp::Ordering::Greater),
+            Op::GreaterEqual => matches!(ordering, Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)),
+            _ => return None
+        };
+        Some((Kind::Bool, result as u32))
+    };
+
+    Ok(match kind {
+        Kind::F32 => {
+            let (a, b) = (f32::from_bits(x), f32::from_bits(y));
+            if let Some(result) = compare(a.partial_cmp(&b)) {
+                return Ok(result);
+            }
+            (Kind::F32, match op {
+                Op::Add => a + b,
+                Op::Subtract => a - b,
+                Op::Multiply => a * b,
+                Op::Divide => a / b,
+                Op::Modulo => a % b,
+                _ => return Err("unsupported float operation".to_owned())
+            }.to_bits())
+        },
+        Kind::I32 => {
+            let (a, b) = (x as i32, y as i32);
+            if let Some(result) = compare(Some(a.cmp(&b))) {
+                return Ok(result);
+            }
+            (Kind::I32, match op {
+                Op::Add => a.wrapping_add(b),
+                Op::Subtract => a.wrapping_sub(b),
+                Op::Multiply => a.wrapping_mul(b),
+                // like WGSL, dividing by zero or overflowing gives the dividend and the remainder is 0
+                Op::Divide => a.checked_div(b).unwrap_or(a),
+                Op::Modulo => a.checked_rem(b).unwrap_or(0),
+                Op::And => a & b,
+                Op::InclusiveOr => a | b,
+                Op::ExclusiveOr => a ^ b,
+                Op::ShiftLeft => a.wrapping_shl(y),
+                Op::ShiftRight => a.wrapping_shr(y),
+                _ => return Err("unsupported integer operation".to_owned())
+            } as u32)
+        },
+        Kind::U32 => {
+            let (a, b) = (x, y);
+            if let Some(result) = compare(Some(a.cmp(&b))) {
+                return Ok(result);
+            }
+            (Kind::U32, match op {
+                Op::Add => a.wrapping_add(b),
+                Op::Subtract => a.wrapping_sub(b),
+                Op::Multiply => a.wrapping_mul(b),
+                Op::Divide => a.checked_div(b).unwrap_or(a),
+                Op::Modulo => a.checked_rem(b).unwrap_or(0),
+                Op::And => a & b,
+                Op::InclusiveOr => a | b,
+                Op::ExclusiveOr => a ^ b,
+                Op::ShiftLeft => a.wrapping_shl(b),
+                Op::ShiftRight => a.wrapping_shr(b),
+                _ => return Err("unsupported integer operation".to_owned())
+            })
+        },
+        Kind::Bool => (Kind::Bool, match op {
+            Op::Equal => x == y,
+            Op::NotEqual => x != y,
+            Op::And | Op::LogicalAnd => x != 0 && y != 0,
+            Op::InclusiveOr | Op::LogicalOr => x != 0 || y != 0,
+            _ => return Err("unsupported bool operation".to_owned())
+        } as u32)
+    })
+}
+
+// Component-wise, a scalar operand is used for every component of a vector one
+fn binary(op: BinaryOperator, (kind, left_len, left): Components, (_, right_len, right): Components) -> Result<Value, String> {
+    let len = left_len.max(right_len);
+    let mut out = [0; 4];
+    let mut out_kind = kind;
+    for (idx, comp) in out.iter_mut().take(len).enumerate() {
+        (out_kind, *comp) = binary_scalar(op, kind, component(&left, left_len, idx), component(&right, right_len, idx))?;
+    }
+    Ok(Value::Vector(out_kind, len as u8, out))
+}
+
+fn floats((_, len, comps): Components) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for (idx, comp) in out.iter_mut().take(len).enumerate() {
+        *comp = f32::from_bits(comps[idx]);
+    }
+    out
+}
+
+fn float_vector(len: usize, comps: [f32; 4]) -> Value {
+    Value::Vector(Kind::F32, len as u8, comps.map(f32::to_bits))
+}
+
+// Applies `f` to every component of float arguments, scalar arguments are used for every component
+fn map_floats(args: &[Components], f: impl Fn(&[f32]) -> f32) -> Value {
+    let len = args.iter().map(|arg| arg.1).max().unwrap_or(1);
+    let mut out = [0.0; 4];
+    let mut comp_args = [0.0; 3];
+    for (idx, comp) in out.iter_mut().take(len).enumerate() {
+        for (arg_idx, arg) in args.iter().enumerate() {
+            comp_args[arg_idx] = f32::from_bits(component(&arg.2, arg.1, idx));
+        }
+        *comp = f(&comp_args[..args.len()]);
+    }
+    float_vector(len, out)
+}
+
+// Applies `f` to every component of integer arguments, as i64 so both signednesses fit
+fn map_ints(kind: Kind, args: &[Components], f: impl Fn(&[i64]) -> i64) -> Value {
+    let len = args.iter().map(|arg| arg.1).max().unwrap_or(1);
+    let mut out = [0; 4];
+    let mut comp_args = [0; 3];
+    for (idx, comp) in out.iter_mut().take(len).enumerate() {
+        for (arg_idx, arg) in args.iter().enumerate() {
+            let bits = component(&arg.2, arg.1, idx);
+            comp_args[arg_idx] = if kind == Kind::I32 { bits as i32 as i64 } else { bits as i64 };
+        }
+        *comp = f(&comp_args[..args.len()]) as u32;
+    }
+    Value::Vector(kind, len as u8, out)
+}
+
+fn dot(a: Components, b: Components) -> f32 {
+    let (a, b) = (floats(a), floats(b));
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn math(fun: MathFunction, args: &[Components]) -> Result<Value, String> {
+    use MathFunction as F;
+    let kind = args[0].0;
+    if kind != Kind::F32 {
+        let value = match fun {
+            F::Abs => map_ints(kind, args, |a| a[0].abs()),
+            F::Min => map_ints(kind, args, |a| a[0].min(a[1])),
+            F::Max => map_ints(kind, args, |a| a[0].max(a[1])),
+            F::Clamp => map_ints(kind, args, |a| a[0].max(a[1]).min(a[2])),
+            F::Sign => map_ints(kind, args, |a| a[0].signum()),
+            F::CountOneBits => map_ints(kind, args, |a| (a[0] as u32).count_ones() as i64),
+            F::ReverseBits => map_ints(kind, args, |a| (a[0] as u32).reverse_bits() as i64),
+            F::CountLeadingZeros => map_ints(kind, args, |a| (a[0] as u32).leading_zeros() as i64),
+            F::CountTrailingZeros => map_ints(kind, args, |a| (a[0] as u32).trailing_zeros() as i64),
+            _ => return Err(format!("unsupported integer function {:?}", fun))
+        };
+        return Ok(value);
+    }
+
+    let len = args[0].1;
+    Ok(match fun {
+        F::Abs => map_floats(args, |a| a[0].abs()),
+        F::Min => map_floats(args, |a| a[0].min(a[1])),
+        F::Max => map_floats(args, |a| a[0].max(a[1])),
+        F::Clamp => map_floats(args, |a| a[0].max(a[1]).min(a[2])),
+        F::Saturate => map_floats(args, |a| a[0].clamp(0.0, 1.0)),
+        F::Cos => map_floats(args, |a| a[0].cos()),
+        F::Cosh => map_floats(args, |a| a[0].cosh()),
+        F::Sin => map_floats(args, |a| a[0].sin()),
+        F::Sinh => map_floats(args, |a| a[0].sinh()),
+        F::Tan => map_floats(args, |a| a[0].tan()),
+        F::Tanh => map_floats(args, |a| a[0].tanh()),
+        F::Acos => map_floats(args, |a| a[0].acos()),
+        F::Asin => map_floats(args, |a| a[0].asin()),
+        F::Atan => map_floats(args, |a| a[0].atan()),
+        F::Atan2 => map_floats(args, |a| a[0].atan2(a[1])),
+        F::Radians => map_floats(args, |a| a[0].to_radians()),
+        F::Degrees => map_floats(args, |a| a[0].to_degrees()),
+        F::Ceil => map_floats(args, |a| a[0].ceil()),
+        F::Floor => map_floats(args, |a| a[0].floor()),
+        F::Round => map_floats(args, |a| a[0].round_ties_even()),
+        F::Fract => map_floats(args, |a| a[0] - a[0].floor()),
+        F::Trunc => map_floats(args, |a| a[0].trunc()),
+        F::Exp => map_floats(args, |a| a[0].exp()),
+        F::Exp2 => map_floats(args, |a| a[0].exp2()),
+        F::Log => map_floats(args, |a| a[0].ln()),
+        F::Log2 => map_floats(args, |a| a[0].log2()),
+        F::Pow => map_floats(args, |a| a[0].powf(a[1])),
+        F::Sqrt => map_floats(args, |a| a[0].sqrt()),
+        F::InverseSqrt => map_floats(args, |a| 1.0 / a[0].sqrt()),
+        F::Sign => map_floats(args, |a| if a[0] > 0.0 { 1.0 } else if a[0] < 0.0 { -1.0 } else { 0.0 }),
+        F::Fma => map_floats(args, |a| a[0].mul_add(a[1], a[2])),
+        F::Mix => map_floats(args, |a| a[0] * (1.0 - a[2]) + a[1] * a[2]),
+        F::Step => map_floats(args, |a| if a[0] <= a[1] { 1.0 } else { 0.0 }),
+        F::SmoothStep => map_floats(args, |a| {
+            let t = ((a[2] - a[0]) / (a[1] - a[0])).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        }),
+        F::Dot => Value::f32(dot(args[0], args[1])),
+        F::Length => Value::f32(dot(args[0], args[0]).sqrt()),
+        F::Distance => {
+            let (a, b) = (floats(args[0]), floats(args[1]));
+            Value::f32(a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum::<f32>().sqrt())
+        },
+        F::Normalize => {
+            let length = dot(args[0], args[0]).sqrt();
+            float_vector(len, floats(args[0]).map(|x| x / length))
+        },
+        F::Cross => {
+            let (a, b) = (floats(args[0]), floats(args[1]));
+            float_vector(3, [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0], 0.0])
+        },
+        _ => return Err(format!("unsupported function {:?}", fun))
+    })
+}
+
+fn convert((from, len, comps): Components, kind: Kind) -> Value {
+    let mut out = comps;
+    for comp in out.iter_mut().take(len) {
+        let bits = *comp;
+        *comp = match (from, kind) {
+            (Kind::F32, Kind::I32) => f32::from_bits(bits) as i32 as u32,
+            (Kind::F32, Kind::U32) => f32::from_bits(bits) as u32,
+            (Kind::I32, Kind::F32) => (bits as i32 as f32).to_bits(),
+            (Kind::U32, Kind::F32) => (bits as f32).to_bits(),
+            (Kind::Bool, Kind::F32) => if bits != 0 { 1.0f32.to_bits() } else { 0 },
+            (Kind::F32, Kind::Bool) => (f32::from_bits(bits) != 0.0) as u32,
+            (_, Kind::Bool) => (bits != 0) as u32,
+            // between signed and unsigned the bits stay the same
+            _ => bits
+        };
+    }
+    Value::Vector(kind, len as u8, out)
+}
+
+fn load_path(mut value: &Value, path: &[u32]) -> Result<Value, String> {
+    for (depth, idx) in path.iter().enumerate() {
+        match value {
+            Value::Composite(items) => value = &items[(*idx as usize).min(items.len() - 1)],
+            Value::Vector(kind, len, comps) if depth == path.len() - 1 => {
+                return Ok(Value::Vector(*kind, 1, [comps[(*idx as usize).min(*len as usize - 1)], 0, 0, 0]));
+            },
+            _ => return Err("invalid pointer".to_owned())
+        }
+    }
+    Ok(value.clone())
+}
+
+fn store_path(target: &mut Value, path: &[u32], value: Value) -> Result<(), String> {
+    let Some((idx, rest)) = path.split_first() else {
+        *target = value;
+        return Ok(());
+    };
+    match target {
+        Value::Composite(items) => {
+            let items = Rc::make_mut(items);
+            let idx = (*idx as usize).min(items.len() - 1);
+            store_path(&mut items[idx], rest, value)
+        },
+        Value::Vector(_, len, comps) if rest.is_empty() => {
+            comps[(*idx as usize).min(*len as usize - 1)] = value.vector()?.2[0];
+            Ok(())
+        },
+        _ => Err("invalid pointer".to_owned())
+    }
+}
+
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value)
+}
+
+#[derive(Default)]
+struct Frame {
+    // values of the emitted expressions
+    exprs: Vec<Value>,
+    locals: Vec<Value>,
+    args: Vec<Value>
+}
+
+// Runs the compute entry point of a shader module on the CPU, one invocation at a time.
+// The heightmaps are read the way the mesh generator's heightmap texture would be.
+struct Interpreter<'a> {
+    module: &'a Module,
+    constants: Vec<Value>,
+    globals: Vec<Value>,
+    // initial values of every function's locals, the entry points' are after the module's functions
+    local_inits: Vec<Vec<Value>>,
+    heightmaps: &'a [Heightmap],
+    heightmap_size: [u32; 3],
+    frames: Vec<Frame>
+}
+
+impl<'a> Interpreter<'a> {
+
+    fn new(module: &'a Module, heightmaps: &'a [Heightmap]) -> Result<Self, String> {
+        let mut interpreter = Self {
+            module,
+            constants: Vec::new(),
+            globals: Vec::new(),
+            local_inits: Vec::new(),
+            heightmaps,
+            heightmap_size: heightmap_texture_size(heightmaps),
+            frames: Vec::new()
+        };
+
+        for (_, constant) in module.constants.iter() {
+            let value = interpreter.const_value(&module.global_expressions, constant.init)?;
+            interpreter.constants.push(value);
+        }
+        for (_, global) in module.global_variables.iter() {
+            let value = match (global.init, &module.types[global.ty].inner) {
+                (Some(init), _) => interpreter.const_value(&module.global_expressions, init)?,
+                (None, TypeInner::Array { size: naga::ArraySize::Dynamic, .. }) => Value::Composite(Rc::new(Vec::new())),
+                (None, _) => zero_value(module, global.ty)?
+            };
+            interpreter.globals.push(value);
+        }
+        let functions = module.functions.iter().map(|(_, function)| function).chain(module.entry_points.iter().map(|entry| &entry.function));
+        for function in functions.collect::<Vec<_>>() {
+            let mut inits = Vec::new();
+            for (_, local) in function.local_variables.iter() {
+                inits.push(match local.init {
+                    Some(init) => interpreter.const_value(&function.expressions, init)?,
+                    None => zero_value(module, local.ty)?
+                });
+            }
+            interpreter.local_inits.push(inits);
+        }
+        Ok(interpreter)
+    }
+
+    fn global(&self, group: u32, binding: u32) -> Result<usize, String> {
+        self.module.global_variables.iter().position(|(_, global)| global.binding.as_ref().is_some_and(|res| res.group == group && res.binding == binding))
+            .ok_or_else(|| format!("no resource at group {} binding {}", group, binding))
+    }
+
+    fn set_buffer(&mut self, group: u32, binding: u32, bytes: &[u8]) -> Result<(), String> {
+        let idx = self.global(group, binding)?;
+        let ty = self.module.global_variables.iter().nth(idx).unwrap().1.ty;
+        self.globals[idx] = value_from_bytes(self.module, ty, bytes)?;
+        Ok(())
+    }
+
+    // A runtime sized array of floats
+    fn set_float_array(&mut self, group: u32, binding: u32, len: usize) -> Result<(), String> {
+        let idx = self.global(group, binding)?;
+        self.globals[idx] = Value::Composite(Rc::new(vec![Value::f32(0.0); len]));
+        Ok(())
+    }
+
+    fn float_array(&self, group: u32, binding: u32) -> Result<Vec<f32>, String> {
+        let Value::Composite(items) = &self.globals[self.global(group, binding)?] else {
+            return Err("not an array".to_owned());
+        };
+        items.iter().map(|item| Ok(f32::from_bits(item.vector()?.2[0]))).collect()
+    }
+
+    // Evaluates constant expressions, which may be outside of emit statements
+    fn const_value(&self, arena: &naga::Arena<Expression>, expr: Handle<Expression>) -> Result<Value, String> {
+        Ok(match &arena[expr] {
+            Expression::Literal(value) => literal(value)?,
+            Expression::Constant(constant) => self.constants.get(constant.index()).cloned().ok_or("constant used before it's defined")?,
+            Expression::ZeroValue(ty) => zero_value(self.module, *ty)?,
+            Expression::Splat { size, value } => {
+                let (kind, _, comps) = self.const_value(arena, *value)?.vector()?;
+                Value::Vector(kind, *size as u8, [comps[0]; 4])
+            },
+            Expression::Compose { ty, components } => {
+                let components = components.iter().map(|comp| self.const_value(arena, *comp)).collect::<Result<Vec<_>, _>>()?;
+                self.compose(*ty, components)?
+            },
+            _ => return Err("unsupported constant expression".to_owned())
+        })
+    }
+
+    fn compose(&self, ty: Handle<Type>, components: Vec<Value>) -> Result<Value, String> {
+        if let TypeInner::Vector { size, scalar } = &self.module.types[ty].inner {
+            // vectors can be built from smaller vectors
+            let mut comps = [0; 4];
+            let mut len = 0;
+            for comp in components {
+                let (_, comp_len, comp_comps) = comp.vector()?;
+                for bits in comp_comps.iter().take(comp_len) {
+                    if len < 4 {
+                        comps[len] = *bits;
+                        len += 1;
+                    }
+                }
+            }
+            return Ok(Value::Vector(scalar_kind(*scalar)?, *size as u8, comps));
+        }
+        Ok(Value::Composite(Rc::new(components)))
+    }
+
+    fn value(&self, function: &Function, frame: &Frame, expr: Handle<Expression>) -> Result<Value, String> {
+        Ok(match &function.expressions[expr] {
+            Expression::Literal(value) => literal(value)?,
+            Expression::Constant(constant) => self.constants[constant.index()].clone(),
+            Expression::ZeroValue(ty) => zero_value(self.module, *ty)?,
+            Expression::FunctionArgument(idx) => frame.args[*idx as usize].clone(),
+            Expression::GlobalVariable(global) => match self.module.global_variables[*global].space {
+                AddressSpace::Handle => Value::Undef,
+                _ => Value::Pointer(Pointer::new(Root::Global(global.index())))
+            },
+            Expression::LocalVariable(local) => Value::Pointer(Pointer::new(Root::Local(local.index()))),
+            _ => frame.exprs[expr.index()].clone()
+        })
+    }
+
+    fn components(&self, function: &Function, frame: &Frame, expr: Handle<Expression>) -> Result<Components, String> {
+        self.value(function, frame, expr)?.vector()
+    }
+
+    fn load(&self, frame: &Frame, pointer: &Pointer) -> Result<Value, String> {
+        let root = match pointer.root {
+            Root::Local(idx) => &frame.locals[idx],
+            Root::Global(idx) => &self.globals[idx]
+        };
+        load_path(root, pointer.path())
+    }
+
+    fn height_texel(&self, layer: i32, x: i32, z: i32) -> f32 {
+        let Some(heightmap) = usize::try_from(layer).ok().and_then(|layer| self.heightmaps.get(layer)) else { return 0.0; };
+        // the texture is as big as the largest heightmap, around smaller ones it's 0
+        if x < 0 || z < 0 || x as u32 >= heightmap.width || z as u32 >= heightmap.height {
+            return 0.0;
+        }
+        heightmap.heights[z as usize * heightmap.width as usize + x as usize]
+    }
+
+    fn eval(&mut self, function: &Function, frame: &Frame, expr: Handle<Expression>) -> Result<Value, String> {
+        if is_pre_emitted(&function.expressions[expr]) {
+            return self.value(function, frame, expr);
+        }
+        Ok(match &function.expressions[expr] {
+            Expression::Compose { ty, components } => {
+                let components = components.iter().map(|comp| self.value(function, frame, *comp)).collect::<Result<Vec<_>, _>>()?;
+                self.compose(*ty, components)?
+            },
+            Expression::Access { base, index } => {
+                let index = self.value(function, frame, *index)?.index()?;
+                match self.value(function, frame, *base)? {
+                    Value::Pointer(pointer) => Value::Pointer(pointer.push(index)?),
+                    base => load_path(&base, &[index])?
+                }
+            },
+            Expression::AccessIndex { base, index } => match self.value(function, frame, *base)? {
+                Value::Pointer(pointer) => Value::Pointer(pointer.push(*index)?),
+                base => load_path(&base, &[*index])?
+            },
+            Expression::Splat { size, value } => {
+                let (kind, _, comps) = self.components(function, frame, *value)?;
+                Value::Vector(kind, *size as u8, [comps[0]; 4])
+            },
+            Expression::Swizzle { size, vector, pattern } => {
+                let (kind, _, comps) = self.components(function, frame, *vector)?;
+                Value::Vector(kind, *size as u8, pattern.map(|comp| comps[comp as usize]))
+            },
+            Expression::Load { pointer } => {
+                let Value::Pointer(pointer) = self.value(function, frame, *pointer)? else { return Err("load from a non pointer".to_owned()); };
+                self.load(frame, &pointer)?
+            },
+            Expression::ImageLoad { coordinate, array_index, .. } => {
+                let (_, _, coords) = self.components(function, frame, *coordinate)?;
+                let layer = match array_index {
+                    Some(layer) => self.components(function, frame, *layer)?.2[0] as i32,
+                    None => 0
+                };
+                let height = self.height_texel(layer, coords[0] as i32, coords[1] as i32);
+                float_vector(4, [height, 0.0, 0.0, 1.0])
+            },
+            Expression::ImageQuery { query, .. } => match query {
+                ImageQuery::Size { .. } => Value::Vector(Kind::U32, 2, [self.heightmap_size[0], self.heightmap_size[1], 0, 0]),
+                ImageQuery::NumLayers => Value::Vector(Kind::U32, 1, [self.heightmap_size[2], 0, 0, 0]),
+                _ => Value::Vector(Kind::U32, 1, [1, 0, 0, 0])
+            },
+            Expression::Unary { op, expr } => unary(*op, self.components(function, frame, *expr)?)?,
+            Expression::Binary { op, left, right } => binary(*op, self.components(function, frame, *left)?, self.components(function, frame, *right)?)?,
+            Expression::Select { condition, accept, reject } => {
+                let (_, cond_len, cond) = self.components(function, frame, *condition)?;
+                let (kind, len, accept) = self.components(function, frame, *accept)?;
+                let (_, _, reject) = self.components(function, frame, *reject)?;
+                let mut out = reject;
+                for idx in 0..len {
+                    if component(&cond, cond_len, idx) != 0 {
+                        out[idx] = accept[idx];
+                    }
+                }
+                Value::Vector(kind, len as u8, out)
+            },
+            Expression::Relational { fun, argument } => {
+                let (_, len, comps) = self.components(function, frame, *argument)?;
+                let comps = &comps[..len];
+                let result = match fun {
+                    RelationalFunction::All => comps.iter().all(|comp| *comp != 0),
+                    RelationalFunction::Any => comps.iter().any(|comp| *comp != 0),
+                    RelationalFunction::IsNan | RelationalFunction::IsInf => {
+                        let mut out = [0; 4];
+                        for (idx, comp) in comps.iter().enumerate() {
+                            let x = f32::from_bits(*comp);
+                            out[idx] = if *fun == RelationalFunction::IsNan { x.is_nan() } else { x.is_infinite() } as u32;
+                        }
+                        return Ok(Value::Vector(Kind::Bool, len as u8, out));
+                    }
+                };
+                Value::Vector(Kind::Bool, 1, [result as u32, 0, 0, 0])
+            },
+            Expression::Math { fun, arg, arg1, arg2, arg3 } => {
+                let mut args = vec![self.components(function, frame, *arg)?];
+                for arg in [arg1, arg2, arg3].into_iter().flatten() {
+                    args.push(self.components(function, frame, *arg)?);
+                }
+                math(*fun, &args)?
+            },
+            Expression::As { expr, kind, convert: conversion } => {
+                let components = self.components(function, frame, *expr)?;
+                let kind = scalar_kind(naga::Scalar { kind: *kind, width: 4 })?;
+                match conversion {
+                    Some(_) => convert(components, kind),
+                    // bitcast
+                    None => from_components((kind, components.1, components.2))
+                }
+            },
+            Expression::ArrayLength(array) => {
+                let Value::Pointer(pointer) = self.value(function, frame, *array)? else { return Err("array length of a non pointer".to_owned()); };
+                let Value::Composite(items) = self.load(frame, &pointer)? else { return Err("array length of a non array".to_owned()); };
+                Value::Vector(Kind::U32, 1, [items.len() as u32, 0, 0, 0])
+            },
+            _ => return Err("unsupported expression".to_owned())
+        })
+    }
+
+    fn call(&mut self, function_idx: usize, function: &Function, args: Vec<Value>) -> Result<Value, String> {
+        let mut frame = self.frames.pop().unwrap_or_default();
+        if frame.exprs.len() < function.expressions.len() {
+            frame.exprs.resize(function.expressions.len(), Value::Undef);
+        }
+        frame.locals.clone_from(&self.local_inits[function_idx]);
+        frame.args = args;
+
+        let result = self.exec(function, &mut frame, &function.body);
+        self.frames.push(frame);
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Undef)
+        }
+    }
+
+    fn exec(&mut self, function: &Function, frame: &mut Frame, block: &Block) -> Result<Flow, String> {
+        for statement in block.iter() {
+            let flow = match statement {
+                Statement::Emit(range) => {
+                    for expr in range.clone() {
+                        frame.exprs[expr.index()] = self.eval(function, frame, expr)?;
+                    }
+                    Flow::Next
+                },
+                Statement::Block(block) => self.exec(function, frame, block)?,
+                Statement::If { condition, accept, reject } => {
+                    if self.value(function, frame, *condition)?.as_bool()? {
+                        self.exec(function, frame, accept)?
+                    } else {
+                        self.exec(function, frame, reject)?
+                    }
+                },
+                Statement::Switch { selector, cases } => {
+                    let selector = self.components(function, frame, *selector)?.2[0];
+                    let mut matched = false;
+                    let mut flow = Flow::Next;
+                    for case in cases {
+                        matched |= match case.value {
+                            naga::SwitchValue::I32(value) => value as u32 == selector,
+                            naga::SwitchValue::U32(value) => value == selector,
+                            naga::SwitchValue::Default => true
+                        };
+                        if !matched {
+                            continue;
+                        }
+                        flow = self.exec(function, frame, &case.body)?;
+                        if !case.fall_through {
+                            break;
+                        }
+                    }
+                    match flow {
+                        Flow::Break => Flow::Next,
+                        flow => flow
+                    }
+                },
+                Statement::Loop { body, continuing, break_if } => loop {
+                    match self.exec(function, frame, body)? {
+                        Flow::Break => break Flow::Next,
+                        Flow::Return(value) => break Flow::Return(value),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                    if let Flow::Return(value) = self.exec(function, frame, continuing)? {
+                        break Flow::Return(value);
+                    }
+                    if let Some(break_if) = break_if {
+                        if self.value(function, frame, *break_if)?.as_bool()? {
+                            break Flow::Next;
+                        }
+                    }
+                },
+                Statement::Break => Flow::Break,
+                Statement::Continue => Flow::Continue,
+                Statement::Return { value } => Flow::Return(match value {
+                    Some(value) => self.value(function, frame, *value)?,
+                    None => Value::Undef
+                }),
+                Statement::Store { pointer, value } => {
+                    let Value::Pointer(pointer) = self.value(function, frame, *pointer)? else { return Err("store to a non pointer".to_owned()); };
+                    let value = self.value(function, frame, *value)?;
+                    let root = match pointer.root {
+                        Root::Local(idx) => &mut frame.locals[idx],
+                        Root::Global(idx) => &mut self.globals[idx]
+                    };
+                    store_path(root, pointer.path(), value)?;
+                    Flow::Next
+                },
+                Statement::Call { function: callee, arguments, result } => {
+                    let args = arguments.iter().map(|arg| self.value(function, frame, *arg)).collect::<Result<Vec<_>, _>>()?;
+                    let value = self.call(callee.index(), &self.module.functions[*callee], args)?;
+                    if let Some(result) = result {
+                        frame.exprs[result.index()] = value;
+                    }
+                    Flow::Next
+                },
+                Statement::Barrier(_) => Flow::Next,
+                _ => return Err("unsupported statement".to_owned())
+            };
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // Runs a compute entry point for one invocation
+    fn run(&mut self, entry_point: usize, invocation_id: [u32; 3]) -> Result<(), String> {
+        let entry = &self.module.entry_points[entry_point];
+        let args = entry.function.arguments.iter().map(|arg| match arg.binding {
+            Some(Binding::BuiltIn(BuiltIn::GlobalInvocationId)) => Ok(Value::Vector(Kind::U32, 3, [invocation_id[0], invocation_id[1], invocation_id[2], 0])),
+            _ => Err("unsupported entry point argument".to_owned())
+        }).collect::<Result<Vec<_>, _>>()?;
+        self.call(self.module.functions.len() + entry_point, &entry.function, args)?;
+        Ok(())
+    }
+
+}
+
+// Evaluates the rows of the map export shader on every CPU core. `uniforms` gives the uniform buffer of a row.
+pub fn evaluate_rows(shader: &str, heightmaps: &[Heightmap], width: u32, rows: u32, pixel_floats: usize, uniforms: impl Fn(u32) -> Vec<u8> + Sync) -> Result<Vec<f32>, String> {
+    let module = naga::front::wgsl::parse_str(shader).map_err(|err| err.emit_to_string(shader))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|err| err.emit_to_string(shader))?;
+    let entry_point = module.entry_points.iter().position(|entry| entry.name == "main").ok_or("the shader has no main function")?;
+
+    let next_row = AtomicU32::new(0);
+    let data = Mutex::new(vec![0.0; rows as usize * width as usize * pixel_floats]);
+    let threads = std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| -> Result<(), String> {
+            let mut interpreter = Interpreter::new(&module, heightmaps)?;
+            interpreter.set_float_array(0, 0, width as usize * pixel_floats)?;
+            loop {
+                let row = next_row.fetch_add(1, Ordering::Relaxed);
+                if row >= rows {
+                    return Ok(());
+                }
+                // one row at a time, the first row of the uniforms is the row evaluated
+                interpreter.set_buffer(1, 0, &uniforms(row))?;
+                for x in 0..width {
+                    interpreter.run(entry_point, [x, 0, 0])?;
+                }
+                let row_data = interpreter.float_array(0, 0)?;
+                let start = row as usize * width as usize * pixel_floats;
+                data.lock().unwrap()[start..start + row_data.len()].copy_from_slice(&row_data);
+            }
+        })).collect();
+        workers.into_iter().map(|worker| worker.join().map_err(|_| "evaluation thread panicked".to_owned())?).collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(data.into_inner().unwrap())
+}
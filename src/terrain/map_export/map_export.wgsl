@@ -0,0 +1,65 @@
+
+@group(0) @binding(0)
+var<storage, read_write> map: array<f32>;
+
+struct Uniforms {
+    min: vec2<f32>,
+    pixel_size: vec2<f32>,
+    resolution: vec2<u32>,
+    first_row: u32,
+    steps: u32,
+    min_height: f32,
+    max_height: f32,
+    step: f32
+}
+
+@group(1) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+fn surface_normal(pos: vec3<f32>) -> vec3<f32> {
+    let e = 0.5;
+    return normalize(vec3(
+        sdf(pos + vec3(e, 0.0, 0.0)).terrain.sdf - sdf(pos - vec3(e, 0.0, 0.0)).terrain.sdf,
+        sdf(pos + vec3(0.0, e, 0.0)).terrain.sdf - sdf(pos - vec3(0.0, e, 0.0)).terrain.sdf,
+        sdf(pos + vec3(0.0, 0.0, e)).terrain.sdf - sdf(pos - vec3(0.0, 0.0, e)).terrain.sdf
+    ));
+}
+
+// One pixel per invocation: the highest surface below max_height, followed by the biome weights there
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let row = uniforms.first_row + id.y;
+    if id.x >= uniforms.resolution.x || row >= uniforms.resolution.y {
+        return;
+    }
+
+    let xz = uniforms.min + (vec2<f32>(f32(id.x), f32(row)) + 0.5) * uniforms.pixel_size;
+
+    var y = uniforms.max_height;
+    var d = sdf(vec3(xz.x, y, xz.y)).terrain.sdf;
+    var height = uniforms.max_height;
+    if d > 0.0 {
+        height = uniforms.min_height;
+        for(var i = 0u; i < uniforms.steps; i++) {
+            let next_y = max(y - uniforms.step, uniforms.min_height);
+            let next_d = sdf(vec3(xz.x, next_y, xz.y)).terrain.sdf;
+            if next_d <= 0.0 {
+                height = mix(y, next_y, d / (d - next_d));
+                break;
+            }
+            y = next_y;
+            d = next_d;
+        }
+    }
+
+    let pos = vec3(xz.x, height, xz.y);
+    let terrain_out = sdf(pos);
+    var biome_w = apply_biome_rules(terrain_out.biome_w, terrain_out.rule_values, pos.y - terrain_out.terrain.sdf, surface_normal(pos));
+
+    let idx = (id.y * uniforms.resolution.x + id.x) * (N_BIOMES + 1u);
+    map[idx] = height;
+    for(var i = 0u; i < N_BIOMES; i++) {
+        map[idx + 1u + i] = biome_w[i];
+    }
+}
+
@@ -1,60 +1,364 @@
 
-use std::{collections::HashSet, sync::{Arc, Mutex}};
+use std::{collections::{HashMap, HashSet}, num::NonZeroU64, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, OnceLock}, time::{Duration, Instant}};
 
 use eframe::wgpu::{self, util::DeviceExt};
 
-use super::{Terrain, TerrainChunk, TerrainVertex, CHUNK_SIZE};
+use super::{ChunkKey, ChunkMesh, Terrain, TerrainChunk, TerrainVertex, TerrainView};
 
 mod tri_table;
+mod cache;
+
+use cache::{CacheKey, CacheLookup, MeshCache};
+
+// Larger heightmaps are scaled down to fit, the largest 2d texture every wgpu device supports
+pub const MAX_HEIGHTMAP_RES: u32 = 8192;
+
+// A chunk is split into its children while the lod center is closer to it than this many times its size
+const LOD_DISTANCE: f32 = 1.5;
+const MAX_LOD: u32 = 16;
+// Chunks meshed together in one submission. The batch size adapts to the frame time, up to this.
+const MAX_BATCH: usize = 32;
+// Memory for the grid values of a batch, which limits the batch size of large chunks
+const BATCH_MEMORY: u64 = 64 << 20;
+// Batches grow while frames are faster than this and shrink when they're slower, a little over a 60hz frame
+const TARGET_FRAME_TIME: Duration = Duration::from_millis(20);
+// Each chunk of a batch has its own uniforms at a multiple of this, the largest offset alignment wgpu allows
+const UNIFORM_STRIDE: u64 = 256;
+
+pub const CHUNK_SIZES: [u32; 4] = [16, 32, 64, 128];
+
+// Directory in the project directory that chunk meshes are cached in
+pub const CHUNK_CACHE_DIR: &str = "chunk_cache";
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Mesher {
+    #[default]
+    MarchingCubes,
+    // one vertex per cell at the average of its edge crossings, smooth
+    SurfaceNets,
+    // one vertex per cell fit to the planes of its edge crossings, keeps sharp edges
+    DualContouring
+}
+
+impl Mesher {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Mesher::MarchingCubes => "Marching Cubes",
+            Mesher::SurfaceNets => "Surface Nets",
+            Mesher::DualContouring => "Dual Contouring",
+        }
+    }
+
+    // value of the mesher uniform in the meshgen shaders and of MESHER in the unreal export
+    pub fn id(&self) -> u32 {
+        match self {
+            Mesher::MarchingCubes => 0,
+            Mesher::SurfaceNets => 1,
+            Mesher::DualContouring => 2,
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MeshSettings {
+    pub mesher: Mesher,
+    // voxels along each axis of a chunk, one of CHUNK_SIZES
+    pub chunk_size: u32,
+    // world units between the voxels of the finest lod
+    pub voxel_size: f32
+}
+
+impl Default for MeshSettings {
+
+    fn default() -> Self {
+        Self {
+            mesher: Mesher::MarchingCubes,
+            chunk_size: 64,
+            voxel_size: 1.0
+        }
+    }
+
+}
 
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
-    begin: glam::Vec4
+    begin: glam::Vec3,
+    scale: f32,
+    skirts: u32,
+    mesher: u32,
+    chunk_size: u32,
+    max_verts: u32,
+    max_indices: u32,
+    count_only: u32,
+    _padding: [u32; 2]
 }
 
-#[repr(C)]
-#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct TerrainMeshgenData {
-    curr_idx: u32
+// The passes of a mesher: sdf values at the grid points, then the vertices, then the faces joining them
+struct MesherPipelines {
+    values: wgpu::ComputePipeline,
+    vertices: wgpu::ComputePipeline,
+    faces: wgpu::ComputePipeline
+}
+
+impl MesherPipelines {
+
+    fn new(device: &wgpu::Device, layout: &wgpu::PipelineLayout, label: &str, mesher_code: &str, faces_entry_point: &str, sdf_code: &str) -> Self {
+        let source = include_str!("meshgen/common.wgsl").to_owned() + mesher_code + sdf_code;
+
+        let shader = device.create_shader_module(
+            wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source.into())
+            }
+        );
+
+        let make_pipeline = |entry_point| device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                module: &shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }
+        );
+
+        Self {
+            values: make_pipeline("values_main"),
+            vertices: make_pipeline("vertices_main"),
+            faces: make_pipeline(faces_entry_point)
+        }
+    }
+
+    fn marching_cubes(device: &wgpu::Device, layout: &wgpu::PipelineLayout, sdf_code: &str) -> Self {
+        Self::new(device, layout, "terrain_marching_cubes", include_str!("meshgen/meshgen.wgsl"), "triangles_main", sdf_code)
+    }
+
+    // surface nets and dual contouring
+    fn dual(device: &wgpu::Device, layout: &wgpu::PipelineLayout, sdf_code: &str) -> Self {
+        Self::new(device, layout, "terrain_dual", include_str!("meshgen/dual.wgsl"), "quads_main", sdf_code)
+    }
+
+    // The grid values are only computed while counting, writing the mesh reuses them
+    fn dispatch<'a>(&'a self, compute_pass: &mut wgpu::ComputePass<'a>, chunk_size: u32, count_only: bool) {
+        if count_only {
+            let grid_groups = (chunk_size + 2).div_ceil(4);
+            compute_pass.set_pipeline(&self.values);
+            compute_pass.dispatch_workgroups(grid_groups, grid_groups, grid_groups);
+        }
+
+        let vertex_groups = (chunk_size + 1).div_ceil(4);
+        compute_pass.set_pipeline(&self.vertices);
+        compute_pass.dispatch_workgroups(vertex_groups, vertex_groups, vertex_groups);
+
+        let face_groups = chunk_size.div_ceil(4);
+        compute_pass.set_pipeline(&self.faces);
+        compute_pass.dispatch_workgroups(face_groups, face_groups, face_groups);
+    }
+
+}
+
+// The buffers of one chunk of a batch that are kept between counting and writing its mesh
+struct ChunkSlot {
+    grid_values: wgpu::Buffer,
+    counts: wgpu::Buffer,
+    count_bind_group: wgpu::BindGroup
+}
+
+// Scratch buffers for meshing batches of chunks of one chunk size
+struct MeshScratch {
+    // shared by the chunks of a batch, they're meshed one after the other
+    vert_map: wgpu::Buffer,
+    slots: Vec<ChunkSlot>
+}
+
+// Chunks whose vertices and indices are being counted. Chunk i uses slot i.
+struct ChunkBatch {
+    chunks: Vec<(ChunkKey, u32)>,
+    counts_read_buffer: wgpu::Buffer,
+    mapped: Arc<AtomicBool>
 }
 
 pub struct TerrainMeshGenerator {
     uniform_buffer: wgpu::Buffer, 
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
     uniform_bind_group: wgpu::BindGroup,
+    heightmap_texture: wgpu::Texture,
 
-    tricount_pipeline: wgpu::ComputePipeline,
-    tricount_layout: wgpu::PipelineLayout,
-    tricount_bind_group: wgpu::BindGroup,
-    tricount_buffer: wgpu::Buffer,
-    tricount_read_buffer: wgpu::Buffer,
-
-    meshgen_pipeline: wgpu::ComputePipeline,
-    meshgen_layout: wgpu::PipelineLayout,
     meshgen_bind_group_layout: wgpu::BindGroupLayout,
-    meshgen_data_buffer: wgpu::Buffer,
-    meshgen_tri_table_buffer: wgpu::Buffer,
+    meshgen_layout: wgpu::PipelineLayout,
+    tri_table_buffer: wgpu::Buffer,
+    marching_cubes: MesherPipelines,
+    dual: MesherPipelines,
+
+    scratch: MeshScratch,
+    batch: Option<ChunkBatch>,
+    batch_size: usize,
+    last_frame: Option<Instant>,
+
+    cache: MeshCache,
+    sdf_hash: u64,
+    heightmap_hash: u64,
 
-    generation_priority: Vec<glam::I64Vec3>
+    pub settings: MeshSettings,
+    generation_priority: Vec<ChunkKey>
 }
 
-impl TerrainMeshGenerator {
+// buffers in binding order: mesh, counts, vertex map, indices, marching cubes table, grid values
+fn make_meshgen_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffers: [&wgpu::Buffer; 6]) -> wgpu::BindGroup {
+    let entries: Vec<wgpu::BindGroupEntry> = buffers.iter().enumerate().map(|(binding, buffer)| wgpu::BindGroupEntry {
+        binding: binding as u32,
+        resource: buffer.as_entire_binding(),
+    }).collect();
+
+    device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            label: Some("terrain_meshgen_bind_group"),
+            layout,
+            entries: &entries
+        }
+    )
+}
+
+// The meshers' code is hashed too, meshes from before it changed are stale
+// FNV-1a. The hashes name the disk cache's directories, so unlike DefaultHasher they stay the same between runs and builds.
+pub struct StableHasher(u64);
+
+impl StableHasher {
+
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.write(&val.to_le_bytes());
+    }
+
+    // prefixed by the length, so consecutive strings can't run into each other
+    pub fn write_str(&mut self, val: &str) {
+        self.write_u64(val.len() as u64);
+        self.write(val.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+}
+
+fn hash_sdf(sdf_code: &str) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_str(include_str!("meshgen/common.wgsl"));
+    hasher.write_str(include_str!("meshgen/meshgen.wgsl"));
+    hasher.write_str(include_str!("meshgen/dual.wgsl"));
+    hasher.write_u64(std::mem::size_of::<TerrainVertex>() as u64);
+    hasher.write_str(sdf_code);
+    hasher.finish()
+}
 
-    fn make_tricount_shader(sdf_code: &str) -> String {
-        include_str!("meshgen/tricount.wgsl").to_string() + sdf_code
+fn hash_heightmaps(heightmaps: &[Heightmap]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_u64(heightmaps.len() as u64);
+    for heightmap in heightmaps {
+        hasher.write_u64(heightmap.width as u64);
+        hasher.write_u64(heightmap.height as u64);
+        for height in &heightmap.heights {
+            hasher.write(&height.to_le_bytes());
+        }
     }
-    
-    fn make_meshgen_shader(sdf_code: &str) -> String {
-        include_str!("meshgen/meshgen.wgsl").to_string() + sdf_code
+    hasher.finish()
+}
+
+fn is_missing(terrain: &Terrain, chunks_needed: &HashMap<ChunkKey, u32>, key: &ChunkKey) -> bool {
+    // chunks whose skirts changed are regenerated too
+    chunks_needed.get(key).is_some_and(|skirts| terrain.chunks.get(key).map(|chunk| chunk.skirts) != Some(*skirts))
+}
+
+// An imported heightmap at the image's resolution, the heights are in 0..1 row by row
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    pub heights: Vec<f32>
+}
+
+// Width, height and layers of the texture holding `heightmaps`. It's as large as the largest heightmap.
+// At least two layers, the GL backend makes single layer textures plain 2d ones that an array view can't read.
+pub fn heightmap_texture_size(heightmaps: &[Heightmap]) -> [u32; 3] {
+    let width = heightmaps.iter().map(|heightmap| heightmap.width).max().unwrap_or(1);
+    let height = heightmaps.iter().map(|heightmap| heightmap.height).max().unwrap_or(1);
+    [width.max(1), height.max(1), (heightmaps.len() as u32).max(2)]
+}
+
+fn make_heightmap_texture(device: &wgpu::Device, heightmaps: &[Heightmap]) -> wgpu::Texture {
+    let [width, height, layers] = heightmap_texture_size(heightmaps);
+    device.create_texture(
+        &wgpu::TextureDescriptor {
+            label: Some("terrain_heightmap_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        }
+    )
+}
+
+// Uploads every heightmap as a layer of a heightmap texture. Layer indices match the order of `heightmaps`.
+// The texture is as large as the largest heightmap, each layer's heightmap is in its top left corner at its own size.
+pub fn upload_heightmaps(device: &wgpu::Device, queue: &wgpu::Queue, heightmaps: &[Heightmap]) -> wgpu::Texture {
+    let texture = make_heightmap_texture(device, heightmaps);
+    for (layer, heightmap) in heightmaps.iter().enumerate() {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&heightmap.heights),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * heightmap.width),
+                rows_per_image: Some(heightmap.height),
+            },
+            wgpu::Extent3d {
+                width: heightmap.width,
+                height: heightmap.height,
+                depth_or_array_layers: 1,
+            }
+        );
     }
+    texture
+}
+
+impl TerrainMeshGenerator {
 
     pub fn new(device: &wgpu::Device, sdf_code: &str) -> Self {
 
-        let uniform_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
+        let uniform_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
                 label: Some("terrain_uniform_buffer"),
-                contents: bytemuck::cast_slice(&[Uniforms { begin: glam::Vec4::ZERO }]),
+                size: MAX_BATCH as u64 * UNIFORM_STRIDE,
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
             }
         );
 
@@ -67,136 +371,50 @@ impl TerrainMeshGenerator {
                         visibility: wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
+                            has_dynamic_offset: true,
                             min_binding_size: None
                         },
                         count: None,
-                    }
-                ]
-            }
-        );
-
-        let uniform_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("terrain_bind_group"),
-                layout: &uniform_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: uniform_buffer.as_entire_binding(),
-                    }
-                ],
-            }
-        );
-
-        let tricount_bind_group_layout = device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                label: Some("terrain_tricount_bind_group_layout"),
-                entries: &[
+                    },
                     wgpu::BindGroupLayoutEntry {
-                        binding: 0,
+                        binding: 1,
                         visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false
                         },
                         count: None,
                     }
-                ] 
-            }
-        );
-
-        let tricount_buffer = device.create_buffer(
-            &wgpu::BufferDescriptor {
-                label: Some("terrain_tricount_buffer"),
-                size: std::mem::size_of::<u32>() as u64,
-                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
-                mapped_at_creation: false,
-            }
-        );
-
-        let tricount_read_buffer = device.create_buffer(
-            &wgpu::BufferDescriptor {
-                label: Some("terrain_tricount_read_buffer"),
-                size: std::mem::size_of::<u32>() as u64,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            }
-        );
-
-        let tricount_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("terrain_tricount_bind_group"),
-                layout: &tricount_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: tricount_buffer.as_entire_binding(),
-                    }
                 ]
             }
         );
 
-        let tricount_layout = device.create_pipeline_layout(
-            &wgpu::PipelineLayoutDescriptor {
-                label: Some("terrain_tricount_pipeline_layout"),
-                bind_group_layouts: &[&tricount_bind_group_layout, &uniform_bind_group_layout],
-                push_constant_ranges: &[]
-            }
-        );
-
-        let tricount_shader = device.create_shader_module(
-            wgpu::ShaderModuleDescriptor {
-                label: Some("terrain_tricount_shader"),
-                source: wgpu::ShaderSource::Wgsl(Self::make_tricount_shader(sdf_code).into()),
-            }
-        );
-
-        let tricount_pipeline = device.create_compute_pipeline(
-            &wgpu::ComputePipelineDescriptor {
-                label: Some("terrain_tricount_pipeline"),
-                layout: Some(&tricount_layout),
-                module: &tricount_shader,
-                entry_point: "main",
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }
-        );
+        let heightmap_texture = make_heightmap_texture(device, &[]);
+        let uniform_bind_group = Self::make_uniform_bind_group(device, &uniform_bind_group_layout, &uniform_buffer, &heightmap_texture);
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None,
+        };
 
+        // mesh, counts, vertex map, indices, marching cubes table, grid values
         let meshgen_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: Some("terrain_meshgen_bind_group_layout"),
                 entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None
-                        },
-                        count: None,
-                    }
+                    storage_entry(0, false),
+                    storage_entry(1, false),
+                    storage_entry(2, false),
+                    storage_entry(3, false),
+                    storage_entry(4, true),
+                    storage_entry(5, false)
                 ]
             }
         );
@@ -205,159 +423,281 @@ impl TerrainMeshGenerator {
             &wgpu::PipelineLayoutDescriptor {
                 label: Some("terrain_meshgen_pipeline_layout"),
                 bind_group_layouts: &[&meshgen_bind_group_layout, &uniform_bind_group_layout],
-                push_constant_ranges: &[] 
-            }
-        );
-
-        let meshgen_shader = device.create_shader_module(
-            wgpu::ShaderModuleDescriptor {
-                label: Some("terrain_meshgen_shader"),
-                source: wgpu::ShaderSource::Wgsl(Self::make_meshgen_shader(sdf_code).into())
-            }
-        );
-
-        let meshgen_pipeline = device.create_compute_pipeline(
-            &wgpu::ComputePipelineDescriptor {
-                label: Some("terrain_meshgen"),
-                layout: Some(&meshgen_layout),
-                module: &meshgen_shader,
-                entry_point: "main",
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                push_constant_ranges: &[]
             }
         );
 
-        let meshgen_uniform_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("terrain_meshgen_uniforms"),
-                contents: bytemuck::cast_slice(&[TerrainMeshgenData::default()]),
-                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            }
-        );
+        let marching_cubes = MesherPipelines::marching_cubes(device, &meshgen_layout, sdf_code);
+        let dual = MesherPipelines::dual(device, &meshgen_layout, sdf_code);
 
-        let meshgen_tri_table_buffer = device.create_buffer_init(
+        let tri_table_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("terrain_meshgen_tri_table_buffer"),
                 contents: bytemuck::cast_slice(&tri_table::TRI_TABLE),
-                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                usage: wgpu::BufferUsages::STORAGE,
             }
         );
 
+        let settings = MeshSettings::default();
+        let scratch = Self::make_scratch(device, &meshgen_bind_group_layout, &tri_table_buffer, &settings);
+
         Self {
             uniform_buffer,
+            uniform_bind_group_layout,
             uniform_bind_group,
+            heightmap_texture,
 
-            tricount_pipeline,
-            tricount_layout,
-            tricount_bind_group,
-            tricount_buffer,
-            tricount_read_buffer,
-
-            meshgen_pipeline,
-            meshgen_layout,
             meshgen_bind_group_layout,
-            meshgen_data_buffer: meshgen_uniform_buffer,
-            meshgen_tri_table_buffer,
+            meshgen_layout,
+            tri_table_buffer,
+            marching_cubes,
+            dual,
 
+            batch_size: 4.min(scratch.slots.len()),
+            scratch,
+            batch: None,
+            last_frame: None,
+
+            cache: MeshCache::new(),
+            sdf_hash: hash_sdf(sdf_code),
+            heightmap_hash: hash_heightmaps(&[]),
+
+            settings,
             generation_priority: Vec::new()
         }
 
     }
 
-    fn generate_chunk(&self, device: &wgpu::Device, queue: &wgpu::Queue, begin: glam::Vec3, tri_counter: Arc<Mutex<u64>>) -> TerrainChunk {
-
-        let mut encoder = device.create_command_encoder(
-            &wgpu::CommandEncoderDescriptor {
-                label: Some("terrain_meshgen_encoder"),
-            }
-        );
-        
-        let mut compute_pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor {
-                label: Some("terrain_tricount"),
-                timestamp_writes: None 
+    fn make_scratch(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, tri_table_buffer: &wgpu::Buffer, settings: &MeshSettings) -> MeshScratch {
+        let make_storage_buffer = |label, size: u64, usage| device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+                mapped_at_creation: false,
             }
         );
 
-        // Set up shared uniforms
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[
-            Uniforms {
-                begin: glam::vec4(begin.x, begin.y, begin.z, 0.0)
+        // marching cubes has a vertex per edge, three per grid point
+        let vert_map = make_storage_buffer("terrain_meshgen_vert_map_buffer", 3 * (settings.chunk_size as u64 + 1).pow(3) * std::mem::size_of::<u32>() as u64, wgpu::BufferUsages::empty());
+        // stands in for the mesh and index buffers while counting
+        let empty = make_storage_buffer("terrain_meshgen_empty_buffer", std::mem::size_of::<u32>() as u64, wgpu::BufferUsages::empty());
+
+        let grid_values_size = (settings.chunk_size as u64 + 2).pow(3) * std::mem::size_of::<f32>() as u64;
+        let n_slots = ((BATCH_MEMORY / grid_values_size) as usize).clamp(1, MAX_BATCH);
+        let slots = (0..n_slots).map(|_| {
+            let grid_values = make_storage_buffer("terrain_meshgen_grid_values_buffer", grid_values_size, wgpu::BufferUsages::empty());
+            let counts = make_storage_buffer("terrain_meshgen_counts_buffer", 2 * std::mem::size_of::<u32>() as u64, wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST);
+            let count_bind_group = make_meshgen_bind_group(device, layout, [&empty, &counts, &vert_map, &empty, tri_table_buffer, &grid_values]);
+            ChunkSlot {
+                grid_values,
+                counts,
+                count_bind_group
             }
-        ])); 
-
-        // Count the triangles in the chunk's mesh
-        queue.write_buffer(&self.tricount_buffer, 0, bytemuck::cast_slice(&[0u32]));
-        
-        compute_pass.set_pipeline(&self.tricount_pipeline);
-        compute_pass.set_bind_group(0, &self.tricount_bind_group, &[]);
-        compute_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-        compute_pass.dispatch_workgroups(CHUNK_SIZE / 16, CHUNK_SIZE / 16, CHUNK_SIZE);
+        }).collect();
 
-        drop(compute_pass);
-
-        encoder.copy_buffer_to_buffer(&self.tricount_buffer, 0, &self.tricount_read_buffer, 0, std::mem::size_of::<u32>() as u64);
+        MeshScratch {
+            vert_map,
+            slots
+        }
+    }
 
-        queue.submit([encoder.finish()]);
+    // Chunks meshed with other settings have to be cleared by the caller
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: MeshSettings) {
+        if settings.chunk_size != self.settings.chunk_size {
+            self.scratch = Self::make_scratch(device, &self.meshgen_bind_group_layout, &self.tri_table_buffer, &settings);
+            self.batch_size = self.batch_size.min(self.scratch.slots.len());
+        }
+        self.settings = settings;
+        self.batch = None;
+    }
 
-        self.tricount_read_buffer.slice(..).map_async(wgpu::MapMode::Read, move |_| {});
+    fn make_uniform_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, uniform_buffer: &wgpu::Buffer, heightmap_texture: &wgpu::Texture) -> wgpu::BindGroup {
+        let heightmap_view = heightmap_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
 
-        device.poll(wgpu::MaintainBase::Wait);
+        device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("terrain_bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: uniform_buffer,
+                            offset: 0,
+                            size: NonZeroU64::new(std::mem::size_of::<Uniforms>() as u64)
+                        }),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&heightmap_view),
+                    }
+                ],
+            }
+        )
+    }
 
-        let tricount = {
-            let bytes = self.tricount_read_buffer.slice(..).get_mapped_range();
-            u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
-        };
+    pub fn update_heightmaps(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, heightmaps: &[Heightmap]) {
+        self.heightmap_texture = upload_heightmaps(device, queue, heightmaps);
+        self.uniform_bind_group = Self::make_uniform_bind_group(device, &self.uniform_bind_group_layout, &self.uniform_buffer, &self.heightmap_texture);
+        self.heightmap_hash = hash_heightmaps(heightmaps);
+        self.batch = None;
+    }
 
-        self.tricount_read_buffer.unmap();
+    // Without counts the chunk is only counted
+    fn write_uniforms(&self, queue: &wgpu::Queue, slot: usize, key: ChunkKey, skirts: u32, counts: Option<(u32, u32)>) {
+        let (max_verts, max_indices) = counts.unwrap_or((u32::MAX, u32::MAX));
+        queue.write_buffer(&self.uniform_buffer, slot as u64 * UNIFORM_STRIDE, bytemuck::cast_slice(&[
+            Uniforms {
+                begin: key.begin(&self.settings),
+                scale: key.scale(&self.settings),
+                skirts,
+                mesher: self.settings.mesher.id(),
+                chunk_size: self.settings.chunk_size,
+                max_verts,
+                max_indices,
+                count_only: counts.is_none() as u32,
+                _padding: [0; 2]
+            }
+        ]));
+    }
 
-        // If we have no triangles, we don't need a mesh
-        if tricount == 0 {
-            return TerrainChunk::new(None, 0, tri_counter);
+    fn pipelines(&self) -> &MesherPipelines {
+        match self.settings.mesher {
+            Mesher::MarchingCubes => &self.marching_cubes,
+            Mesher::SurfaceNets | Mesher::DualContouring => &self.dual
         }
+    }
 
-        // Generate the mesh
+    // Counts the vertices and indices of each chunk in one submission. The counts are read back without waiting for the gpu.
+    fn start_batch(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunks: Vec<(ChunkKey, u32)>) {
         let mut encoder = device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor {
-                label: Some("terrain_meshgen_encoder"),
+                label: Some("terrain_meshgen_count_encoder"),
             }
         );
 
-        let mesh = device.create_buffer(
+        for (slot, (key, skirts)) in chunks.iter().enumerate() {
+            self.write_uniforms(queue, slot, *key, *skirts, None);
+            encoder.clear_buffer(&self.scratch.slots[slot].counts, 0, None);
+        }
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(
+                &wgpu::ComputePassDescriptor {
+                    label: Some("terrain_meshgen_count"),
+                    timestamp_writes: None 
+                }
+            );
+
+            for slot in 0..chunks.len() {
+                compute_pass.set_bind_group(0, &self.scratch.slots[slot].count_bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.uniform_bind_group, &[(slot as u64 * UNIFORM_STRIDE) as u32]);
+                self.pipelines().dispatch(&mut compute_pass, self.settings.chunk_size, true);
+            }
+        }
+
+        let counts_size = 2 * std::mem::size_of::<u32>() as u64;
+        let counts_read_buffer = device.create_buffer(
             &wgpu::BufferDescriptor {
-                label: Some("terrain_chunk_mesh"),
-                size:  (tricount as u64) * 3 * (std::mem::size_of::<TerrainVertex>() as u64), 
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                label: Some("terrain_meshgen_counts_read_buffer"),
+                size: chunks.len() as u64 * counts_size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
                 mapped_at_creation: false,
             }
         );
+        for slot in 0..chunks.len() {
+            encoder.copy_buffer_to_buffer(&self.scratch.slots[slot].counts, 0, &counts_read_buffer, slot as u64 * counts_size, counts_size);
+        }
 
-        queue.write_buffer(&self.meshgen_data_buffer, 0, bytemuck::cast_slice(&[
-            TerrainMeshgenData {
-                curr_idx: 0,
-            }
-        ]));
-        
-        let meshgen_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("terrain_meshgen_bind_group"),
-                layout: &self.meshgen_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: mesh.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: self.meshgen_data_buffer.as_entire_binding()
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: self.meshgen_tri_table_buffer.as_entire_binding()
-                    }
-                ],
+        queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(AtomicBool::new(false));
+        let callback_mapped = mapped.clone();
+        counts_read_buffer.slice(..).map_async(wgpu::MapMode::Read, move |_| callback_mapped.store(true, Ordering::Release));
+
+        self.batch = Some(ChunkBatch {
+            chunks,
+            counts_read_buffer,
+            mapped
+        });
+    }
+
+    // Meshes the counted chunks of a batch into buffers of their size, in one submission, and adds them to the terrain
+    fn finish_batch(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, batch: ChunkBatch, terrain: &mut Terrain, chunks_needed: &HashMap<ChunkKey, u32>, tri_counter: &Arc<Mutex<u64>>) {
+        let counts: Vec<u32> = bytemuck::cast_slice(&batch.counts_read_buffer.slice(..).get_mapped_range()).to_vec();
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("terrain_meshgen_encoder"),
             }
         );
 
+        let mut meshed = Vec::new();
+        let mut new_meshes = Vec::new();
+        let mesh_hash = self.mesh_hash();
+        for (slot, (key, skirts)) in batch.chunks.into_iter().enumerate() {
+            // the view might have moved on while the chunk was counted
+            if !is_missing(terrain, chunks_needed, &key) {
+                continue;
+            }
+
+            let verts = counts[2 * slot];
+            let indices = counts[2 * slot + 1];
+            let vertex_bytes = verts as u64 * std::mem::size_of::<TerrainVertex>() as u64;
+            let index_bytes = indices as u64 * std::mem::size_of::<u32>() as u64;
+
+            // A mesh the device can't hold is left out of the terrain and the cache, the viewport reports it
+            let max_bytes = device.limits().max_buffer_size.min(device.limits().max_storage_buffer_binding_size as u64);
+            if vertex_bytes.max(index_bytes) > max_bytes {
+                self.add_chunk(terrain, chunks_needed, key, skirts, None, tri_counter);
+                if let Some(chunk) = terrain.chunks.get_mut(&key) {
+                    chunk.too_large = true;
+                }
+                continue;
+            }
+
+            // If we have no triangles, we don't need a mesh
+            let mesh = if indices == 0 {
+                None
+            } else {
+                let mesh = ChunkMesh {
+                    vertices: device.create_buffer(
+                        &wgpu::BufferDescriptor {
+                            label: Some("terrain_chunk_vertices"),
+                            size: vertex_bytes,
+                            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }
+                    ),
+                    indices: device.create_buffer(
+                        &wgpu::BufferDescriptor {
+                            label: Some("terrain_chunk_indices"),
+                            size: index_bytes,
+                            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }
+                    ),
+                    render_bind_group: OnceLock::new()
+                };
+
+                let chunk_slot = &self.scratch.slots[slot];
+                self.write_uniforms(queue, slot, key, skirts, Some((verts, indices)));
+                encoder.clear_buffer(&chunk_slot.counts, 0, None);
+                meshed.push((slot, make_meshgen_bind_group(device, &self.meshgen_bind_group_layout, [
+                    &mesh.vertices, &chunk_slot.counts, &self.scratch.vert_map, &mesh.indices, &self.tri_table_buffer, &chunk_slot.grid_values
+                ])));
+
+                Some(Arc::new(mesh))
+            };
+
+            new_meshes.push((CacheKey { mesh_hash, chunk: key, skirts }, mesh.clone()));
+            self.add_chunk(terrain, chunks_needed, key, skirts, mesh, tri_counter);
+        }
+
         {
             let mut compute_pass = encoder.begin_compute_pass(
                 &wgpu::ComputePassDescriptor {
@@ -366,122 +706,252 @@ impl TerrainMeshGenerator {
                 }
             );
 
-            compute_pass.set_pipeline(&self.meshgen_pipeline);
-            compute_pass.set_bind_group(0, &meshgen_bind_group, &[]);
-            compute_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-            compute_pass.dispatch_workgroups(CHUNK_SIZE / 16, CHUNK_SIZE / 16, CHUNK_SIZE);
+            for (slot, bind_group) in &meshed {
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.uniform_bind_group, &[(*slot as u64 * UNIFORM_STRIDE) as u32]);
+                self.pipelines().dispatch(&mut compute_pass, self.settings.chunk_size, false);
+            }
         }
 
         queue.submit([encoder.finish()]);
+        self.cache.insert(device, queue, new_meshes);
+    }
+
+    // Shows a chunk, finer chunks it replaces go right away and coarser ones once the rest of their children are there
+    fn add_chunk(&mut self, terrain: &mut Terrain, chunks_needed: &HashMap<ChunkKey, u32>, key: ChunkKey, skirts: u32, mesh: Option<Arc<ChunkMesh>>, tri_counter: &Arc<Mutex<u64>>) {
+        if mesh.is_some() {
+            // chunk contains some triangles, therefore nearby chunks are likely to contain more.
+            // prioritize them.
+            for axis in glam::I64Vec3::AXES {
+                self.generation_priority.push(ChunkKey { loc: key.loc + axis, lod: key.lod });
+                self.generation_priority.push(ChunkKey { loc: key.loc - axis, lod: key.lod });
+            }
+        }
 
-        TerrainChunk::new(Some(mesh), tricount, tri_counter) 
+        let tris = mesh.as_ref().map(|mesh| mesh.tris()).unwrap_or(0);
+        terrain.chunks.retain(|other, _| other.lod >= key.lod || !other.overlaps(&key) || chunks_needed.contains_key(other));
+        terrain.chunks.insert(key, TerrainChunk::new(mesh, tris, skirts, key.center(&self.settings), tri_counter.clone()));
+    }
 
+    // Hash of everything a chunk's mesh depends on besides the chunk and its skirts
+    fn mesh_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.sdf_hash);
+        hasher.write_u64(self.heightmap_hash);
+        hasher.write_u64(self.settings.mesher.id() as u64);
+        hasher.write_u64(self.settings.chunk_size as u64);
+        hasher.write_u64(self.settings.voxel_size.to_bits() as u64);
+        hasher.finish()
     }
 
-    // returns whether there are more chunks to generate
-    pub fn generate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, terrain: &mut Terrain, center: glam::Vec3, size: f32, tri_counter: &Arc<Mutex<u64>>) -> bool {
-        let min = center - glam::Vec3::splat(size / 2.0);
-        let max = center + glam::Vec3::splat(size / 2.0);
-
-        let min_int = (min / (CHUNK_SIZE as f32)).floor();
-        let min_int = glam::I64Vec3::new(min_int.x as i64, min_int.y as i64, min_int.z as i64);
-        let max_int = (max / (CHUNK_SIZE as f32)).ceil();
-        let max_int = glam::I64Vec3::new(max_int.x as i64, max_int.y as i64, max_int.z as i64);
-
-        let mut chunks_needed = HashSet::new();
-        for x in min_int.x..max_int.x {
-            for y in min_int.y..max_int.y {
-                for z in min_int.z..max_int.z {
-                    let loc = glam::i64vec3(x, y, z);
-                    chunks_needed.insert(loc);
+    // Meshes are also cached on disk in the directory, if there is one
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.cache.set_dir(dir);
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    fn intersects(&self, key: &ChunkKey, min: glam::Vec3, max: glam::Vec3) -> bool {
+        let begin = key.begin(&self.settings);
+        let end = begin + glam::Vec3::splat(key.size(&self.settings));
+        begin.cmplt(max).all() && end.cmpgt(min).all()
+    }
+
+    // The leaves of the chunk octree covering min..max, with the faces of each that need skirts.
+    // Chunks get finer closer to lod_center.
+    fn select_chunks(&self, lod_center: glam::Vec3, min: glam::Vec3, max: glam::Vec3) -> HashMap<ChunkKey, u32> {
+        let intersects = |key: &ChunkKey| self.intersects(key, min, max);
+
+        let finest_size = self.settings.chunk_size as f32 * self.settings.voxel_size;
+        let root_lod = ((max - min).max_element() / finest_size).log2().ceil().clamp(0.0, MAX_LOD as f32) as u32;
+        let root_size = finest_size * (1u64 << root_lod) as f32;
+        let root_min = (min / root_size).floor().as_i64vec3();
+        let root_max = (max / root_size).ceil().as_i64vec3();
+
+        let mut to_split = Vec::new();
+        for x in root_min.x..root_max.x {
+            for y in root_min.y..root_max.y {
+                for z in root_min.z..root_max.z {
+                    to_split.push(ChunkKey { loc: glam::i64vec3(x, y, z), lod: root_lod });
                 }
             }
         }
 
-        terrain.chunks.retain(|loc, _| chunks_needed.contains(loc));
-        self.generation_priority.retain(|loc| chunks_needed.contains(loc) && !terrain.chunks.contains_key(loc));
-
-        // We'll generate chunks closer to the center first
-        let mut chunks_to_generate: Vec<glam::I64Vec3> = chunks_needed.clone().into_iter().collect();
-        let center_int = (min_int + max_int) / 2;
-        let chunk_sorter = |a: &glam::I64Vec3, b: &glam::I64Vec3| b.distance_squared(center_int).cmp(&a.distance_squared(center_int));
-        chunks_to_generate.sort_by(chunk_sorter); 
-        self.generation_priority.sort_by(chunk_sorter);
-        
-        // Generate a fixed number of chunks per frame 
-        let mut chunks_per_frame = 1;
-        while chunks_per_frame > 0 {
-            let loc = if let Some(loc) = self.generation_priority.pop() {
-                loc
-            } else if let Some(loc) = chunks_to_generate.pop() {
-                loc
-            } else {
-                break;
-            };
-            if terrain.chunks.get(&loc).is_some() {
+        let mut leaves = HashSet::new();
+        while let Some(key) = to_split.pop() {
+            if !intersects(&key) {
                 continue;
             }
-            chunks_per_frame -= 1;
-            let chunk_begin = glam::vec3((loc.x * (CHUNK_SIZE as i64)) as f32, (loc.y * (CHUNK_SIZE as i64)) as f32, (loc.z * (CHUNK_SIZE as i64)) as f32);
-            let chunk = self.generate_chunk(
-                device,
-                queue,
-                chunk_begin,
-                tri_counter.clone()
-            );
-            if chunk.mesh.is_some() {
-                // chunk contains some triangles, therefore nearby chunks are likely to contain more.
-                // prioritize them.
-                for axis in glam::I64Vec3::AXES {
-                    self.generation_priority.push(loc + axis);
-                    self.generation_priority.push(loc - axis);
-                }
+            let begin = key.begin(&self.settings);
+            let size = key.size(&self.settings);
+            let dist = lod_center.distance(lod_center.clamp(begin, begin + glam::Vec3::splat(size)));
+            if key.lod > 0 && dist < LOD_DISTANCE * size {
+                to_split.extend(key.children());
+            } else {
+                leaves.insert(key);
             }
-            terrain.chunks.insert(loc, chunk);
         }
 
-        return !chunks_to_generate.is_empty();
+        // faces next to a leaf of another lod get skirts
+        leaves.iter().map(|key| {
+            let mut skirts = 0;
+            for (axis_idx, axis) in glam::I64Vec3::AXES.into_iter().enumerate() {
+                for (dir_idx, dir) in [-1, 1].into_iter().enumerate() {
+                    let neighbor = ChunkKey { loc: key.loc + axis * dir, lod: key.lod };
+                    if !leaves.contains(&neighbor) && intersects(&neighbor) {
+                        skirts |= 1 << (2 * axis_idx + dir_idx);
+                    }
+                }
+            }
+            (*key, skirts)
+        }).collect()
     }
 
-    pub fn update_shaders(&mut self, device: &wgpu::Device, sdf_code: &str) {
+    // Hides the octants of old chunks that the finer chunks replacing them already cover, so the two don't overlap
+    fn hide_replaced(&self, terrain: &mut Terrain, min: glam::Vec3, max: glam::Vec3) {
+        let mut ancestors = HashSet::new();
+        for key in terrain.chunks.keys() {
+            let mut ancestor = *key;
+            for _ in key.lod..MAX_LOD {
+                ancestor = ChunkKey { loc: ancestor.loc >> 1, lod: ancestor.lod + 1 };
+                if !ancestors.insert(ancestor) {
+                    break;
+                }
+            }
+        }
 
-        let tricount_shader = device.create_shader_module(
-            wgpu::ShaderModuleDescriptor {
-                label: Some("terrain_tricount_shader"),
-                source: wgpu::ShaderSource::Wgsl(Self::make_tricount_shader(sdf_code).into()),
+        // whether chunks cover every part of the key inside the view
+        fn covered(generator: &TerrainMeshGenerator, terrain: &Terrain, ancestors: &HashSet<ChunkKey>, key: &ChunkKey, min: glam::Vec3, max: glam::Vec3) -> bool {
+            if terrain.chunks.contains_key(key) {
+                return true;
             }
-        );
+            ancestors.contains(key) && key.children().all(|child| !generator.intersects(&child, min, max) || covered(generator, terrain, ancestors, &child, min, max))
+        }
 
-        let tricount_pipeline = device.create_compute_pipeline(
-            &wgpu::ComputePipelineDescriptor {
-                label: Some("terrain_tricount_pipeline"),
-                layout: Some(&self.tricount_layout),
-                module: &tricount_shader,
-                entry_point: "main",
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+        let hidden: Vec<(ChunkKey, u32)> = terrain.chunks.keys()
+            .filter(|key| key.lod > 0 && ancestors.contains(key))
+            .map(|key| {
+                let mut hidden_octants = 0;
+                for (octant, child) in key.children().enumerate() {
+                    if covered(self, terrain, &ancestors, &child, min, max) {
+                        hidden_octants |= 1 << octant;
+                    }
+                }
+                (*key, hidden_octants)
+            })
+            .collect();
+        for chunk in terrain.chunks.values_mut() {
+            chunk.hidden_octants = 0;
+        }
+        for (key, hidden_octants) in hidden {
+            terrain.chunks.get_mut(&key).unwrap().hidden_octants = hidden_octants;
+        }
+    }
+
+    // returns whether there are more chunks to generate
+    pub fn generate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, terrain: &mut Terrain, view: &TerrainView, tri_counter: &Arc<Mutex<u64>>) -> bool {
+        let min = view.min();
+        let max = view.max();
+        // the camera is outside the view box, chunks get finer closer to the point of the box nearest to it
+        let lod_center = view.cam_pos.clamp(min, max);
+
+        let chunks_needed = self.select_chunks(lod_center, min, max);
+        let mut chunks_to_generate: Vec<ChunkKey> = chunks_needed.keys().filter(|key| is_missing(terrain, &chunks_needed, key)).copied().collect();
+
+        // Old chunks stay until the chunks replacing them are generated, so the terrain doesn't flicker while changing lod
+        let mut replaced = HashSet::new();
+        for key in &chunks_to_generate {
+            let mut ancestor = *key;
+            for _ in key.lod..=MAX_LOD {
+                replaced.insert(ancestor);
+                ancestor = ChunkKey { loc: ancestor.loc >> 1, lod: ancestor.lod + 1 };
             }
-        );
+        }
+        let missing: HashSet<ChunkKey> = chunks_to_generate.iter().copied().collect();
+        terrain.chunks.retain(|key, chunk| {
+            if chunks_needed.get(key) == Some(&chunk.skirts) || replaced.contains(key) {
+                return true;
+            }
+            let mut ancestor = *key;
+            for _ in key.lod..=MAX_LOD {
+                if missing.contains(&ancestor) {
+                    return true;
+                }
+                ancestor = ChunkKey { loc: ancestor.loc >> 1, lod: ancestor.lod + 1 };
+            }
+            false
+        });
+        self.generation_priority.retain(|key| is_missing(terrain, &chunks_needed, key));
+
+        // We'll generate chunks closer to the camera first
+        let settings = self.settings;
+        let chunk_sorter = |a: &ChunkKey, b: &ChunkKey| b.center(&settings).distance_squared(lod_center).total_cmp(&a.center(&settings).distance_squared(lod_center));
+        chunks_to_generate.sort_by(chunk_sorter); 
+        self.generation_priority.sort_by(chunk_sorter);
+        
+        // Batches grow by a chunk while frames are fast and halve when they're slow
+        let now = Instant::now();
+        if let (Some(last_frame), Some(_)) = (self.last_frame, &self.batch) {
+            if now - last_frame < TARGET_FRAME_TIME {
+                self.batch_size = (self.batch_size + 1).min(self.scratch.slots.len());
+            } else {
+                self.batch_size = (self.batch_size / 2).max(1);
+            }
+        }
+        self.last_frame = Some(now);
 
-        self.tricount_pipeline = tricount_pipeline;
+        device.poll(wgpu::Maintain::Poll);
+        self.cache.poll(device);
+        if let Some(batch) = self.batch.take_if(|batch| batch.mapped.load(Ordering::Acquire)) {
+            self.finish_batch(device, queue, batch, terrain, &chunks_needed, tri_counter);
+        }
 
-        let meshgen_shader = device.create_shader_module(
-            wgpu::ShaderModuleDescriptor {
-                label: Some("terrain_meshgen_shader"),
-                source: wgpu::ShaderSource::Wgsl(Self::make_meshgen_shader(sdf_code).into())
+        // Chunks meshed before come from the cache, the ones still being read from disk wait for it
+        let mesh_hash = self.mesh_hash();
+        let mut loading = HashSet::new();
+        for key in chunks_to_generate.iter().rev() {
+            if !is_missing(terrain, &chunks_needed, key) {
+                continue;
             }
-        );
+            let skirts = chunks_needed[key];
+            match self.cache.get(CacheKey { mesh_hash, chunk: *key, skirts }) {
+                CacheLookup::Found(mesh) => self.add_chunk(terrain, &chunks_needed, *key, skirts, mesh, tri_counter),
+                CacheLookup::Loading => { loading.insert(*key); },
+                CacheLookup::Missing => {}
+            }
+        }
 
-        let meshgen_pipeline = device.create_compute_pipeline(
-            &wgpu::ComputePipelineDescriptor {
-                label: Some("terrain_meshgen"),
-                layout: Some(&self.meshgen_layout),
-                module: &meshgen_shader,
-                entry_point: "main",
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+        if self.batch.is_none() {
+            let mut chunks: Vec<(ChunkKey, u32)> = Vec::new();
+            while chunks.len() < self.batch_size {
+                let key = if let Some(key) = self.generation_priority.pop() {
+                    key
+                } else if let Some(key) = chunks_to_generate.pop() {
+                    key
+                } else {
+                    break;
+                };
+                if is_missing(terrain, &chunks_needed, &key) && !loading.contains(&key) && !chunks.iter().any(|(other, _)| *other == key) {
+                    chunks.push((key, chunks_needed[&key]));
+                }
             }
-        );
+            if !chunks.is_empty() {
+                self.start_batch(device, queue, chunks);
+            }
+        }
 
-        self.meshgen_pipeline = meshgen_pipeline;
+        self.hide_replaced(terrain, min, max);
+
+        return self.batch.is_some() || !loading.is_empty() || chunks_to_generate.iter().any(|key| is_missing(terrain, &chunks_needed, key));
     }
 
-}
+    pub fn update_shaders(&mut self, device: &wgpu::Device, sdf_code: &str) {
+        self.marching_cubes = MesherPipelines::marching_cubes(device, &self.meshgen_layout, sdf_code);
+        self.dual = MesherPipelines::dual(device, &self.meshgen_layout, sdf_code);
+        self.sdf_hash = hash_sdf(sdf_code);
+        self.batch = None;
+    }
+
+}
\ No newline at end of file
@@ -0,0 +1,435 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex, OnceLock}, thread::JoinHandle};
+
+use eframe::wgpu::{self, util::DeviceExt};
+
+use crate::terrain::{ChunkKey, ChunkMesh, TerrainVertex};
+
+// Memory for meshes kept on the gpu after they're no longer shown, counted by the size of their buffers
+const CACHE_MEMORY: u64 = 256 << 20;
+// counted for every entry too, so empty chunks get evicted
+const ENTRY_MEMORY: u64 = 64;
+// Disk space for meshes, the directories of the least recently used mesh hashes are deleted past it
+const DISK_CACHE_SIZE: u64 = 2 << 30;
+// written to a mesh hash's directory whenever it's used, its modification time orders them for eviction
+const LAST_USED_FILE: &str = "last_used";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    // everything the mesh depends on besides the chunk, see TerrainMeshGenerator::mesh_hash
+    pub mesh_hash: u64,
+    pub chunk: ChunkKey,
+    pub skirts: u32
+}
+
+fn hash_dir(dir: &Path, mesh_hash: u64) -> PathBuf {
+    dir.join(format!("{:016x}", mesh_hash))
+}
+
+impl CacheKey {
+
+    fn path(&self, dir: &Path) -> PathBuf {
+        let ChunkKey { loc, lod } = self.chunk;
+        hash_dir(dir, self.mesh_hash).join(format!("{}_{}_{}_{}_{}.chunk", lod, loc.x, loc.y, loc.z, self.skirts))
+    }
+
+}
+
+struct CacheEntry {
+    mesh: Option<Arc<ChunkMesh>>,
+    last_used: u64
+}
+
+impl CacheEntry {
+
+    fn memory(&self) -> u64 {
+        ENTRY_MEMORY + self.mesh.as_ref().map(|mesh| mesh.vertices.size() + mesh.indices.size()).unwrap_or(0)
+    }
+
+}
+
+// A mesh being read back from the gpu to be written to disk
+struct PendingWrite {
+    path: PathBuf,
+    buffer: wgpu::Buffer,
+    verts: u32,
+    mapped: Arc<AtomicBool>
+}
+
+// File work done on the disk thread, in order, so pruning and clearing never overlap
+enum DiskJob {
+    // marks the mesh hash's directory as used and deletes old ones when the disk cache is too big
+    UseHash(PathBuf, u64),
+    Write(PathBuf, Vec<u8>),
+    // with the cache's epoch when asked for
+    Read(CacheKey, u64, PathBuf),
+    Clear(PathBuf)
+}
+
+// A chunk file read on the disk thread
+struct DiskRead {
+    key: CacheKey,
+    epoch: u64,
+    // None if the file is missing or doesn't hold a whole chunk
+    contents: Option<Vec<u8>>
+}
+
+// Where a chunk's mesh is
+pub enum CacheLookup {
+    // None inside if the chunk is empty
+    Found(Option<Arc<ChunkMesh>>),
+    // being read from disk
+    Loading,
+    Missing
+}
+
+// Meshes of chunks generated before, so undoing a graph change or reopening a project doesn't mesh them again.
+// The most recently used are kept in memory. With a directory, every mesh is written to disk too.
+// Files are read and written on a thread of the cache's own, so the frame never waits for the disk.
+// A chunk file holds the vertex and index counts followed by the vertices and indices.
+pub struct MeshCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // entries by last use, the first is evicted when the cache is full
+    lru: BTreeMap<u64, CacheKey>,
+    clock: u64,
+    memory: u64,
+
+    dir: Option<PathBuf>,
+    // chunks known not to be on disk, so they're only looked for once
+    not_on_disk: HashSet<CacheKey>,
+    // chunks being read from disk
+    reading: HashSet<CacheKey>,
+    // counts clears and directory changes, so reads asked for before them are dropped
+    epoch: u64,
+    pending_writes: Vec<PendingWrite>,
+    // the mesh hash last used, the disk cache is pruned when it changes
+    mesh_hash: Option<u64>,
+
+    disk_thread: Option<JoinHandle<()>>,
+    disk_jobs: Option<mpsc::Sender<DiskJob>>,
+    // chunk files read by the disk thread
+    disk_reads: Mutex<mpsc::Receiver<DiskRead>>
+}
+
+impl MeshCache {
+
+    pub fn new() -> Self {
+        let (jobs_tx, jobs_rx) = mpsc::channel();
+        let (reads_tx, reads_rx) = mpsc::channel();
+        // exits once the cache is dropped and the jobs left are done
+        let disk_thread = std::thread::spawn(move || {
+            for job in jobs_rx {
+                match job {
+                    DiskJob::UseHash(dir, mesh_hash) => {
+                        let _ = std::fs::create_dir_all(hash_dir(&dir, mesh_hash));
+                        let _ = std::fs::write(hash_dir(&dir, mesh_hash).join(LAST_USED_FILE), []);
+                        prune_disk_cache(&dir, mesh_hash);
+                    },
+                    DiskJob::Write(path, contents) => write_chunk(&path, &contents),
+                    DiskJob::Read(key, epoch, path) => {
+                        if reads_tx.send(DiskRead { key, epoch, contents: read_chunk(&path) }).is_err() {
+                            return;
+                        }
+                    },
+                    DiskJob::Clear(dir) => {
+                        let _ = std::fs::remove_dir_all(dir);
+                    }
+                }
+            }
+        });
+
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            memory: 0,
+            dir: None,
+            not_on_disk: HashSet::new(),
+            reading: HashSet::new(),
+            epoch: 0,
+            pending_writes: Vec::new(),
+            mesh_hash: None,
+            disk_thread: Some(disk_thread),
+            disk_jobs: Some(jobs_tx),
+            disk_reads: Mutex::new(reads_rx)
+        }
+    }
+
+    fn send(&self, job: DiskJob) {
+        if let Some(disk_jobs) = &self.disk_jobs {
+            let _ = disk_jobs.send(job);
+        }
+    }
+
+    pub fn set_dir(&mut self, dir: Option<PathBuf>) {
+        if self.dir != dir {
+            self.dir = dir;
+            self.not_on_disk.clear();
+            self.reading.clear();
+            self.epoch += 1;
+            self.pending_writes.clear();
+            self.mesh_hash = None;
+        }
+    }
+
+    fn use_hash(&mut self, mesh_hash: u64) {
+        if self.mesh_hash == Some(mesh_hash) {
+            return;
+        }
+        self.mesh_hash = Some(mesh_hash);
+        if let Some(dir) = &self.dir {
+            self.send(DiskJob::UseHash(dir.clone(), mesh_hash));
+        }
+    }
+
+    // Forgets every mesh, on disk too
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.memory = 0;
+        self.not_on_disk.clear();
+        self.reading.clear();
+        self.epoch += 1;
+        self.pending_writes.clear();
+        self.mesh_hash = None;
+        if let Some(dir) = &self.dir {
+            self.send(DiskJob::Clear(dir.clone()));
+        }
+    }
+
+    fn touch(&mut self, key: CacheKey) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.lru.insert(self.clock, key);
+        }
+    }
+
+    fn insert_entry(&mut self, key: CacheKey, mesh: Option<Arc<ChunkMesh>>) {
+        self.clock += 1;
+        let entry = CacheEntry {
+            mesh,
+            last_used: self.clock
+        };
+        self.memory += entry.memory();
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.lru.remove(&old.last_used);
+            self.memory -= old.memory();
+        }
+        self.lru.insert(self.clock, key);
+
+        while self.memory > CACHE_MEMORY {
+            let Some((_, oldest)) = self.lru.pop_first() else { break; };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.memory -= entry.memory();
+            }
+        }
+    }
+
+    // The mesh of a chunk. Chunks not in memory are read from disk in the background.
+    pub fn get(&mut self, key: CacheKey) -> CacheLookup {
+        if let Some(entry) = self.entries.get(&key) {
+            let mesh = entry.mesh.clone();
+            self.touch(key);
+            return CacheLookup::Found(mesh);
+        }
+        if self.reading.contains(&key) {
+            return CacheLookup::Loading;
+        }
+
+        let Some(dir) = &self.dir else { return CacheLookup::Missing; };
+        if self.not_on_disk.contains(&key) {
+            return CacheLookup::Missing;
+        }
+        let path = key.path(dir);
+        self.use_hash(key.mesh_hash);
+        self.send(DiskJob::Read(key, self.epoch, path));
+        self.reading.insert(key);
+        CacheLookup::Loading
+    }
+
+    // Adds newly generated meshes, submitted before this so their buffers can be read back for the disk
+    pub fn insert(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, meshes: Vec<(CacheKey, Option<Arc<ChunkMesh>>)>) {
+        if let Some((key, _)) = meshes.first() {
+            self.use_hash(key.mesh_hash);
+        }
+        if let Some(dir) = self.dir.clone() {
+            let mut encoder = device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor {
+                    label: Some("terrain_mesh_cache_encoder"),
+                }
+            );
+
+            let mut writes = Vec::new();
+            for (key, mesh) in &meshes {
+                self.not_on_disk.remove(key);
+                let path = key.path(&dir);
+                let Some(mesh) = mesh else {
+                    self.send(DiskJob::Write(path, chunk_file(0, 0, &[])));
+                    continue;
+                };
+
+                let vertex_bytes = mesh.vertices.size();
+                let index_bytes = mesh.indices.size();
+                let buffer = device.create_buffer(
+                    &wgpu::BufferDescriptor {
+                        label: Some("terrain_mesh_cache_read_buffer"),
+                        size: vertex_bytes + index_bytes,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    }
+                );
+                encoder.copy_buffer_to_buffer(&mesh.vertices, 0, &buffer, 0, vertex_bytes);
+                encoder.copy_buffer_to_buffer(&mesh.indices, 0, &buffer, vertex_bytes, index_bytes);
+
+                writes.push(PendingWrite {
+                    path,
+                    buffer,
+                    verts: (vertex_bytes / std::mem::size_of::<TerrainVertex>() as u64) as u32,
+                    mapped: Arc::new(AtomicBool::new(false))
+                });
+            }
+
+            queue.submit([encoder.finish()]);
+
+            for write in writes {
+                let callback_mapped = write.mapped.clone();
+                write.buffer.slice(..).map_async(wgpu::MapMode::Read, move |_| callback_mapped.store(true, Ordering::Release));
+                self.pending_writes.push(write);
+            }
+        }
+
+        for (key, mesh) in meshes {
+            self.insert_entry(key, mesh);
+        }
+    }
+
+    // Hands the meshes that finished reading back to the disk thread and uploads the chunks it read
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let pending_writes = std::mem::take(&mut self.pending_writes);
+        for write in pending_writes {
+            if !write.mapped.load(Ordering::Acquire) {
+                self.pending_writes.push(write);
+                continue;
+            }
+            let bytes = write.buffer.slice(..).get_mapped_range();
+            let indices = ((bytes.len() - write.verts as usize * std::mem::size_of::<TerrainVertex>()) / std::mem::size_of::<u32>()) as u32;
+            self.send(DiskJob::Write(write.path.clone(), chunk_file(write.verts, indices, &bytes)));
+        }
+
+        let disk_reads: Vec<_> = self.disk_reads.get_mut().unwrap().try_iter().collect();
+        for DiskRead { key, epoch, contents } in disk_reads {
+            // asked for before the cache was cleared or moved
+            if epoch != self.epoch || !self.reading.remove(&key) {
+                continue;
+            }
+            match contents {
+                Some(contents) => self.insert_entry(key, upload_chunk(device, &contents)),
+                None => { self.not_on_disk.insert(key); }
+            }
+        }
+    }
+
+}
+
+impl Drop for MeshCache {
+
+    // lets the disk thread finish the writes queued
+    fn drop(&mut self) {
+        self.disk_jobs = None;
+        if let Some(thread) = self.disk_thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+}
+
+// Deletes the directories of the least recently used mesh hashes until the cache fits in DISK_CACHE_SIZE, keeping the current one
+fn prune_disk_cache(dir: &Path, current_hash: u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return; };
+    let current_dir = hash_dir(dir, current_hash);
+
+    let mut hash_dirs: Vec<(std::time::SystemTime, u64, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .map(|path| {
+            let last_used = std::fs::metadata(path.join(LAST_USED_FILE)).or_else(|_| std::fs::metadata(&path))
+                .and_then(|meta| meta.modified())
+                .unwrap_or(std::time::UNIX_EPOCH);
+            let size = walkdir::WalkDir::new(&path).into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|meta| meta.is_file())
+                .map(|meta| meta.len())
+                .sum();
+            (last_used, size, path)
+        })
+        .collect();
+    hash_dirs.sort_by_key(|(last_used, _, _)| *last_used);
+
+    let mut total: u64 = hash_dirs.iter().map(|(_, size, _)| size).sum();
+    for (_, size, path) in hash_dirs {
+        if total <= DISK_CACHE_SIZE {
+            break;
+        }
+        if path != current_dir {
+            let _ = std::fs::remove_dir_all(&path);
+            total -= size;
+        }
+    }
+}
+
+fn chunk_file(verts: u32, indices: u32, data: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(8 + data.len());
+    contents.extend_from_slice(bytemuck::cast_slice(&[verts, indices]));
+    contents.extend_from_slice(data);
+    contents
+}
+
+fn write_chunk(path: &Path, contents: &[u8]) {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let _ = std::fs::write(path, contents);
+}
+
+fn chunk_counts(contents: &[u8]) -> Option<(usize, usize)> {
+    let counts: [u32; 2] = bytemuck::pod_read_unaligned(contents.get(..8)?);
+    let vertex_bytes = counts[0] as usize * std::mem::size_of::<TerrainVertex>();
+    let index_bytes = counts[1] as usize * std::mem::size_of::<u32>();
+    (contents.len() == 8 + vertex_bytes + index_bytes).then_some((vertex_bytes, index_bytes))
+}
+
+// None if the file is missing or doesn't hold a whole chunk
+fn read_chunk(path: &Path) -> Option<Vec<u8>> {
+    let contents = std::fs::read(path).ok()?;
+    chunk_counts(&contents)?;
+    Some(contents)
+}
+
+// The mesh of a chunk file read by read_chunk, None if the chunk is empty
+fn upload_chunk(device: &wgpu::Device, contents: &[u8]) -> Option<Arc<ChunkMesh>> {
+    let (vertex_bytes, index_bytes) = chunk_counts(contents)?;
+    if index_bytes == 0 {
+        return None;
+    }
+
+    Some(Arc::new(ChunkMesh {
+        vertices: device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("terrain_chunk_vertices"),
+                contents: &contents[8..8 + vertex_bytes],
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            }
+        ),
+        indices: device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("terrain_chunk_indices"),
+                contents: &contents[8 + vertex_bytes..],
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            }
+        ),
+        render_bind_group: OnceLock::new()
+    }))
+}
@@ -0,0 +1,181 @@
+// Shared by the meshers. Each chunk is meshed twice: first only counting its vertices and indices,
+// then writing them into buffers of exactly that size. The grid values are kept between the two.
+
+@group(0) @binding(0)
+var<storage, read_write> mesh: array<f32>;
+
+struct Counts {
+    verts: atomic<u32>,
+    indices: atomic<u32>
+}
+
+@group(0) @binding(1)
+var<storage, read_write> counts: Counts;
+
+// which vertex belongs to each cell or edge, or NO_VERT
+@group(0) @binding(2)
+var<storage, read_write> vert_map: array<u32>;
+
+@group(0) @binding(3)
+var<storage, read_write> indices: array<u32>;
+
+// sdf values at the grid points from -1 to uniforms.chunk_size along each axis
+@group(0) @binding(5)
+var<storage, read_write> grid_values: array<f32>;
+
+struct Uniforms {
+    begin: vec3<f32>,
+    // world units per voxel
+    scale: f32,
+    // chunk faces that get skirts, bits are -x, +x, -y, +y, -z, +z
+    skirts: u32,
+    // see Mesher::id
+    mesher: u32,
+    // voxels along each axis
+    chunk_size: u32,
+    // room in the mesh and index buffers
+    max_verts: u32,
+    max_indices: u32,
+    // set while counting, nothing is written to the mesh and index buffers
+    count_only: u32
+}
+
+@group(1) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+const NO_VERT = 0xffffffffu;
+
+fn grid_pos(p: vec3<i32>) -> vec3<f32> {
+    return uniforms.scale * vec3<f32>(p) + uniforms.begin;
+}
+
+fn grid_idx(p: vec3<i32>) -> u32 {
+    let g = vec3<u32>(p + 1);
+    let n = uniforms.chunk_size + 2u;
+    return g.x + n * (g.y + n * g.z);
+}
+
+fn grid_value(p: vec3<i32>) -> f32 {
+    return grid_values[grid_idx(p)];
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn values_main(
+    @builtin(global_invocation_id) id : vec3<u32>
+) {
+    if any(id >= vec3(uniforms.chunk_size + 2u)) {
+        return;
+    }
+    let p = vec3<i32>(id) - 1;
+    grid_values[grid_idx(p)] = sdf(grid_pos(p)).terrain.sdf;
+}
+
+// central differences of the sdf, points away from the terrain
+fn sdf_gradient(pos: vec3<f32>) -> vec3<f32> {
+    let e = uniforms.scale * 0.25;
+    let grad = vec3(
+        sdf(pos + vec3(e, 0.0, 0.0)).terrain.sdf - sdf(pos - vec3(e, 0.0, 0.0)).terrain.sdf,
+        sdf(pos + vec3(0.0, e, 0.0)).terrain.sdf - sdf(pos - vec3(0.0, e, 0.0)).terrain.sdf,
+        sdf(pos + vec3(0.0, 0.0, e)).terrain.sdf - sdf(pos - vec3(0.0, 0.0, e)).terrain.sdf
+    );
+    if dot(grad, grad) < 1e-12 {
+        return vec3(0.0, 1.0, 0.0);
+    }
+    return normalize(grad);
+}
+
+// pos, norm, 4 material slots, 4 material weights, 4 biome ids and 4 biome weights
+const VERTEX_FLOATS = 22u;
+
+fn set_vert(idx: u32, pos: vec3<f32>, norm: vec3<f32>, material_: ReducedMaterial, biomes_: ReducedMaterial) {
+    if uniforms.count_only != 0u {
+        return;
+    }
+    var material = material_;
+    var biomes = biomes_;
+    let begin = idx * VERTEX_FLOATS;
+    mesh[begin + 0] = pos.x;
+    mesh[begin + 1] = pos.y;
+    mesh[begin + 2] = pos.z;
+    mesh[begin + 3] = norm.x;
+    mesh[begin + 4] = norm.y;
+    mesh[begin + 5] = norm.z;
+    for(var i = 0u; i < 4u; i++) {
+        mesh[begin + 6 + i] = 0.0;
+        mesh[begin + 10 + i] = 0.0;
+        mesh[begin + 14 + i] = 0.0;
+        mesh[begin + 18 + i] = 0.0;
+    }
+    for(var i = 0u; i < MATERIAL_SLOTS; i++) {
+        mesh[begin + 6 + i] = bitcast<f32>(material.tex[i]);
+        mesh[begin + 10 + i] = material.w[i];
+        mesh[begin + 14 + i] = bitcast<f32>(biomes.tex[i]);
+        mesh[begin + 18 + i] = biomes.w[i];
+    }
+}
+
+// copies a vertex moved by offset, for skirts
+fn copy_vert(dst: u32, src: u32, offset: vec3<f32>) {
+    if uniforms.count_only != 0u {
+        return;
+    }
+    for(var i = 0u; i < VERTEX_FLOATS; i++) {
+        mesh[dst * VERTEX_FLOATS + i] = mesh[src * VERTEX_FLOATS + i];
+    }
+    for(var i = 0u; i < 3u; i++) {
+        mesh[dst * VERTEX_FLOATS + i] += offset[i];
+    }
+}
+
+fn vert_pos(idx: u32) -> vec3<f32> {
+    let begin = idx * VERTEX_FLOATS;
+    return vec3(mesh[begin], mesh[begin + 1], mesh[begin + 2]);
+}
+
+fn vert_norm(idx: u32) -> vec3<f32> {
+    let begin = idx * VERTEX_FLOATS;
+    return vec3(mesh[begin + 3], mesh[begin + 4], mesh[begin + 5]);
+}
+
+fn add_tri(begin: u32, a: u32, b: u32, c: u32) {
+    if uniforms.count_only != 0u {
+        return;
+    }
+    indices[begin + 0] = a;
+    indices[begin + 1] = b;
+    indices[begin + 2] = c;
+}
+
+// reserves n indices, or returns NO_VERT if they don't fit. Reserved indices past the end are zeroed,
+// so a chunk that overflows loses triangles instead of getting garbage ones.
+fn alloc_indices(n: u32) -> u32 {
+    let begin = atomicAdd(&counts.indices, n);
+    if begin + n <= uniforms.max_indices {
+        return begin;
+    }
+    for(var i = begin; i < uniforms.max_indices; i++) {
+        indices[i] = 0u;
+    }
+    return NO_VERT;
+}
+
+// a skirt below the mesh edge from vertex a to vertex b, folded down into the terrain
+// to cover the crack between this chunk's mesh and a neighbor of another lod
+fn add_skirt(a: u32, b: u32) {
+    let skirt_begin = alloc_indices(6u);
+    if skirt_begin == NO_VERT {
+        return;
+    }
+    let skirt_verts = atomicAdd(&counts.verts, 2u);
+    // out of vertices, the reserved indices still have to be written
+    if skirt_verts + 2u > uniforms.max_verts {
+        add_tri(skirt_begin, 0u, 0u, 0u);
+        add_tri(skirt_begin + 3u, 0u, 0u, 0u);
+        return;
+    }
+    let depth = uniforms.scale * 2.0;
+    copy_vert(skirt_verts, a, -vert_norm(a) * depth);
+    copy_vert(skirt_verts + 1u, b, -vert_norm(b) * depth);
+    add_tri(skirt_begin, b, a, skirt_verts);
+    add_tri(skirt_begin + 3u, b, skirt_verts, skirt_verts + 1u);
+}
@@ -0,0 +1,204 @@
+// Surface nets and dual contouring. Every cell the surface passes through gets one vertex,
+// and every grid edge crossing the surface becomes a quad joining the vertices of the four cells around it.
+// Cells go from -1 to uniforms.chunk_size - 1 along each axis so quads on the chunk's min faces can be closed.
+// vert_map holds the vertex of each cell.
+
+// pulls the dual contouring vertex towards the mass point so flat and degenerate cells stay stable
+const QEF_BIAS = 0.05;
+// bisection steps locating each edge crossing for dual contouring, linear interpolation misplaces crossings near sharp features
+const CROSSING_STEPS = 6u;
+
+// corner i of a cell is offset by bit 0 of i along x, bit 1 along y and bit 2 along z
+fn corner(i: u32) -> vec3<i32> {
+    return vec3(i32(i & 1u), i32((i >> 1u) & 1u), i32((i >> 2u) & 1u));
+}
+
+// corner pairs of the 12 cell edges
+var<private> EDGES: array<vec2<u32>, 12> = array(
+    vec2(0u, 1u), vec2(2u, 3u), vec2(4u, 5u), vec2(6u, 7u),
+    vec2(0u, 2u), vec2(1u, 3u), vec2(4u, 6u), vec2(5u, 7u),
+    vec2(0u, 4u), vec2(1u, 5u), vec2(2u, 6u), vec2(3u, 7u)
+);
+
+fn cell_idx(cell: vec3<i32>) -> u32 {
+    let c = vec3<u32>(cell + 1);
+    let n = uniforms.chunk_size + 1u;
+    return c.x + n * (c.y + n * c.z);
+}
+
+fn find_crossing(a_: vec3<f32>, a_val_: f32, b_: vec3<f32>, b_val_: f32, steps: u32) -> vec3<f32> {
+    var a = a_;
+    var a_val = a_val_;
+    var b = b_;
+    var b_val = b_val_;
+    for(var i = 0u; i < steps; i++) {
+        let mid = (a + b) * 0.5;
+        let mid_val = sdf(mid).terrain.sdf;
+        if (mid_val <= 0.0) == (a_val <= 0.0) {
+            a = mid;
+            a_val = mid_val;
+        } else {
+            b = mid;
+            b_val = mid_val;
+        }
+    }
+    let t = clamp(a_val / (a_val - b_val), 0.0, 1.0);
+    return mix(a, b, t);
+}
+
+// minimizes the squared distance to the tangent planes of the edge crossings, relative to the mass point
+fn solve_qef(ata_: mat3x3<f32>, atb: vec3<f32>) -> vec3<f32> {
+    let a = ata_ + mat3x3(QEF_BIAS, 0.0, 0.0, 0.0, QEF_BIAS, 0.0, 0.0, 0.0, QEF_BIAS);
+    let det = determinant(a);
+    if abs(det) < 1e-8 {
+        return vec3(0.0);
+    }
+    let inv = transpose(mat3x3(cross(a[1], a[2]), cross(a[2], a[0]), cross(a[0], a[1]))) * (1.0 / det);
+    return inv * atb;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn vertices_main(
+    @builtin(global_invocation_id) id : vec3<u32>
+) {
+    if any(id >= vec3(uniforms.chunk_size + 1u)) {
+        return;
+    }
+    let cell = vec3<i32>(id) - 1;
+
+    var pos: array<vec3<f32>, 8>;
+    var vals: array<f32, 8>;
+    var inside = 0u;
+    for(var i = 0u; i < 8u; i++) {
+        pos[i] = grid_pos(cell + corner(i));
+        vals[i] = grid_value(cell + corner(i));
+        inside += u32(vals[i] <= 0.0);
+    }
+
+    if inside == 0u || inside == 8u {
+        vert_map[cell_idx(cell)] = NO_VERT;
+        return;
+    }
+
+    let idx = atomicAdd(&counts.verts, 1u);
+    if idx >= uniforms.max_verts {
+        vert_map[cell_idx(cell)] = NO_VERT;
+        return;
+    }
+    vert_map[cell_idx(cell)] = idx;
+    if uniforms.count_only != 0u {
+        return;
+    }
+
+    // edge crossings
+    let steps = select(0u, CROSSING_STEPS, uniforms.mesher == 2u);
+    var crossings: array<vec3<f32>, 12>;
+    var n_crossings = 0u;
+    var mass = vec3(0.0);
+    for(var i = 0u; i < 12u; i++) {
+        let a = EDGES[i].x;
+        let b = EDGES[i].y;
+        if (vals[a] <= 0.0) == (vals[b] <= 0.0) {
+            continue;
+        }
+        crossings[n_crossings] = find_crossing(pos[a], vals[a], pos[b], vals[b], steps);
+        mass += crossings[n_crossings];
+        n_crossings++;
+    }
+    mass /= f32(n_crossings);
+
+    var vert = mass;
+    if uniforms.mesher == 2u {
+        // positions are relative to the mass point and in voxel units to keep the qef well conditioned
+        var ata = mat3x3(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        var atb = vec3(0.0);
+        for(var i = 0u; i < n_crossings; i++) {
+            let n = sdf_gradient(crossings[i]);
+            let d = dot(n, (crossings[i] - mass) / uniforms.scale);
+            ata += mat3x3(n * n.x, n * n.y, n * n.z);
+            atb += n * d;
+        }
+        let cell_min = pos[0];
+        let cell_max = pos[7];
+        vert = clamp(mass + solve_qef(ata, atb) * uniforms.scale, cell_min, cell_max);
+    }
+
+    let out = sdf(vert);
+    let norm = sdf_gradient(vert);
+    set_vert(idx, vert, norm, reduce_material(out, vert, norm), reduce_biomes(out, vert, norm));
+}
+
+// the faces of the chunk's mesh border the cell lies on, same bits as uniforms.skirts
+fn cell_skirt_faces(cell: vec3<i32>) -> u32 {
+    let last = i32(uniforms.chunk_size) - 1;
+    var faces = 0u;
+    if cell.x == -1 { faces |= 1u; }
+    if cell.x == last { faces |= 2u; }
+    if cell.y == -1 { faces |= 4u; }
+    if cell.y == last { faces |= 8u; }
+    if cell.z == -1 { faces |= 16u; }
+    if cell.z == last { faces |= 32u; }
+    return faces & uniforms.skirts;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn quads_main(
+    @builtin(global_invocation_id) id : vec3<u32>
+) {
+    if any(id >= vec3(uniforms.chunk_size)) {
+        return;
+    }
+    let p = vec3<i32>(id);
+    let p_air = grid_value(p) > 0.0;
+
+    for(var axis = 0; axis < 3; axis++) {
+        var dir = vec3(0);
+        dir[axis] = 1;
+        // u and v span the plane of the quad, with cross(u, v) = dir
+        var u = vec3(0);
+        u[(axis + 1) % 3] = 1;
+        var v = vec3(0);
+        v[(axis + 2) % 3] = 1;
+
+        let q_air = grid_value(p + dir) > 0.0;
+        if p_air == q_air {
+            continue;
+        }
+
+        var cells = array(p - u - v, p - v, p, p - u);
+        // counter clockwise seen from the air
+        if p_air {
+            cells = array(p - u - v, p - u, p, p - v);
+        }
+        var quad: array<u32, 4>;
+        var valid = true;
+        for(var i = 0; i < 4; i++) {
+            quad[i] = vert_map[cell_idx(cells[i])];
+            valid = valid && quad[i] != NO_VERT;
+        }
+        if !valid {
+            continue;
+        }
+
+        let begin = alloc_indices(6u);
+        if begin == NO_VERT {
+            continue;
+        }
+        // split along the shorter diagonal
+        if distance(vert_pos(quad[0]), vert_pos(quad[2])) <= distance(vert_pos(quad[1]), vert_pos(quad[3])) {
+            add_tri(begin, quad[0], quad[1], quad[2]);
+            add_tri(begin + 3u, quad[0], quad[2], quad[3]);
+        } else {
+            add_tri(begin, quad[0], quad[1], quad[3]);
+            add_tri(begin + 3u, quad[1], quad[2], quad[3]);
+        }
+
+        // quad sides on the border of the mesh next to a chunk of another lod get a skirt
+        for(var j = 0; j < 4; j++) {
+            let k = (j + 1) % 4;
+            if (cell_skirt_faces(cells[j]) & cell_skirt_faces(cells[k])) != 0u {
+                add_skirt(quad[j], quad[k]);
+            }
+        }
+    }
+}
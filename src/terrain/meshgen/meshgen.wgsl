@@ -1,157 +1,154 @@
+// Marching cubes. Every grid edge crossing the surface gets one vertex, shared by the triangles of the cells around it.
+// vert_map holds the vertex of each edge, see edge_idx.
 
-@group(0) @binding(0)
-var<storage, read_write> mesh: array<f32>;
+@group(0) @binding(4)
+var<storage> tri_table: array<i32, 4096>;
 
-struct Params {
-    curr_idx: atomic<u32>
-}
+// The cell faces each marching cubes edge lies on, same bits as uniforms.skirts
+var<private> EDGE_FACES: array<u32, 12> = array(20u, 18u, 24u, 17u, 36u, 34u, 40u, 33u, 5u, 6u, 10u, 9u);
 
-@group(0) @binding(1)
-var<storage, read_write> params: Params; 
+// The corner each marching cubes edge starts at, as bits x, y, z, and the axis it runs along
+var<private> EDGE_ORIGINS: array<u32, 12> = array(0u, 1u, 2u, 0u, 4u, 5u, 6u, 4u, 0u, 1u, 3u, 2u);
+var<private> EDGE_AXES: array<u32, 12> = array(0u, 1u, 0u, 1u, 0u, 1u, 0u, 1u, 2u, 2u, 2u, 2u);
 
-@group(0) @binding(2)
-var<storage> tri_table: array<i32, 4096>;
+fn edge_idx(p: vec3<u32>, axis: u32) -> u32 {
+    let n = uniforms.chunk_size + 1u;
+    return p.x + n * (p.y + n * (p.z + n * axis));
+}
 
-struct Uniforms {
-    begin: vec3<f32>
+fn cell_skirt_faces(id: vec3<u32>) -> u32 {
+    let last = uniforms.chunk_size - 1u;
+    var faces = 0u;
+    if id.x == 0u { faces |= 1u; }
+    if id.x == last { faces |= 2u; }
+    if id.y == 0u { faces |= 4u; }
+    if id.y == last { faces |= 8u; }
+    if id.z == 0u { faces |= 16u; }
+    if id.z == last { faces |= 32u; }
+    return faces & uniforms.skirts;
 }
 
-@group(1) @binding(0)
-var<uniform> uniforms: Uniforms;
+// Where the sdf crosses zero between two grid points, from 0 at a to 1 at b
+fn crossing(a_val: f32, b_val: f32) -> f32 {
+    if a_val == b_val {
+        return 0.0;
+    }
+    return clamp(-a_val / (b_val - a_val), 0.0, 1.0);
+}
 
 fn lerp_verts(a: vec3<f32>, a_val: f32, b: vec3<f32>, b_val: f32) -> vec3<f32> {
-    if abs(a_val) < 0.01 {
-        return a;
-    }
-    if abs(b_val) < 0.01 {
-        return b;
-    }
-    let m = -a_val / (b_val - a_val);
-    return a + m * (b - a);
+    return a + crossing(a_val, b_val) * (b - a);
 }
 
-fn lerp_terrain_biome_weights(a_: array<f32, N_BIOMES>, a_val: f32, b_: array<f32, N_BIOMES>, b_val: f32) -> array<f32, N_BIOMES> {
+fn lerp_terrain_output(a_: TerrainOutput, a_val: f32, b_: TerrainOutput, b_val: f32) -> TerrainOutput {
     var a = a_;
     var b = b_;
-    var w: array<f32, N_BIOMES>;
-    if abs(a_val) < 0.01 {
-        return a;
+    var out = a;
+    let m = crossing(a_val, b_val);
+    for(var i = 0u; i < N_BIOMES; i++) {
+        out.biome_w[i] = a.biome_w[i] + m * (b.biome_w[i] - a.biome_w[i]);
     }
-    if abs(b_val) < 0.01 {
-        return b;
+    for(var i = 0u; i < MATERIAL_LAYERS; i++) {
+        out.layer_w[i] = a.layer_w[i] + m * (b.layer_w[i] - a.layer_w[i]);
     }
-    let m = -a_val / (b_val - a_val);
-    for(var i = 0u; i < N_BIOMES; i++) {
-        w[i] = a[i] + m * (b[i] - a[i]);
+    for(var i = 0u; i < BIOME_RULE_INPUTS; i++) {
+        out.rule_values[i] = a.rule_values[i] + m * (b.rule_values[i] - a.rule_values[i]);
     }
-    return w;
+    return out;
 }
 
-fn set_vert(idx: u32, pos: vec3<f32>, norm: vec3<f32>, material: ReducedMaterial) {
-    mesh[idx * 9 + 0] = pos.x;
-    mesh[idx * 9 + 1] = pos.y;
-    mesh[idx * 9 + 2] = pos.z;
-    mesh[idx * 9 + 3] = norm.x;
-    mesh[idx * 9 + 4] = norm.y;
-    mesh[idx * 9 + 5] = norm.z;
-    mesh[idx * 9 + 6] = bitcast<f32>(material.tex1);
-    mesh[idx * 9 + 7] = bitcast<f32>(material.tex2);
-    mesh[idx * 9 + 8] = material.w;
+@compute @workgroup_size(4, 4, 4)
+fn vertices_main(
+    @builtin(global_invocation_id) id : vec3<u32>
+) {
+    if any(id >= vec3(uniforms.chunk_size + 1u)) {
+        return;
+    }
+    let p = vec3<i32>(id);
+    let a_val = grid_value(p);
+
+    for(var axis = 0u; axis < 3u; axis++) {
+        var dir = vec3(0);
+        dir[axis] = 1;
+        if id[axis] == uniforms.chunk_size {
+            continue;
+        }
+        let b_val = grid_value(p + dir);
+        if (a_val > 0.0) == (b_val > 0.0) {
+            vert_map[edge_idx(id, axis)] = NO_VERT;
+            continue;
+        }
+
+        let idx = atomicAdd(&counts.verts, 1u);
+        if idx >= uniforms.max_verts {
+            vert_map[edge_idx(id, axis)] = NO_VERT;
+            continue;
+        }
+        vert_map[edge_idx(id, axis)] = idx;
+        if uniforms.count_only != 0u {
+            continue;
+        }
+
+        let a = grid_pos(p);
+        let b = grid_pos(p + dir);
+        let pos = lerp_verts(a, a_val, b, b_val);
+        let out = lerp_terrain_output(sdf(a), a_val, sdf(b), b_val);
+        let norm = sdf_gradient(pos);
+        set_vert(idx, pos, norm, reduce_material(out, pos, norm), reduce_biomes(out, pos, norm));
+    }
 }
 
-@compute @workgroup_size(16, 16, 1)
-fn main(
+@compute @workgroup_size(4, 4, 4)
+fn triangles_main(
     @builtin(global_invocation_id) id : vec3<u32>
 ) {
-
-    let scl = 1.0;
-
-    let v000 = scl * vec3(f32(id.x + 0), f32(id.y + 0), f32(id.z + 0)) + uniforms.begin; 
-    let v100 = scl * vec3(f32(id.x + 1), f32(id.y + 0), f32(id.z + 0)) + uniforms.begin; 
-    let v010 = scl * vec3(f32(id.x + 0), f32(id.y + 1), f32(id.z + 0)) + uniforms.begin; 
-    let v001 = scl * vec3(f32(id.x + 0), f32(id.y + 0), f32(id.z + 1)) + uniforms.begin; 
-    let v110 = scl * vec3(f32(id.x + 1), f32(id.y + 1), f32(id.z + 0)) + uniforms.begin; 
-    let v101 = scl * vec3(f32(id.x + 1), f32(id.y + 0), f32(id.z + 1)) + uniforms.begin; 
-    let v011 = scl * vec3(f32(id.x + 0), f32(id.y + 1), f32(id.z + 1)) + uniforms.begin; 
-    let v111 = scl * vec3(f32(id.x + 1), f32(id.y + 1), f32(id.z + 1)) + uniforms.begin; 
-
-    let t000 = sdf(v000);
-    let t100 = sdf(v100);
-    let t010 = sdf(v010);
-    let t001 = sdf(v001);
-    let t110 = sdf(v110);
-    let t101 = sdf(v101);
-    let t011 = sdf(v011);
-    let t111 = sdf(v111);
-
-    let s000 = t000.terrain.sdf;
-    let s100 = t100.terrain.sdf;
-    let s010 = t010.terrain.sdf;
-    let s001 = t001.terrain.sdf;
-    let s110 = t110.terrain.sdf;
-    let s101 = t101.terrain.sdf;
-    let s011 = t011.terrain.sdf;
-    let s111 = t111.terrain.sdf;
+    if any(id >= vec3(uniforms.chunk_size)) {
+        return;
+    }
+    let p = vec3<i32>(id);
 
     let idx = 
-        (u32(s000 > 0.0) << 0) |
-        (u32(s100 > 0.0) << 1) |
-        (u32(s010 > 0.0) << 3) |
-        (u32(s110 > 0.0) << 2) |
-        (u32(s001 > 0.0) << 4) |
-        (u32(s101 > 0.0) << 5) |
-        (u32(s011 > 0.0) << 7) |
-        (u32(s111 > 0.0) << 6);
-
-    let e0  = lerp_verts(v000, s000, v100, s100);    
-    let e1  = lerp_verts(v100, s100, v110, s110);    
-    let e2  = lerp_verts(v010, s010, v110, s110);    
-    let e3  = lerp_verts(v000, s000, v010, s010);    
-    let e4  = lerp_verts(v001, s001, v101, s101);    
-    let e5  = lerp_verts(v101, s101, v111, s111);    
-    let e6  = lerp_verts(v011, s011, v111, s111);    
-    let e7  = lerp_verts(v001, s001, v011, s011);    
-    let e8  = lerp_verts(v000, s000, v001, s001);    
-    let e9  = lerp_verts(v100, s100, v101, s101);    
-    let e10 = lerp_verts(v110, s110, v111, s111);    
-    let e11 = lerp_verts(v010, s010, v011, s011);
-
-    let b0  = lerp_terrain_biome_weights(t000.biome_w, s000, t100.biome_w, s100);    
-    let b1  = lerp_terrain_biome_weights(t100.biome_w, s100, t110.biome_w, s110);    
-    let b2  = lerp_terrain_biome_weights(t010.biome_w, s010, t110.biome_w, s110);    
-    let b3  = lerp_terrain_biome_weights(t000.biome_w, s000, t010.biome_w, s010);    
-    let b4  = lerp_terrain_biome_weights(t001.biome_w, s001, t101.biome_w, s101);    
-    let b5  = lerp_terrain_biome_weights(t101.biome_w, s101, t111.biome_w, s111);    
-    let b6  = lerp_terrain_biome_weights(t011.biome_w, s011, t111.biome_w, s111);    
-    let b7  = lerp_terrain_biome_weights(t001.biome_w, s001, t011.biome_w, s011);    
-    let b8  = lerp_terrain_biome_weights(t000.biome_w, s000, t001.biome_w, s001);    
-    let b9  = lerp_terrain_biome_weights(t100.biome_w, s100, t101.biome_w, s101);    
-    let b10 = lerp_terrain_biome_weights(t110.biome_w, s110, t111.biome_w, s111);    
-    let b11 = lerp_terrain_biome_weights(t010.biome_w, s010, t011.biome_w, s011);
-
-    var edges =    array(e0, e1, e2, e3, e4, e5, e6, e7, e8, e9, e10, e11);
-    var biome_ws = array(b0, b1, b2, b3, b4, b5, b6, b7, b8, b9, b10, b11);
+        (u32(grid_value(p + vec3(0, 0, 0)) > 0.0) << 0) |
+        (u32(grid_value(p + vec3(1, 0, 0)) > 0.0) << 1) |
+        (u32(grid_value(p + vec3(0, 1, 0)) > 0.0) << 3) |
+        (u32(grid_value(p + vec3(1, 1, 0)) > 0.0) << 2) |
+        (u32(grid_value(p + vec3(0, 0, 1)) > 0.0) << 4) |
+        (u32(grid_value(p + vec3(1, 0, 1)) > 0.0) << 5) |
+        (u32(grid_value(p + vec3(0, 1, 1)) > 0.0) << 7) |
+        (u32(grid_value(p + vec3(1, 1, 1)) > 0.0) << 6);
+
+    var verts: array<u32, 12>;
+    for (var i = 0u; i < 12u; i++) {
+        let origin = EDGE_ORIGINS[i];
+        verts[i] = vert_map[edge_idx(id + vec3(origin & 1u, (origin >> 1u) & 1u, (origin >> 2u) & 1u), EDGE_AXES[i])];
+    }
+
+    let skirt_faces = cell_skirt_faces(id);
 
     for (var i = 0u; i < 15u; i += 3u) {
         if tri_table[idx * 16u + i] < 0 {
             break;
         }
-        let p1 = edges[tri_table[idx * 16 + i + 0]];
-        let p2 = edges[tri_table[idx * 16 + i + 1]];
-        let p3 = edges[tri_table[idx * 16 + i + 2]];
-
-        let biome_w1 = biome_ws[tri_table[idx * 16 + i + 0]];
-        let biome_w2 = biome_ws[tri_table[idx * 16 + i + 1]];
-        let biome_w3 = biome_ws[tri_table[idx * 16 + i + 2]];
-
-        let norm = normalize(cross(p1 - p2, p1 - p3));
-
-        let begin = atomicAdd(&params.curr_idx, 3u);
-
-        set_vert(begin + 0, p1, norm, reduce_material(biome_w1));
-        set_vert(begin + 1, p2, norm, reduce_material(biome_w2));
-        set_vert(begin + 2, p3, norm, reduce_material(biome_w3));
+        var edges = array(tri_table[idx * 16u + i], tri_table[idx * 16u + i + 1u], tri_table[idx * 16u + i + 2u]);
+        var tri = array(verts[edges[0]], verts[edges[1]], verts[edges[2]]);
+        if tri[0] == NO_VERT || tri[1] == NO_VERT || tri[2] == NO_VERT {
+            continue;
+        }
 
+        let begin = alloc_indices(3u);
+        if begin == NO_VERT {
+            continue;
+        }
+        add_tri(begin, tri[0], tri[1], tri[2]);
+
+        // triangle edges on a chunk face next to a chunk of another lod get a skirt
+        if skirt_faces != 0u {
+            for (var j = 0u; j < 3u; j++) {
+                let k = (j + 1u) % 3u;
+                if (EDGE_FACES[edges[j]] & EDGE_FACES[edges[k]] & skirt_faces) != 0u {
+                    add_skirt(tri[j], tri[k]);
+                }
+            }
+        }
     }
-
 }
@@ -2,14 +2,62 @@
 use eframe::wgpu::{self, util::DeviceExt};
 use glam::{Mat4, Vec3};
 
-use super::{texture_atlas::TextureAtlas, Terrain, TerrainVertex};
+use super::{texture_atlas::TextureAtlas, Terrain, TerrainView};
+
+// Biomes past this use a fallback color when shading by biome
+pub const MAX_SHADED_BIOMES: usize = 64;
 
 #[repr(C)]
-#[derive(Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct TerrainRendererUniforms {
     trans: [[f32; 4]; 4],
     bounds_min: glam::Vec4,
     bounds_max: glam::Vec4,
+    // shading mode and the biome shown by the weight heatmap
+    shading: [u32; 4],
+    biome_colors: [[f32; 4]; MAX_SHADED_BIOMES]
+}
+
+// Per chunk data, the shader discards the octants around the center that finer chunks already cover
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkInstance {
+    center: Vec3,
+    hidden_octants: u32
+}
+
+impl ChunkInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![6 => Float32x3, 7 => Uint32];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TerrainShading {
+    Textured,
+    // debug colors of the biomes, blended by weight
+    BiomeColors,
+    // heatmap of a single biome's weight
+    BiomeWeight(usize)
+}
+
+impl TerrainShading {
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TerrainShading::Textured => "Textured",
+            TerrainShading::BiomeColors => "Biome Colors",
+            TerrainShading::BiomeWeight(_) => "Biome Weight",
+        }
+    }
+
 }
 
 pub struct TerrainRenderer {
@@ -17,7 +65,11 @@ pub struct TerrainRenderer {
     uniform_buffer: wgpu::Buffer,
     renderer_bind_group: wgpu::BindGroup,
     atlas_bind_group_layout: wgpu::BindGroupLayout,
-    atlas_bind_group: Option<wgpu::BindGroup>
+    atlas_bind_group: Option<wgpu::BindGroup>,
+    mesh_bind_group_layout: wgpu::BindGroupLayout,
+    instance_buffer: Option<wgpu::Buffer>,
+    shading: [u32; 4],
+    biome_colors: [[f32; 4]; MAX_SHADED_BIOMES]
 }
 
 impl TerrainRenderer {
@@ -26,7 +78,7 @@ impl TerrainRenderer {
         let uniform_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("terrain_renderer_uniforms"),
-                contents: bytemuck::cast_slice(&[TerrainRendererUniforms::default()]),
+                contents: bytemuck::cast_slice(&[<TerrainRendererUniforms as bytemuck::Zeroable>::zeroed()]),
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             }
         );
@@ -86,10 +138,27 @@ impl TerrainRenderer {
             }
         );
 
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None,
+        };
+        let mesh_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("terrain_renderer_mesh_bind_group_layout"),
+                entries: &[storage_entry(0), storage_entry(1)]
+            }
+        );
+
         let render_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: Some("terrain_renderer_pipeline_layout"),
-                bind_group_layouts: &[&renderer_bind_group_layout, &atlas_bind_group_layout],
+                bind_group_layouts: &[&renderer_bind_group_layout, &atlas_bind_group_layout, &mesh_bind_group_layout],
                 push_constant_ranges: &[],
             }
         );
@@ -104,7 +173,7 @@ impl TerrainRenderer {
                     module: &shader,
                     entry_point: "vs_main",
                     compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    buffers: &[TerrainVertex::desc()],
+                    buffers: &[ChunkInstance::desc()],
                 },
                 fragment: Some(wgpu::FragmentState {
                     module: &shader,
@@ -148,16 +217,35 @@ impl TerrainRenderer {
             uniform_buffer,
             renderer_bind_group,
             atlas_bind_group_layout,
-            atlas_bind_group: None
+            atlas_bind_group: None,
+            mesh_bind_group_layout,
+            instance_buffer: None,
+            shading: [0; 4],
+            biome_colors: [[0.5, 0.5, 0.5, 1.0]; MAX_SHADED_BIOMES]
         }
     }
 
-    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, cam: Mat4, bounds_min: Vec3, bounds_max: Vec3, atlas: &TextureAtlas) {
+    pub fn set_shading(&mut self, shading: TerrainShading, biome_colors: &[[f32; 3]]) {
+        self.shading = match shading {
+            TerrainShading::Textured => [0, 0, 0, 0],
+            TerrainShading::BiomeColors => [1, 0, 0, 0],
+            TerrainShading::BiomeWeight(biome) => [2, biome as u32, 0, 0],
+        };
+        for (color, biome_color) in self.biome_colors.iter_mut().zip(biome_colors) {
+            *color = [biome_color[0], biome_color[1], biome_color[2], 1.0];
+        }
+    }
+
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, terrain: &Terrain, cam: Mat4, view: &TerrainView, atlas: &TextureAtlas) {
+        let bounds_min = view.min();
+        let bounds_max = view.max();
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[
             TerrainRendererUniforms {
                 trans: cam.to_cols_array_2d(),
                 bounds_min: glam::vec4(bounds_min.x, bounds_min.y, bounds_min.z, 0.0),
                 bounds_max: glam::vec4(bounds_max.x, bounds_max.y, bounds_max.z, 0.0),
+                shading: self.shading,
+                biome_colors: self.biome_colors
             }
         ]));
 
@@ -177,19 +265,54 @@ impl TerrainRenderer {
                 ] 
             }
         ));
+
+        // in the order render draws the chunks
+        let instances: Vec<ChunkInstance> = terrain.chunks.values()
+            .filter_map(|chunk| Some((chunk, chunk.mesh.as_ref()?)))
+            .map(|(chunk, mesh)| {
+                mesh.render_bind_group.get_or_init(|| device.create_bind_group(
+                    &wgpu::BindGroupDescriptor {
+                        label: Some("terrain_mesh_bind_group"),
+                        layout: &self.mesh_bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: mesh.vertices.as_entire_binding()
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: mesh.indices.as_entire_binding()
+                            }
+                        ]
+                    }
+                ));
+                ChunkInstance { center: chunk.center, hidden_octants: chunk.hidden_octants }
+            })
+            .collect();
+        self.instance_buffer = (!instances.is_empty()).then(|| device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("terrain_chunk_instances"),
+                contents: bytemuck::cast_slice(&instances),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        ));
     }
 
     pub fn render<'a>(&'a self, terrain: &'a Terrain, pass: &mut wgpu::RenderPass<'a>) {
-        for (_loc, chunk) in &terrain.chunks {
-            if let Some(mesh) = &chunk.mesh {
-                pass.set_pipeline(&self.render_pipeline);
-                pass.set_vertex_buffer(0, mesh.slice(..));
-                pass.set_bind_group(0, &self.renderer_bind_group, &[]);
-                if let Some(atlas_bind_group) = &self.atlas_bind_group {
-                    pass.set_bind_group(1, atlas_bind_group, &[]);
-                }
-                pass.draw(0..(3 * chunk.tris), 0..1);
+        let Some(instance_buffer) = &self.instance_buffer else { return; };
+        let meshes = terrain.chunks.values().filter_map(|chunk| Some((chunk, chunk.mesh.as_ref()?)));
+        for (instance, (chunk, mesh)) in meshes.enumerate() {
+            let instance = instance as u32;
+            let Some(mesh_bind_group) = mesh.render_bind_group.get() else { continue; };
+            pass.set_pipeline(&self.render_pipeline);
+            pass.set_vertex_buffer(0, instance_buffer.slice(..));
+            pass.set_bind_group(0, &self.renderer_bind_group, &[]);
+            if let Some(atlas_bind_group) = &self.atlas_bind_group {
+                pass.set_bind_group(1, atlas_bind_group, &[]);
             }
+            pass.set_bind_group(2, mesh_bind_group, &[]);
+            // the vertex shader reads the vertices through the index buffer itself
+            pass.draw(0..(3 * chunk.tris), instance..(instance + 1));
         }
     }
 
@@ -1,8 +1,13 @@
 
+const MAX_SHADED_BIOMES = 64u;
+
 struct Uniforms {
     trans: mat4x4<f32>,
     bounds_min: vec4<f32>,
     bounds_max: vec4<f32>,
+    // x: 0 textured, 1 biome colors, 2 weight of biome y
+    shading: vec4<u32>,
+    biome_colors: array<vec4<f32>, MAX_SHADED_BIOMES>
 };
 
 @group(0) @binding(0) 
@@ -14,34 +19,75 @@ var atlas: texture_2d<f32>;
 @group(1) @binding(1)
 var atlas_sampler: sampler;
 
-struct VertexInput {
-    @location(0) pos: vec3<f32>,
-    @location(1) norm: vec3<f32>,
-    @location(2) mat1: u32,
-    @location(3) mat2: u32,
-    @location(4) mat_weight: f32
+// The chunk's mesh, read directly so every vertex can pass on the materials of its whole triangle.
+// Vertices are TerrainVertex as 32 bit words.
+@group(2) @binding(0)
+var<storage, read> vertices: array<u32>;
+
+@group(2) @binding(1)
+var<storage, read> indices: array<u32>;
+
+const VERTEX_WORDS = 22u;
+
+fn vertex_vec3(vert: u32, offset: u32) -> vec3<f32> {
+    let base = vert * VERTEX_WORDS + offset;
+    return bitcast<vec3<f32>>(vec3(vertices[base], vertices[base + 1u], vertices[base + 2u]));
+}
+
+fn vertex_uvec4(vert: u32, offset: u32) -> vec4<u32> {
+    let base = vert * VERTEX_WORDS + offset;
+    return vec4(vertices[base], vertices[base + 1u], vertices[base + 2u], vertices[base + 3u]);
+}
+
+struct ChunkInput {
+    @location(6) center: vec3<f32>,
+    // octants covered by finer chunks, bits are x + 2y + 4z of the octant
+    @location(7) hidden_octants: u32
 };
 
+// The textures, or the biomes when shading by biome, of the triangle's three corners are flat and blended
+// per fragment by the barycentric coordinates, since the corners don't have to put the same ids in the same slots
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) norm: vec3<f32>,
     @location(1) pos: vec3<f32>,
-    @location(2) @interpolate(flat) mat1: u32,
-    @location(3) @interpolate(flat) mat2: u32,
-    @location(4) mat_weight: f32
+    @location(2) bary: vec3<f32>,
+    @location(3) @interpolate(flat) ids0: vec4<u32>,
+    @location(4) @interpolate(flat) ids1: vec4<u32>,
+    @location(5) @interpolate(flat) ids2: vec4<u32>,
+    @location(6) @interpolate(flat) weights0: vec4<f32>,
+    @location(7) @interpolate(flat) weights1: vec4<f32>,
+    @location(8) @interpolate(flat) weights2: vec4<f32>,
+    @location(9) @interpolate(flat) chunk_center: vec3<f32>,
+    @location(10) @interpolate(flat) hidden_octants: u32
 };
 
 @vertex
 fn vs_main(
-    model: VertexInput,
+    @builtin(vertex_index) vertex_index: u32,
+    chunk: ChunkInput,
 ) -> VertexOutput {
+    let tri = vertex_index / 3u;
+    let corner = vertex_index % 3u;
+    let vert = indices[vertex_index];
+    let corners = vec3(indices[3u * tri], indices[3u * tri + 1u], indices[3u * tri + 2u]);
+    // mats and mat_weights, or biomes and biome_weights
+    let ids = select(6u, 14u, uniforms.shading.x != 0u);
+    let weights = ids + 4u;
+
     var out: VertexOutput;
-    out.norm = model.norm;
-    out.clip_position = uniforms.trans * vec4<f32>(model.pos, 1.0);
-    out.pos = model.pos;
-    out.mat1 = model.mat1;
-    out.mat2 = model.mat2;
-    out.mat_weight = model.mat_weight;
+    out.norm = vertex_vec3(vert, 3u);
+    out.pos = vertex_vec3(vert, 0u);
+    out.clip_position = uniforms.trans * vec4<f32>(out.pos, 1.0);
+    out.bary = vec3(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    out.ids0 = vertex_uvec4(corners.x, ids);
+    out.ids1 = vertex_uvec4(corners.y, ids);
+    out.ids2 = vertex_uvec4(corners.z, ids);
+    out.weights0 = bitcast<vec4<f32>>(vertex_uvec4(corners.x, weights));
+    out.weights1 = bitcast<vec4<f32>>(vertex_uvec4(corners.y, weights));
+    out.weights2 = bitcast<vec4<f32>>(vertex_uvec4(corners.z, weights));
+    out.chunk_center = chunk.center;
+    out.hidden_octants = chunk.hidden_octants;
     return out;
 }
 
@@ -49,38 +95,107 @@ const atlas_size: f32 = 8192.0;
 const mat_size: f32 = 512.0;
 const atlas_x_cells = u32(atlas_size / mat_size);
 
-fn sample_atlas_cell(cell_x: f32, cell_y: f32, uv: vec2<f32>) -> vec4<f32> {
+// Explicit gradients, since how many textures are sampled differs between fragments
+fn sample_atlas_cell(cell_x: f32, cell_y: f32, uv: vec2<f32>, uv_dx: vec2<f32>, uv_dy: vec2<f32>) -> vec4<f32> {
     let cell_uv = vec2(cell_x, cell_y) + fract(uv) * (mat_size - 4.0) / mat_size + 2.0 / mat_size; 
     let tex_uv = cell_uv / (atlas_size / mat_size);
-    return textureSample(atlas, atlas_sampler, tex_uv);
+    let grad_scale = (mat_size - 4.0) / atlas_size;
+    return textureSampleGrad(atlas, atlas_sampler, tex_uv, uv_dx * grad_scale, uv_dy * grad_scale);
+}
+
+fn sample_albedo(id: u32, uv: vec2<f32>, uv_dx: vec2<f32>, uv_dy: vec2<f32>) -> vec4<f32> {
+    return sample_atlas_cell(f32(id % atlas_x_cells), f32(id / atlas_x_cells), uv * 0.05, uv_dx * 0.05, uv_dy * 0.05);
 }
 
-fn sample_albedo(id: u32, uv: vec2<f32>) -> vec4<f32> {
-    return sample_atlas_cell(f32(id % atlas_x_cells), f32(id / atlas_x_cells), uv * 0.05);
+fn biome_color(id: u32) -> vec3<f32> {
+    if id >= MAX_SHADED_BIOMES {
+        return vec3(0.5);
+    }
+    return uniforms.biome_colors[id].rgb;
+}
+
+fn heatmap(w: f32) -> vec3<f32> {
+    let t = clamp(w, 0.0, 1.0);
+    if t < 0.5 {
+        return mix(vec3(0.05, 0.05, 0.35), vec3(0.1, 0.8, 0.2), t * 2.0);
+    }
+    return mix(vec3(0.1, 0.8, 0.2), vec3(1.0, 0.15, 0.05), t * 2.0 - 1.0);
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pos_dx = dpdx(in.pos);
+    let pos_dy = dpdy(in.pos);
+
     if  in.pos.x < uniforms.bounds_min.x || in.pos.y < uniforms.bounds_min.y || in.pos.z < uniforms.bounds_min.z ||
         in.pos.x > uniforms.bounds_max.x || in.pos.y > uniforms.bounds_max.y || in.pos.z > uniforms.bounds_max.z {
             discard;
     } 
 
+    let octant = select(vec3(0u), vec3(1u), in.pos >= in.chunk_center);
+    if (in.hidden_octants & (1u << (octant.x + 2u * octant.y + 4u * octant.z))) != 0u {
+        discard;
+    }
+
     let norm = normalize(in.norm);
+    let light = dot(norm, normalize(vec3(1.0, 1.0, 1.0))) * 0.25 + 0.75;
+
+    var corner_ids = array(in.ids0, in.ids1, in.ids2);
+    var corner_weights = array(in.weights0, in.weights1, in.weights2);
+
+    if uniforms.shading.x == 1u {
+        var color = vec3(0.0);
+        for(var corner = 0u; corner < 3u; corner++) {
+            for(var i = 0u; i < 4u; i++) {
+                color += in.bary[corner] * corner_weights[corner][i] * biome_color(corner_ids[corner][i]);
+            }
+        }
+        return vec4(color * light, 1.0);
+    }
+    if uniforms.shading.x == 2u {
+        var w = 0.0;
+        for(var corner = 0u; corner < 3u; corner++) {
+            for(var i = 0u; i < 4u; i++) {
+                if corner_ids[corner][i] == uniforms.shading.y {
+                    w += in.bary[corner] * corner_weights[corner][i];
+                }
+            }
+        }
+        return vec4(heatmap(w) * light, 1.0);
+    }
+
+    // the weights of each texture over the corners, so textures the corners share are only sampled once
+    var mats: array<u32, 12>;
+    var mat_weights: array<f32, 12>;
+    var n_mats = 0u;
+    for(var corner = 0u; corner < 3u; corner++) {
+        for(var i = 0u; i < 4u; i++) {
+            let w = in.bary[corner] * corner_weights[corner][i];
+            if w <= 0.0 {
+                continue;
+            }
+            var j = 0u;
+            while j < n_mats && mats[j] != corner_ids[corner][i] {
+                j++;
+            }
+            if j == n_mats {
+                mats[j] = corner_ids[corner][i];
+                n_mats++;
+            }
+            mat_weights[j] += w;
+        }
+    }
+
     let norm_abs = abs(norm);
     let triplanar_weights = norm_abs / (norm_abs.x + norm_abs.y + norm_abs.z);
-    let albedo1 =
-        sample_albedo(u32(in.mat1), in.pos.yz) * triplanar_weights.x +
-        sample_albedo(u32(in.mat1), in.pos.xz) * triplanar_weights.y +
-        sample_albedo(u32(in.mat1), in.pos.xy) * triplanar_weights.z;
-    let albedo2 =
-        sample_albedo(u32(in.mat2), in.pos.yz) * triplanar_weights.x +
-        sample_albedo(u32(in.mat2), in.pos.xz) * triplanar_weights.y +
-        sample_albedo(u32(in.mat2), in.pos.xy) * triplanar_weights.z;
-    let albedo = in.mat_weight * albedo2 + (1.0 - in.mat_weight) * albedo1;
+    var albedo = vec4(0.0);
+    for(var i = 0u; i < n_mats; i++) {
+        albedo += mat_weights[i] * (
+            sample_albedo(mats[i], in.pos.yz, pos_dx.yz, pos_dy.yz) * triplanar_weights.x +
+            sample_albedo(mats[i], in.pos.xz, pos_dx.xz, pos_dy.xz) * triplanar_weights.y +
+            sample_albedo(mats[i], in.pos.xy, pos_dx.xy, pos_dy.xy) * triplanar_weights.z);
+    }
 
-    let light = dot(norm, normalize(vec3(1.0, 1.0, 1.0))) * 0.25 + 0.75;
-    
     return albedo * light; 
     // return vec4(f32(in.mat1) / 2.0, f32(in.mat2) / 2.0, 0.0, 1.0);
     // return vec4(light, light, light, 1.0);